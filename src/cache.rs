@@ -2,19 +2,25 @@
 ///
 /// TODO:
 /// * We want to try upgrading derived caches without pruning them. This will likely require the concept of a content checksum (which would just be the cache key of the object file that would be used to create the derived cache.
-use std::fs::{read_dir, remove_file, File, OpenOptions};
+use std::fs::{create_dir_all, read_dir, remove_file, File, Metadata, OpenOptions};
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use failure::Fail;
+use parking_lot::RwLock;
 use sentry::integrations::failure::capture_fail;
 use symbolic::common::ByteView;
 use tempfile::NamedTempFile;
+use tokio_retry::strategy::jitter;
 
 use crate::config::{CacheConfig, Config};
 use crate::logging::LogError;
 use crate::types::Scope;
+use crate::utils::futures::CallOnDrop;
 
 /// Content of cache items whose writing failed.
 ///
@@ -26,6 +32,19 @@ use crate::types::Scope;
 /// yet.
 const MALFORMED_MARKER: &[u8] = b"malformed";
 
+/// Content of cache items for downloads that failed due to a transient error.
+///
+/// Items with this value expire after `retry_download_errors_after`, which is typically much
+/// shorter than the expiry of negative cache items. Timeouts, connection errors or server errors
+/// of a source are likely to resolve soon and must not be cached as if the file was missing.
+const DOWNLOAD_ERROR_MARKER: &[u8] = b"downloaderror";
+
+/// Minimum time since the last write before a temporary file is considered abandoned.
+///
+/// Multiple instances may share a cache directory. Temporary files that have been written to
+/// recently may belong to a running computation of another instance and are not removed.
+pub const TEMP_FILE_GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum CacheStatus {
     /// A cache item that represents the presence of something. E.g. we succeeded in downloading an
@@ -37,6 +56,9 @@ pub enum CacheStatus {
     /// We are unable to create or use the cache item. E.g. we failed to create a symcache. See
     /// docs for `MALFORMED_MARKER`.
     Malformed,
+    /// We failed to download the item due to a transient error, e.g. a timeout or a server error.
+    /// See docs for `DOWNLOAD_ERROR_MARKER`.
+    DownloadError,
 }
 
 impl AsRef<str> for CacheStatus {
@@ -45,6 +67,7 @@ impl AsRef<str> for CacheStatus {
             CacheStatus::Positive => "positive",
             CacheStatus::Negative => "negative",
             CacheStatus::Malformed => "malformed",
+            CacheStatus::DownloadError => "download_error",
         }
     }
 }
@@ -53,6 +76,8 @@ impl CacheStatus {
     pub fn from_content(s: &[u8]) -> CacheStatus {
         if s == MALFORMED_MARKER {
             CacheStatus::Malformed
+        } else if s == DOWNLOAD_ERROR_MARKER {
+            CacheStatus::DownloadError
         } else if s.is_empty() {
             CacheStatus::Negative
         } else {
@@ -72,6 +97,10 @@ impl CacheStatus {
                 let mut f = File::create(path)?;
                 f.write_all(MALFORMED_MARKER)?;
             }
+            CacheStatus::DownloadError => {
+                let mut f = File::create(path)?;
+                f.write_all(DOWNLOAD_ERROR_MARKER)?;
+            }
         }
 
         Ok(())
@@ -102,8 +131,11 @@ pub struct Cache {
     /// Time when this process started.
     start_time: SystemTime,
 
-    /// Options intended to be user-configurable.
-    cache_config: CacheConfig,
+    /// Options intended to be user-configurable. Shared between clones and replaced on reload.
+    cache_config: Arc<RwLock<CacheConfig>>,
+
+    /// Number of computations writing temporary files into this cache. Shared between clones.
+    active_writes: Arc<AtomicUsize>,
 }
 
 impl Cache {
@@ -116,10 +148,24 @@ impl Cache {
             name,
             cache_dir: cache_dir.map(|x| x.as_ref().to_owned()),
             start_time: SystemTime::now(),
-            cache_config,
+            cache_config: Arc::new(RwLock::new(cache_config)),
+            active_writes: Arc::new(AtomicUsize::new(0)),
         }
     }
 
+    /// Returns the currently active cache options.
+    pub fn config(&self) -> CacheConfig {
+        *self.cache_config.read()
+    }
+
+    /// Replaces the cache options of this cache and all of its clones.
+    ///
+    /// Items already opened are not affected, but subsequent lookups and cleanups use the new
+    /// expiry settings.
+    pub fn set_config(&self, cache_config: CacheConfig) {
+        *self.cache_config.write() = cache_config;
+    }
+
     pub fn name(&self) -> &'static str {
         self.name
     }
@@ -128,13 +174,113 @@ impl Cache {
         self.cache_dir.as_ref().map(|x| &**x)
     }
 
-    pub fn cleanup(&self) -> Result<(), CleanupError> {
+    /// Marks a computation that writes temporary files into this cache.
+    ///
+    /// The computation counts as active until the returned guard is dropped.
+    pub fn begin_write(&self) -> CallOnDrop {
+        self.active_writes.fetch_add(1, Ordering::SeqCst);
+        let active_writes = self.active_writes.clone();
+        CallOnDrop::new(move || {
+            active_writes.fetch_sub(1, Ordering::SeqCst);
+        })
+    }
+
+    /// Returns the number of computations writing temporary files into this cache.
+    pub fn active_writes(&self) -> usize {
+        self.active_writes.load(Ordering::SeqCst)
+    }
+
+    /// Verifies that items can be written to this cache.
+    ///
+    /// The cache directory is created if necessary, but the configured base directory must exist.
+    /// Otherwise, a volume holding the caches may have failed to mount. Succeeds if caching is
+    /// disabled.
+    pub fn check_writable(&self) -> io::Result<()> {
+        let cache_dir = match self.cache_dir {
+            Some(ref x) => x,
+            None => return Ok(()),
+        };
+
+        if let Some(base_dir) = cache_dir.parent() {
+            if !base_dir.is_dir() {
+                let message = format!("{} is not a directory", base_dir.display());
+                return Err(io::Error::new(io::ErrorKind::NotFound, message));
+            }
+        }
+
+        create_dir_all(cache_dir)?;
+        let mut file = NamedTempFile::new_in(cache_dir)?;
+        file.write_all(b"ready")?;
+        Ok(())
+    }
+
+    /// Removes temporary files of downloads and computations that did not complete.
+    ///
+    /// Cache items are written to temporary files next to their final location and only moved into
+    /// place once complete, so this never removes cache items. Only files that have not been
+    /// written to for `min_age` are removed, since other instances may share the cache directory.
+    /// This must only be called when no computations of this process are running anymore, see
+    /// `active_writes`. Returns the number of removed files.
+    pub fn remove_temp_files(&self, min_age: Duration) -> io::Result<usize> {
+        let cache_dir = match self.cache_dir {
+            Some(ref x) => x.clone(),
+            None => return Ok(0),
+        };
+
+        let mut removed = 0;
+        let mut directories = vec![cache_dir];
+        while let Some(directory) = directories.pop() {
+            let entries = match catch_not_found(|| read_dir(directory))? {
+                Some(x) => x,
+                None => continue,
+            };
+
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    directories.push(path);
+                    continue;
+                } else if !is_temp_file(&path) {
+                    continue;
+                }
+
+                let modified = match catch_not_found(|| path.metadata()?.modified())? {
+                    Some(x) => x,
+                    None => continue,
+                };
+
+                // Files with an mtime in the future are treated as recently written.
+                let abandoned = modified.elapsed().map_or(false, |age| age >= min_age);
+                if abandoned && catch_not_found(|| remove_file(&path))?.is_some() {
+                    log::debug!("Removed temporary file {}", path.display());
+                    removed += 1;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    pub fn cleanup(&self) -> Result<CleanupStats, CleanupError> {
+        let (stats, _) = self.cleanup_entries()?;
+        stats.report(self.name);
+        Ok(stats)
+    }
+
+    /// Removes expired items and evicts items exceeding the size budget of this cache.
+    ///
+    /// Returns statistics on the removed items, as well as all positive items that have been
+    /// retained. These can be used to enforce a budget across multiple caches.
+    fn cleanup_entries(&self) -> Result<(CleanupStats, Vec<CacheEntry>), CleanupError> {
         log::info!("Cleaning up cache: {}", self.name);
         let cache_dir = match self.cache_dir {
             Some(ref x) => x.clone(),
             None => return Err(CleanupError::NoCachingConfigured),
         };
 
+        let mut stats = CleanupStats::default();
+        let mut retained = Vec::new();
+
         let mut directories = vec![cache_dir];
         while !directories.is_empty() {
             let directory = directories.pop().unwrap();
@@ -143,7 +289,7 @@ impl Cache {
                 Some(x) => x,
                 None => {
                     log::warn!("Directory not found");
-                    return Ok(());
+                    continue;
                 }
             };
 
@@ -152,28 +298,76 @@ impl Cache {
                 let path = entry.path();
                 if path.is_dir() {
                     directories.push(path.to_owned());
-                } else if let Err(e) = self.try_cleanup_path(&path) {
-                    log::error!("Failed to clean up {}: {}", path.display(), LogError(&e));
-                    capture_fail(&e);
+                } else {
+                    match self.try_cleanup_path(&path, &mut stats) {
+                        Ok(Some(entry)) => retained.push(entry),
+                        Ok(None) => (),
+                        Err(e) => {
+                            log::error!("Failed to clean up {}: {}", path.display(), LogError(&e));
+                            capture_fail(&e);
+                        }
+                    }
                 }
             }
         }
 
-        Ok(())
+        if let Some(max_size) = self.config().max_size {
+            evict_to_size(retained.iter_mut().collect(), max_size);
+            drain_evicted(&mut retained, &mut stats);
+        }
+
+        Ok((stats, retained))
     }
 
-    fn try_cleanup_path(&self, path: &Path) -> Result<(), CleanupError> {
+    /// Removes the file at `path` if it has expired.
+    ///
+    /// Returns the cache entry if the file is retained and holds a positive cache item.
+    fn try_cleanup_path(
+        &self,
+        path: &Path,
+        stats: &mut CleanupStats,
+    ) -> Result<Option<CacheEntry>, CleanupError> {
         log::trace!("Checking {}", path.display());
-        if path.is_file() {
-            if catch_not_found(|| self.check_expiry(path))?.is_none() {
-                log::info!("Removing {}", path.display());
-                catch_not_found(|| remove_file(path))?;
+        if !path.is_file() {
+            return Err(CleanupError::NotAFile);
+        }
+
+        let metadata = match catch_not_found(|| path.metadata())? {
+            Some(metadata) => metadata,
+            None => return Ok(None),
+        };
+
+        if catch_not_found(|| self.check_expiry(path))?.is_none() {
+            log::info!("Removing {}", path.display());
+            if catch_not_found(|| remove_file(path))?.is_some() {
+                stats.removed_files += 1;
+                stats.removed_bytes += metadata.len();
             }
 
-            Ok(())
-        } else {
-            Err(CleanupError::NotAFile)
+            return Ok(None);
+        }
+
+        // Temporary files of running computations are not cache items yet. They must not cause
+        // the eviction of finished items.
+        if is_temp_file(path) {
+            return Ok(None);
+        }
+
+        // Only positive items count towards the size budget.
+        let is_positive = metadata.len() > 0
+            && catch_not_found(|| is_marker(path, &metadata, MALFORMED_MARKER))? == Some(false)
+            && catch_not_found(|| is_marker(path, &metadata, DOWNLOAD_ERROR_MARKER))?
+                == Some(false);
+        if !is_positive {
+            return Ok(None);
         }
+
+        Ok(Some(CacheEntry {
+            path: path.to_owned(),
+            size: metadata.len(),
+            last_used: metadata.modified()?,
+            evicted: false,
+        }))
     }
 
     /// Validate cache expiration of path. If cache should not be used,
@@ -192,22 +386,17 @@ impl Cache {
         //   when the failed download happened (when the file was created)
         // * malformed: A file with the content `b"malformed"`. Represents a failed symcache
         //   conversion. mtime indicates when we attempted to convert.
+        // * download error: A file with the content `b"downloaderror"`. Represents a download
+        //   that failed due to a transient error. mtime indicates when the download failed.
         // * ok (don't really have a name): File has any other content, mtime is used to keep track
         //   of last use.
         let metadata = path.metadata()?;
 
         log::trace!("File length: {}", metadata.len());
 
-        let is_malformed = if MALFORMED_MARKER.len() as u64 == metadata.len() {
-            let mut file = File::open(path)?;
-            let mut buf = vec![0; MALFORMED_MARKER.len()];
-            file.read_exact(&mut buf)?;
-
-            log::trace!("First {} bytes: {:?}", buf.len(), buf);
-            buf == MALFORMED_MARKER
-        } else {
-            false
-        };
+        let cache_config = self.config();
+        let is_malformed = is_marker(path, &metadata, MALFORMED_MARKER)?;
+        let is_download_error = is_marker(path, &metadata, DOWNLOAD_ERROR_MARKER)?;
 
         let is_negative = metadata.len() == 0;
 
@@ -217,10 +406,9 @@ impl Cache {
 
             let created_at = metadata.modified()?;
 
-            let retry_malformed = if let (Ok(elapsed), Some(retry_malformed_after)) = (
-                created_at.elapsed(),
-                self.cache_config.retry_malformed_after,
-            ) {
+            let retry_malformed = if let (Ok(elapsed), Some(retry_malformed_after)) =
+                (created_at.elapsed(), cache_config.retry_malformed_after)
+            {
                 elapsed > retry_malformed_after
             } else {
                 false
@@ -233,9 +421,11 @@ impl Cache {
         }
 
         let max_mtime = if is_negative {
-            self.cache_config.retry_misses_after
+            cache_config.retry_misses_after
+        } else if is_download_error {
+            cache_config.retry_download_errors_after
         } else {
-            self.cache_config.max_unused_for
+            cache_config.max_unused_for
         };
 
         let mtime = if let Some(max_mtime) = max_mtime {
@@ -252,6 +442,7 @@ impl Cache {
 
         Ok(!is_negative
             && !is_malformed
+            && !is_download_error
             && mtime.map(|x| x > Duration::from_secs(3600)).unwrap_or(true))
     }
 
@@ -276,6 +467,107 @@ impl Cache {
     }
 }
 
+/// Checks whether `path` is a temporary file created by `NamedTempFile`.
+fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| name.starts_with(".tmp"))
+}
+
+/// Checks whether the cache item at `path` consists of the given marker, such as the
+/// `MALFORMED_MARKER`.
+fn is_marker(path: &Path, metadata: &Metadata, marker: &[u8]) -> io::Result<bool> {
+    if marker.len() as u64 != metadata.len() {
+        return Ok(false);
+    }
+
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; marker.len()];
+    file.read_exact(&mut buf)?;
+
+    log::trace!("First {} bytes: {:?}", buf.len(), buf);
+    Ok(buf == marker)
+}
+
+/// Statistics on cache items removed during cleanup.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CleanupStats {
+    /// Number of files removed from the cache.
+    pub removed_files: u64,
+    /// Total size of all removed files in bytes.
+    pub removed_bytes: u64,
+}
+
+impl CleanupStats {
+    /// Logs the statistics and emits metrics for the cache with the given name.
+    fn report(&self, name: &str) {
+        log::info!(
+            "Cleaned up cache {}: removed {} files ({} bytes)",
+            name,
+            self.removed_files,
+            self.removed_bytes
+        );
+
+        metric!(
+            counter(&format!("caches.{}.cleanup.files_removed", name)) += self.removed_files as i64
+        );
+        metric!(
+            counter(&format!("caches.{}.cleanup.bytes_removed", name)) += self.removed_bytes as i64
+        );
+    }
+}
+
+/// A positive cache item that has been retained by cleanup.
+#[derive(Debug)]
+struct CacheEntry {
+    /// Path to the cache file.
+    path: PathBuf,
+    /// Size of the cache file in bytes.
+    size: u64,
+    /// Time of last use, which is the mtime for positive cache items.
+    last_used: SystemTime,
+    /// Whether the entry has been evicted to stay within a size budget.
+    evicted: bool,
+}
+
+/// Evicts least recently used entries until their total size is within `max_size`.
+///
+/// Evicted entries are marked, but not removed from their containers. Use `drain_evicted` to
+/// remove them and account for them in cleanup statistics.
+fn evict_to_size(mut entries: Vec<&mut CacheEntry>, max_size: u64) {
+    let mut total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+    if total_size <= max_size {
+        return;
+    }
+
+    entries.sort_by_key(|entry| entry.last_used);
+
+    for entry in entries {
+        if total_size <= max_size {
+            break;
+        }
+
+        log::info!("Evicting {}", entry.path.display());
+        if let Err(e) = catch_not_found(|| remove_file(&entry.path)) {
+            log::error!("Failed to evict {}: {}", entry.path.display(), LogError(&e));
+            continue;
+        }
+
+        entry.evicted = true;
+        total_size -= entry.size;
+    }
+}
+
+/// Removes evicted entries and adds them to the cleanup statistics.
+fn drain_evicted(entries: &mut Vec<CacheEntry>, stats: &mut CleanupStats) {
+    for entry in entries.iter().filter(|entry| entry.evicted) {
+        stats.removed_files += 1;
+        stats.removed_bytes += entry.size;
+    }
+
+    entries.retain(|entry| !entry.evicted);
+}
+
 #[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub struct CacheKey {
     pub cache_key: String,
@@ -309,11 +601,14 @@ where
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct Caches {
     pub objects: Cache,
     pub object_meta: Cache,
     pub symcaches: Cache,
     pub cficaches: Cache,
+    /// Maximum total size of all caches in bytes.
+    pub max_size: Arc<RwLock<Option<u64>>>,
 }
 
 impl Caches {
@@ -335,14 +630,86 @@ impl Caches {
                 let path = config.cache_dir("cficaches");
                 Cache::new("cficaches", path, config.caches.derived)
             },
+            max_size: Arc::new(RwLock::new(config.caches.max_size)),
+        }
+    }
+
+    /// Applies the cache options of a reloaded configuration.
+    ///
+    /// The cache directory cannot change at runtime, so only expiry and size limits are updated.
+    pub fn reload(&self, config: &Config) {
+        self.objects.set_config(config.caches.downloaded);
+        self.object_meta.set_config(config.caches.derived);
+        self.symcaches.set_config(config.caches.derived);
+        self.cficaches.set_config(config.caches.derived);
+        *self.max_size.write() = config.caches.max_size;
+    }
+
+    /// Returns all caches managed by this instance.
+    pub fn all(&self) -> [&Cache; 4] {
+        [
+            &self.objects,
+            &self.object_meta,
+            &self.symcaches,
+            &self.cficaches,
+        ]
+    }
+
+    /// Removes temporary files from all caches. See `Cache::remove_temp_files`.
+    ///
+    /// Caches with computations that are still writing, for instance because they outlived the
+    /// shutdown timeout, are skipped.
+    pub fn remove_temp_files(&self, min_age: Duration) {
+        for cache in self.all().iter() {
+            let active_writes = cache.active_writes();
+            if active_writes > 0 {
+                log::warn!(
+                    "Not removing temporary files from {}, {} computations are still running",
+                    cache.name,
+                    active_writes
+                );
+                continue;
+            }
+
+            match cache.remove_temp_files(min_age) {
+                Ok(0) => (),
+                Ok(removed) => {
+                    log::info!("Removed {} temporary files from {}", removed, cache.name)
+                }
+                Err(e) => log::error!(
+                    "Failed to remove temporary files from {}: {}",
+                    cache.name,
+                    LogError(&e)
+                ),
+            }
         }
     }
 
     pub fn cleanup(&self) -> Result<(), CleanupError> {
-        self.objects.cleanup()?;
-        self.object_meta.cleanup()?;
-        self.symcaches.cleanup()?;
-        self.cficaches.cleanup()?;
+        let start_time = Instant::now();
+        let caches = self.all();
+
+        let mut stats = Vec::with_capacity(caches.len());
+        let mut retained = Vec::with_capacity(caches.len());
+        for cache in &caches {
+            let (cache_stats, cache_retained) = cache.cleanup_entries()?;
+            stats.push(cache_stats);
+            retained.push(cache_retained);
+        }
+
+        let max_size = *self.max_size.read();
+        if let Some(max_size) = max_size {
+            evict_to_size(retained.iter_mut().flatten().collect(), max_size);
+            for (cache_stats, cache_retained) in stats.iter_mut().zip(&mut retained) {
+                drain_evicted(cache_retained, cache_stats);
+            }
+        }
+
+        for (cache, cache_stats) in caches.iter().zip(&stats) {
+            cache_stats.report(cache.name);
+        }
+
+        metric!(timer("caches.cleanup.duration") = start_time.elapsed());
         Ok(())
     }
 }
@@ -351,6 +718,42 @@ pub fn cleanup(config: Config) -> Result<(), CleanupError> {
     Caches::new(&config).cleanup()
 }
 
+/// Spawns a background thread that periodically cleans up all caches.
+///
+/// Runs never overlap, since the next run is only scheduled after the previous one has completed.
+/// Each interval is extended by up to 10% random jitter, so that multiple instances sharing a
+/// cache directory do not clean up in lockstep.
+///
+/// The interval is not reloaded with the configuration, but each run uses the reloaded expiry and
+/// size limits of the caches.
+pub fn spawn_periodic_cleanup(config: &Config, caches: Caches) {
+    let interval = match config.caches.cleanup_interval {
+        Some(interval) => interval,
+        None => return,
+    };
+
+    if config.cache_dir.is_none() {
+        log::warn!("Periodic cache cleanup is configured, but caching is disabled");
+        return;
+    }
+
+    let result = thread::Builder::new()
+        .name("symbolicator-cleanup".into())
+        .spawn(move || loop {
+            thread::sleep(interval + jitter(interval / 10));
+
+            if let Err(e) = caches.cleanup() {
+                log::error!("Failed to clean up caches: {}", LogError(&e));
+                capture_fail(&e);
+            }
+        });
+
+    match result {
+        Ok(_) => log::info!("Scheduled cache cleanup every {:?}", interval),
+        Err(e) => log::error!("Failed to spawn cache cleanup thread: {}", LogError(&e)),
+    }
+}
+
 #[cfg(test)]
 fn tempdir() -> io::Result<tempfile::TempDir> {
     tempfile::tempdir_in(".")
@@ -392,6 +795,39 @@ fn test_max_unused_for() -> Result<(), CleanupError> {
     Ok(())
 }
 
+#[test]
+fn test_set_config() -> Result<(), CleanupError> {
+    use std::fs::create_dir_all;
+    use std::io::Write;
+    use std::thread::sleep;
+
+    let tempdir = tempdir()?;
+    create_dir_all(tempdir.path().join("foo"))?;
+
+    let cache = Cache::new(
+        "test",
+        Some(tempdir.path()),
+        CacheConfig {
+            max_unused_for: None,
+            ..CacheConfig::default_derived()
+        },
+    );
+
+    File::create(tempdir.path().join("foo/killthis"))?.write_all(b"hi")?;
+    sleep(Duration::from_millis(11));
+
+    // Clones share the configuration, so the update is visible to the original cache.
+    cache.clone().set_config(CacheConfig {
+        max_unused_for: Some(Duration::from_millis(10)),
+        ..CacheConfig::default_derived()
+    });
+    cache.cleanup()?;
+
+    assert!(read_dir(tempdir.path().join("foo"))?.next().is_none());
+
+    Ok(())
+}
+
 #[test]
 fn test_retry_misses_after() -> Result<(), CleanupError> {
     use std::fs::create_dir_all;
@@ -428,6 +864,42 @@ fn test_retry_misses_after() -> Result<(), CleanupError> {
     Ok(())
 }
 
+#[test]
+fn test_retry_download_errors_after() -> Result<(), CleanupError> {
+    use std::fs::create_dir_all;
+    use std::io::Write;
+    use std::thread::sleep;
+
+    let tempdir = tempdir()?;
+    create_dir_all(tempdir.path().join("foo"))?;
+
+    let cache = Cache::new(
+        "test",
+        Some(tempdir.path()),
+        CacheConfig {
+            retry_download_errors_after: Some(Duration::from_millis(20)),
+            ..CacheConfig::default_derived()
+        },
+    );
+
+    File::create(tempdir.path().join("foo/keepthis"))?.write_all(b"")?;
+    File::create(tempdir.path().join("foo/killthis"))?.write_all(b"downloaderror")?;
+    sleep(Duration::from_millis(25));
+
+    File::create(tempdir.path().join("foo/keepthis2"))?.write_all(b"downloaderror")?;
+    cache.cleanup()?;
+
+    let mut basenames: Vec<_> = read_dir(tempdir.path().join("foo"))?
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect();
+
+    basenames.sort();
+
+    assert_eq!(basenames, vec!["keepthis", "keepthis2"]);
+
+    Ok(())
+}
+
 #[test]
 fn test_cleanup_malformed() -> Result<(), CleanupError> {
     use std::fs::create_dir_all;
@@ -460,3 +932,237 @@ fn test_cleanup_malformed() -> Result<(), CleanupError> {
 
     Ok(())
 }
+
+#[test]
+fn test_check_writable() -> Result<(), CleanupError> {
+    let tempdir = tempdir()?;
+
+    let cache = Cache::new(
+        "test",
+        Some(tempdir.path().join("test")),
+        CacheConfig::default_derived(),
+    );
+    cache.check_writable()?;
+    assert!(tempdir.path().join("test").is_dir());
+
+    let cache = Cache::new(
+        "test",
+        Some(tempdir.path().join("missing/test")),
+        CacheConfig::default_derived(),
+    );
+    assert!(cache.check_writable().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_remove_temp_files() -> Result<(), CleanupError> {
+    use std::fs::create_dir_all;
+    use std::io::Write;
+    use std::thread::sleep;
+
+    let tempdir = tempdir()?;
+    create_dir_all(tempdir.path().join("foo"))?;
+
+    let cache = Cache::new("test", Some(tempdir.path()), CacheConfig::default_derived());
+
+    File::create(tempdir.path().join("foo/keepthis"))?.write_all(b"hi")?;
+    File::create(tempdir.path().join("foo/.tmpkillthis"))?.write_all(b"hi")?;
+
+    // Recently written files may belong to another instance sharing the cache directory.
+    assert_eq!(cache.remove_temp_files(Duration::from_secs(3600))?, 0);
+
+    sleep(Duration::from_millis(11));
+    assert_eq!(cache.remove_temp_files(Duration::from_millis(10))?, 1);
+
+    let basenames: Vec<_> = read_dir(tempdir.path().join("foo"))?
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect();
+
+    assert_eq!(basenames, vec!["keepthis"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_remove_temp_files_while_writing() -> Result<(), CleanupError> {
+    use std::io::Write;
+
+    let tempdir = tempdir()?;
+    let caches = Caches::new(&Config {
+        cache_dir: Some(tempdir.path().to_owned()),
+        ..Config::default()
+    });
+
+    let objects_dir = caches.objects.cache_dir().unwrap().to_owned();
+    create_dir_all(&objects_dir)?;
+    let temp_file = objects_dir.join(".tmpwriting");
+    File::create(&temp_file)?.write_all(b"hi")?;
+
+    let guard = caches.objects.begin_write();
+    assert_eq!(caches.objects.active_writes(), 1);
+    caches.remove_temp_files(Duration::from_secs(0));
+    assert!(temp_file.exists());
+
+    drop(guard);
+    assert_eq!(caches.objects.active_writes(), 0);
+    caches.remove_temp_files(Duration::from_secs(0));
+    assert!(!temp_file.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_max_size() -> Result<(), CleanupError> {
+    use std::fs::create_dir_all;
+    use std::io::Write;
+    use std::thread::sleep;
+
+    let tempdir = tempdir()?;
+    create_dir_all(tempdir.path().join("foo"))?;
+
+    let cache = Cache::new(
+        "test",
+        Some(tempdir.path()),
+        CacheConfig {
+            max_size: Some(10),
+            ..CacheConfig::default_derived()
+        },
+    );
+
+    File::create(tempdir.path().join("foo/killthis"))?.write_all(b"hello")?;
+    sleep(Duration::from_millis(10));
+    File::create(tempdir.path().join("foo/keepthis"))?.write_all(b"hello")?;
+    File::create(tempdir.path().join("foo/keepthis2"))?.write_all(b"")?;
+    sleep(Duration::from_millis(10));
+    File::create(tempdir.path().join("foo/keepthis3"))?.write_all(b"hello")?;
+
+    let stats = cache.cleanup()?;
+    assert_eq!(
+        stats,
+        CleanupStats {
+            removed_files: 1,
+            removed_bytes: 5,
+        }
+    );
+
+    let mut basenames: Vec<_> = read_dir(tempdir.path().join("foo"))?
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect();
+
+    basenames.sort();
+
+    assert_eq!(basenames, vec!["keepthis", "keepthis2", "keepthis3"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_max_size_temp_files() -> Result<(), CleanupError> {
+    use std::fs::create_dir_all;
+    use std::io::Write;
+
+    let tempdir = tempdir()?;
+    create_dir_all(tempdir.path().join("foo"))?;
+
+    let cache = Cache::new(
+        "test",
+        Some(tempdir.path()),
+        CacheConfig {
+            max_size: Some(10),
+            ..CacheConfig::default_derived()
+        },
+    );
+
+    File::create(tempdir.path().join("foo/keepthis"))?.write_all(b"hello")?;
+    File::create(tempdir.path().join("foo/.tmpdownloading"))?.write_all(&[0; 100])?;
+
+    let stats = cache.cleanup()?;
+    assert_eq!(stats, CleanupStats::default());
+
+    let mut basenames: Vec<_> = read_dir(tempdir.path().join("foo"))?
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect();
+
+    basenames.sort();
+
+    assert_eq!(basenames, vec![".tmpdownloading", "keepthis"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_periodic_cleanup() -> Result<(), CleanupError> {
+    use std::fs::create_dir_all;
+    use std::thread::sleep;
+
+    let tempdir = tempdir()?;
+    let config = Config {
+        cache_dir: Some(tempdir.path().to_owned()),
+        caches: crate::config::CacheConfigs {
+            downloaded: CacheConfig {
+                retry_misses_after: Some(Duration::from_millis(1)),
+                ..CacheConfig::default_downloaded()
+            },
+            cleanup_interval: Some(Duration::from_millis(20)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    create_dir_all(tempdir.path().join("objects/foo"))?;
+    let path = tempdir.path().join("objects/foo/killthis");
+    File::create(&path)?;
+
+    spawn_periodic_cleanup(&config, Caches::new(&config));
+
+    // The first run is scheduled after the interval rather than immediately.
+    let start = Instant::now();
+    while path.exists() && start.elapsed() < Duration::from_secs(5) {
+        sleep(Duration::from_millis(10));
+    }
+
+    assert!(!path.exists());
+    assert!(start.elapsed() >= Duration::from_millis(10));
+
+    Ok(())
+}
+
+#[test]
+fn test_global_max_size() -> Result<(), CleanupError> {
+    use std::fs::create_dir_all;
+    use std::io::Write;
+    use std::thread::sleep;
+
+    let tempdir = tempdir()?;
+    let config = Config {
+        cache_dir: Some(tempdir.path().to_owned()),
+        caches: crate::config::CacheConfigs {
+            max_size: Some(10),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    create_dir_all(tempdir.path().join("objects/foo"))?;
+    create_dir_all(tempdir.path().join("symcaches/foo"))?;
+
+    File::create(tempdir.path().join("objects/foo/killthis"))?.write_all(b"hello")?;
+    sleep(Duration::from_millis(10));
+    File::create(tempdir.path().join("symcaches/foo/killthis"))?.write_all(b"hello")?;
+    sleep(Duration::from_millis(10));
+    File::create(tempdir.path().join("objects/foo/keepthis"))?.write_all(b"hello")?;
+    File::create(tempdir.path().join("symcaches/foo/keepthis"))?.write_all(b"hello")?;
+
+    Caches::new(&config).cleanup()?;
+
+    for cache in &["objects", "symcaches"] {
+        let basenames: Vec<_> = read_dir(tempdir.path().join(cache).join("foo"))?
+            .map(|x| x.unwrap().file_name().into_string().unwrap())
+            .collect();
+
+        assert_eq!(basenames, vec!["keepthis"]);
+    }
+
+    Ok(())
+}
@@ -7,6 +7,7 @@ use structopt::StructOpt;
 use crate::cache::{self, CleanupError};
 use crate::config::{Config, ConfigError};
 use crate::logging;
+use crate::offline::{self, SymbolicateError};
 use crate::server::{self, ServerError};
 
 /// An enum representing a CLI error.
@@ -23,6 +24,10 @@ pub enum CliError {
     /// Indicates an error while cleaning up caches.
     #[fail(display = "Failed to clean up caches")]
     Cleanup(#[fail(cause)] CleanupError),
+
+    /// Indicates an error while symbolicating a local file.
+    #[fail(display = "Failed to symbolicate")]
+    Symbolicate(#[fail(cause)] SymbolicateError),
 }
 
 fn get_crate_version() -> &'static str {
@@ -49,6 +54,14 @@ enum Command {
     /// Clean local caches.
     #[structopt(name = "cleanup")]
     Cleanup,
+
+    /// Symbolicate a crash report or JSON payload without starting the server.
+    #[structopt(name = "symbolicate")]
+    Symbolicate {
+        /// Path to the crash report or JSON symbolication request.
+        #[structopt(parse(from_os_str), value_name = "FILE")]
+        input: PathBuf,
+    },
 }
 
 /// Command line interface parser.
@@ -92,8 +105,9 @@ pub fn execute() -> Result<(), CliError> {
     sentry::integrations::panic::register_panic_handler();
 
     match cli.command {
-        Command::Run => server::run(config)?,
+        Command::Run => server::run(config, cli.config())?,
         Command::Cleanup => cache::cleanup(config)?,
+        Command::Symbolicate { ref input } => offline::symbolicate(config, input)?,
     }
 
     Ok(())
@@ -1,3 +1,4 @@
+use std::env;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
@@ -8,16 +9,59 @@ use failure::Fail;
 use log::LevelFilter;
 use sentry::internals::Dsn;
 use serde::Deserialize;
+use serde_yaml::Value;
 
-use crate::types::SourceConfig;
+use crate::types::{ProxyConfig, RequestSource, SourceConfig, SourceReferenceKind};
 
-#[derive(Debug, Fail, derive_more::From)]
+#[derive(Debug, Fail)]
 pub enum ConfigError {
     #[fail(display = "Failed to open file")]
     Io(#[fail(cause)] io::Error),
 
     #[fail(display = "Failed to parse YAML")]
     Parsing(#[fail(cause)] serde_yaml::Error),
+
+    #[fail(display = "Environment variable `{}` is not set", _0)]
+    MissingVariable(String),
+
+    #[fail(display = "Failed to read secret file `{}`", path)]
+    SecretFile {
+        path: String,
+        #[fail(cause)]
+        cause: io::Error,
+    },
+
+    #[fail(display = "Unterminated `${{` in config value `{}`", _0)]
+    UnterminatedReference(String),
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(error: io::Error) -> Self {
+        ConfigError::Io(error)
+    }
+}
+
+impl From<serde_yaml::Error> for ConfigError {
+    fn from(error: serde_yaml::Error) -> Self {
+        ConfigError::Parsing(error)
+    }
+}
+
+/// An error returned when resolving the sources of a symbolication request.
+#[derive(Debug, Fail)]
+pub enum SourceError {
+    /// The request references a source that is not configured.
+    #[fail(display = "Unknown source `{}`", _0)]
+    Unknown(String),
+
+    /// An inline source overrides the proxy, which is only permitted in the configuration.
+    #[fail(display = "Source `{}` may not override proxy settings", _0)]
+    Proxy(String),
+
+    /// An inline source uses credentials of the server, which is only permitted in the
+    /// configuration.
+    #[fail(display = "Source `{}` may not use server credentials", _0)]
+    Credentials(String),
 }
 
 /// Controls the log format
@@ -62,6 +106,8 @@ impl Default for Logging {
 pub struct Metrics {
     /// host/port of statsd instance
     pub statsd: Option<String>,
+    /// Aggregate metrics in process and expose them on `/metrics` instead of sending them to statsd.
+    pub prometheus: bool,
     /// The prefix that should be added to all metrics.
     pub prefix: String,
 }
@@ -70,6 +116,7 @@ impl Default for Metrics {
     fn default() -> Self {
         Metrics {
             statsd: None,
+            prometheus: false,
             prefix: "symbolicator".into(),
         }
     }
@@ -86,6 +133,20 @@ pub struct CacheConfig {
 
     /// Maximum duration since creation of malformed cache item (item age).
     pub retry_malformed_after: Option<Duration>,
+
+    /// Maximum duration since creation of a cache item for a failed download (item age).
+    ///
+    /// Defaults to 5 minutes if omitted, so that existing cache configurations do not keep failed
+    /// downloads forever.
+    #[serde(default = "default_retry_download_errors_after")]
+    pub retry_download_errors_after: Option<Duration>,
+
+    /// Maximum size of each cache in bytes. Least recently used items are evicted first.
+    pub max_size: Option<u64>,
+}
+
+fn default_retry_download_errors_after() -> Option<Duration> {
+    Some(Duration::from_secs(300))
 }
 
 impl CacheConfig {
@@ -94,6 +155,8 @@ impl CacheConfig {
             max_unused_for: Some(Duration::from_secs(3600 * 24 * 7)),
             retry_misses_after: Some(Duration::from_secs(3600)),
             retry_malformed_after: Some(Duration::from_secs(3600 * 24)),
+            retry_download_errors_after: default_retry_download_errors_after(),
+            max_size: None,
         }
     }
 
@@ -102,6 +165,8 @@ impl CacheConfig {
             max_unused_for: Some(Duration::from_secs(3600 * 24)),
             retry_misses_after: Some(Duration::from_secs(3600)),
             retry_malformed_after: Some(Duration::from_secs(3600 * 24)),
+            retry_download_errors_after: default_retry_download_errors_after(),
+            max_size: None,
         }
     }
 }
@@ -113,6 +178,10 @@ pub struct CacheConfigs {
     pub downloaded: CacheConfig,
     /// Configure how long caches derived from downloads are cached for.
     pub derived: CacheConfig,
+    /// Maximum total size of all caches in bytes. Least recently used items are evicted first.
+    pub max_size: Option<u64>,
+    /// Interval at which the server cleans up caches in the background. Disabled by default.
+    pub cleanup_interval: Option<Duration>,
 }
 
 impl Default for CacheConfigs {
@@ -120,6 +189,30 @@ impl Default for CacheConfigs {
         CacheConfigs {
             downloaded: CacheConfig::default_downloaded(),
             derived: CacheConfig::default_derived(),
+            max_size: None,
+            cleanup_interval: None,
+        }
+    }
+}
+
+/// Options for the readiness check at `/ready`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Readiness {
+    /// Probe every default source with a request for a file that does not exist.
+    pub probe_sources: bool,
+    /// Whether the server is considered ready if a source cannot be reached.
+    pub require_sources: bool,
+    /// Maximum time for each check.
+    pub timeout: Duration,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Readiness {
+            probe_sources: false,
+            require_sources: false,
+            timeout: Duration::from_secs(5),
         }
     }
 }
@@ -154,6 +247,15 @@ pub struct Config {
 
     /// Allow reserved IP addresses for requests to sources.
     pub connect_to_reserved_ips: bool,
+
+    /// Outbound HTTP proxy for all downloads.
+    pub proxy: ProxyConfig,
+
+    /// Checks performed by the readiness endpoint.
+    pub readiness: Readiness,
+
+    /// Maximum time to wait for running symbolication requests when shutting down.
+    pub shutdown_timeout: Duration,
 }
 
 impl Config {
@@ -167,6 +269,74 @@ impl Config {
     pub fn default_sources(&self) -> Arc<Vec<SourceConfig>> {
         self.sources.clone()
     }
+
+    /// Resolves the sources of a symbolication request against the configured sources.
+    ///
+    /// Without sources, all configured sources are used. Otherwise, inline sources and references
+    /// are used in the given order. If the request excludes any configured source, all remaining
+    /// configured sources are used in addition.
+    ///
+    /// Inline sources may not override proxy settings, since proxies are exempt from the
+    /// restriction on reserved IP addresses.
+    pub fn resolve_sources(
+        &self,
+        sources: Option<Vec<RequestSource>>,
+    ) -> Result<Arc<Vec<SourceConfig>>, SourceError> {
+        let requested = match sources {
+            Some(requested) => requested,
+            None => return Ok(self.default_sources()),
+        };
+
+        let find = |id: &str| {
+            self.sources
+                .iter()
+                .find(|source| source.id() == id)
+                .ok_or_else(|| SourceError::Unknown(id.to_owned()))
+        };
+
+        let mut excluded = Vec::new();
+        for source in &requested {
+            if let RequestSource::Reference(ref reference) = *source {
+                if reference.kind == SourceReferenceKind::Exclude {
+                    excluded.push(find(&reference.id)?.id());
+                }
+            }
+        }
+
+        let mut resolved = Vec::new();
+        for source in requested {
+            match source {
+                RequestSource::Inline(source) => {
+                    if source.proxy().is_some() {
+                        return Err(SourceError::Proxy(source.id().to_owned()));
+                    }
+                    if source.uses_server_credentials() {
+                        return Err(SourceError::Credentials(source.id().to_owned()));
+                    }
+                    resolved.push(source);
+                }
+                RequestSource::Reference(reference) => {
+                    let source = find(&reference.id)?;
+                    let duplicate = resolved.iter().any(|s| s.id() == source.id());
+                    if reference.kind == SourceReferenceKind::Ref && !duplicate {
+                        resolved.push(source.clone());
+                    }
+                }
+            }
+        }
+
+        if !excluded.is_empty() {
+            for source in self.sources.iter() {
+                let skip = excluded.contains(&source.id())
+                    || resolved.iter().any(|s| s.id() == source.id());
+                if !skip {
+                    resolved.push(source.clone());
+                }
+            }
+        }
+
+        Ok(Arc::new(resolved))
+    }
 }
 
 /// Checks if we are running in docker.
@@ -212,15 +382,314 @@ impl Default for Config {
             symstore_proxy: true,
             sources: Arc::new(vec![]),
             connect_to_reserved_ips: false,
+            proxy: ProxyConfig::default(),
+            readiness: Readiness::default(),
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Resolves a single `${...}` reference.
+///
+/// References of the form `${file:PATH}` are replaced with the contents of the file at `PATH`,
+/// without trailing newlines. All other references name an environment variable.
+fn resolve_reference(reference: &str) -> Result<String, ConfigError> {
+    if reference.starts_with("file:") {
+        let path = &reference["file:".len()..];
+        let contents = fs::read_to_string(path).map_err(|cause| ConfigError::SecretFile {
+            path: path.to_owned(),
+            cause,
+        })?;
+
+        Ok(contents
+            .trim_end_matches(|c| c == '\n' || c == '\r')
+            .to_owned())
+    } else {
+        env::var(reference).map_err(|_| ConfigError::MissingVariable(reference.to_owned()))
+    }
+}
+
+/// Expands `${VAR}` and `${file:PATH}` references in a config string.
+///
+/// A literal `${` can be written as `$${`.
+fn expand_string(value: &str) -> Result<String, ConfigError> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(index) = rest.find("${") {
+        if rest[..index].ends_with('$') {
+            expanded.push_str(&rest[..index - 1]);
+            expanded.push_str("${");
+            rest = &rest[index + 2..];
+            continue;
         }
+
+        expanded.push_str(&rest[..index]);
+        let end = match rest[index..].find('}') {
+            Some(end) => index + end,
+            None => return Err(ConfigError::UnterminatedReference(value.to_owned())),
+        };
+
+        expanded.push_str(&resolve_reference(&rest[index + 2..end])?);
+        rest = &rest[end + 1..];
     }
+
+    expanded.push_str(rest);
+    Ok(expanded)
 }
 
+/// Recursively expands references in all strings of a YAML value.
+fn expand_value(value: &mut Value) -> Result<(), ConfigError> {
+    match value {
+        Value::String(string) => *string = expand_string(string)?,
+        Value::Sequence(sequence) => {
+            for item in sequence {
+                expand_value(item)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                expand_value(item)?;
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => (),
+    }
+
+    Ok(())
+}
+
+/// Top-level config keys that may contain secrets and support references.
+const EXPANDED_KEYS: &[&str] = &["sources", "metrics", "sentry_dsn", "proxy"];
+
 impl Config {
     pub fn get(path: Option<&Path>) -> Result<Self, ConfigError> {
-        Ok(match path {
-            Some(path) => serde_yaml::from_reader(fs::File::open(path)?)?,
-            None => Config::default(),
-        })
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+
+        let mut value: Value = serde_yaml::from_reader(fs::File::open(path)?)?;
+        if let Value::Mapping(ref mut mapping) = value {
+            for key in EXPANDED_KEYS {
+                if let Some(item) = mapping.get_mut(&Value::String((*key).to_owned())) {
+                    expand_value(item)?;
+                }
+            }
+        }
+
+        Ok(serde_yaml::from_value(value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn test_partial_cache_config() {
+        let config: CacheConfig = serde_yaml::from_str(
+            r#"
+            max_unused_for: {secs: 86400, nanos: 0}
+            retry_misses_after: {secs: 3600, nanos: 0}
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.max_unused_for, Some(Duration::from_secs(86400)));
+        assert_eq!(config.retry_misses_after, Some(Duration::from_secs(3600)));
+        assert_eq!(config.retry_malformed_after, None);
+        assert_eq!(
+            config.retry_download_errors_after,
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn test_expand_variable() {
+        env::set_var("SYMBOLICATOR_TEST_SECRET", "hunter2");
+        assert_eq!(
+            expand_string("key-${SYMBOLICATOR_TEST_SECRET}-suffix").unwrap(),
+            "key-hunter2-suffix"
+        );
+    }
+
+    #[test]
+    fn test_expand_missing_variable() {
+        let error = expand_string("${SYMBOLICATOR_TEST_UNSET}").unwrap_err();
+        match error {
+            ConfigError::MissingVariable(name) => assert_eq!(name, "SYMBOLICATOR_TEST_UNSET"),
+            _ => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn test_expand_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "s3cr3t").unwrap();
+
+        let reference = format!("${{file:{}}}", file.path().display());
+        assert_eq!(expand_string(&reference).unwrap(), "s3cr3t");
+    }
+
+    #[test]
+    fn test_expand_escaped() {
+        assert_eq!(expand_string("a$${b}c").unwrap(), "a${b}c");
+        assert_eq!(expand_string("no $ref").unwrap(), "no $ref");
+        assert!(expand_string("${UNTERMINATED").is_err());
+    }
+
+    fn source_config() -> Config {
+        let sources = serde_yaml::from_str(
+            r#"
+            - {type: filesystem, id: a, path: /a}
+            - {type: filesystem, id: b, path: /b}
+            - {type: filesystem, id: c, path: /c}
+            "#,
+        )
+        .unwrap();
+
+        Config {
+            sources: Arc::new(sources),
+            ..Config::default()
+        }
+    }
+
+    fn resolve(config: &Config, sources: &str) -> Result<Vec<String>, SourceError> {
+        let sources = serde_json::from_str(sources).unwrap();
+        let resolved = config.resolve_sources(Some(sources))?;
+        Ok(resolved.iter().map(|s| s.id().to_owned()).collect())
+    }
+
+    #[test]
+    fn test_zero_concurrent_requests() {
+        let sources = serde_yaml::from_str::<Vec<SourceConfig>>(
+            "[{type: filesystem, id: a, path: /a, max_concurrent_requests: 0}]",
+        );
+        assert!(sources.is_err());
+
+        let sources = serde_yaml::from_str::<Vec<SourceConfig>>(
+            "[{type: filesystem, id: a, path: /a, max_concurrent_requests: 1}]",
+        )
+        .unwrap();
+        assert_eq!(
+            sources[0].download_config().max_concurrent_requests,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_resolve_references() {
+        let config = source_config();
+        let sources = r#"[
+            {"type": "filesystem", "id": "inline", "path": "/inline"},
+            {"type": "ref", "id": "c"}
+        ]"#;
+
+        assert_eq!(resolve(&config, sources).unwrap(), vec!["inline", "c"]);
+        assert_eq!(config.resolve_sources(None).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_resolve_excludes() {
+        let config = source_config();
+        let sources = r#"[
+            {"type": "ref", "id": "c"},
+            {"type": "exclude", "id": "b"}
+        ]"#;
+
+        assert_eq!(resolve(&config, sources).unwrap(), vec!["c", "a"]);
+    }
+
+    #[test]
+    fn test_resolve_unknown() {
+        let config = source_config();
+        let error = resolve(&config, r#"[{"type": "ref", "id": "x"}]"#).unwrap_err();
+        match error {
+            SourceError::Unknown(id) => assert_eq!(id, "x"),
+            _ => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn test_resolve_inline_proxy() {
+        let config = source_config();
+        let sources = r#"[{
+            "type": "filesystem",
+            "id": "inline",
+            "path": "/inline",
+            "proxy": {"http_proxy": "http://proxy:3128"}
+        }]"#;
+
+        let error = resolve(&config, sources).unwrap_err();
+        match error {
+            SourceError::Proxy(id) => assert_eq!(id, "inline"),
+            _ => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn test_resolve_inline_gcs_credentials() {
+        let config = source_config();
+        let sources = r#"[{
+            "type": "gcs",
+            "id": "inline",
+            "bucket": "symbols",
+            "private_key": "a2V5",
+            "client_email": "symbolicator@example.iam.gserviceaccount.com"
+        }]"#;
+
+        assert_eq!(resolve(&config, sources).unwrap(), vec!["inline"]);
+
+        let rejected = [
+            r#"[{"type": "gcs", "id": "inline", "bucket": "symbols",
+                 "gcs_credentials_provider": "metadata"}]"#,
+            r#"[{"type": "gcs", "id": "inline", "bucket": "symbols",
+                 "gcs_credentials_provider": "application_default"}]"#,
+            r#"[{"type": "gcs", "id": "inline", "bucket": "symbols",
+                 "private_key": "a2V5", "client_email": "a@example.com",
+                 "credentials_file": "/etc/passwd"}]"#,
+            r#"[{"type": "gcs", "id": "inline", "bucket": "symbols",
+                 "private_key": "a2V5", "client_email": "a@example.com",
+                 "metadata_url": "http://127.0.0.1/"}]"#,
+        ];
+
+        for sources in rejected.iter() {
+            let error = resolve(&config, sources).unwrap_err();
+            match error {
+                SourceError::Credentials(id) => assert_eq!(id, "inline"),
+                _ => panic!("unexpected error: {}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_inline_s3_credentials() {
+        let config = source_config();
+        let sources = r#"[{
+            "type": "s3",
+            "id": "inline",
+            "bucket": "symbols",
+            "region": "us-east-1",
+            "access_key": "AKIAEXAMPLE",
+            "secret_key": "c2VjcmV0"
+        }]"#;
+
+        assert_eq!(resolve(&config, sources).unwrap(), vec!["inline"]);
+
+        let sources = r#"[{
+            "type": "s3",
+            "id": "inline",
+            "bucket": "symbols",
+            "region": "us-east-1",
+            "aws_credentials_provider": "instance"
+        }]"#;
+
+        let error = resolve(&config, sources).unwrap_err();
+        match error {
+            SourceError::Credentials(id) => assert_eq!(id, "inline"),
+            _ => panic!("unexpected error: {}", error),
+        }
     }
 }
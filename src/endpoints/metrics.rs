@@ -0,0 +1,36 @@
+use actix_web::{web, HttpResponse};
+
+use crate::metrics;
+
+/// Returns all metrics in the Prometheus text format, if enabled.
+fn get_metrics() -> HttpResponse {
+    match metrics::render_prometheus() {
+        Some(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.route("/metrics", web::get().to(get_metrics));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::Service as _;
+    use actix_web::http::StatusCode;
+
+    use crate::test;
+
+    #[test]
+    fn test_get_disabled() {
+        test::setup();
+
+        let mut server = test::test_service(Default::default());
+        let request = test::TestRequest::with_uri("/metrics").to_request();
+        let response = test::block_fn(|| server.call(request)).unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
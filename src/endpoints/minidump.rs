@@ -3,17 +3,18 @@ use actix_web::web::Bytes;
 use actix_web::{error, web, Error};
 use futures::{future, Future, Stream};
 
-use crate::endpoints::symbolicate::SymbolicationRequestQueryParams;
+use crate::config::Config;
+use crate::endpoints::symbolicate::{check_accepting, SymbolicationRequestQueryParams};
 use crate::service::symbolication::SymbolicationActor;
 use crate::service::Service;
-use crate::types::{RequestId, Scope, SourceConfig, SymbolicationResponse};
+use crate::types::{RequestId, RequestOptions, RequestSource, Scope, SymbolicationResponse};
 use crate::utils::futures::ResultFuture;
 use crate::utils::multipart::{read_multipart_file, read_multipart_sources};
 use crate::utils::sentry::ToSentryScope;
 
 #[derive(Debug, Default)]
 struct MinidumpRequest {
-    sources: Option<Vec<SourceConfig>>,
+    sources: Option<Vec<RequestSource>>,
     minidump: Option<Bytes>,
 }
 
@@ -49,18 +50,20 @@ fn handle_form_field(
 
 fn process_minidump(
     symbolication: &SymbolicationActor,
+    config: &Config,
     request: MinidumpRequest,
     scope: Scope,
+    options: RequestOptions,
 ) -> Result<RequestId, Error> {
     let minidump = request
         .minidump
         .ok_or_else(|| error::ErrorBadRequest("missing minidump"))?;
 
-    let sources = request
-        .sources
-        .ok_or_else(|| error::ErrorBadRequest("missing sources"))?;
+    let sources = config
+        .resolve_sources(request.sources)
+        .map_err(error::ErrorBadRequest)?;
 
-    Ok(symbolication.process_minidump(scope, minidump, sources))
+    Ok(symbolication.process_minidump(scope, minidump, (*sources).clone(), options))
 }
 
 fn post_minidump(
@@ -70,24 +73,25 @@ fn post_minidump(
 ) -> ResultFuture<web::Json<SymbolicationResponse>, Error> {
     log::trace!("Received minidump");
 
-    let default_sources = service.config().default_sources();
+    if let Err(error) = check_accepting(&service) {
+        return Box::new(future::err(error));
+    }
+
+    let config = service.config();
     let symbolication = service.symbolication();
 
     let params = params.into_inner();
     params.configure_scope();
 
-    let SymbolicationRequestQueryParams { scope, timeout } = params;
+    let options = params.options();
+    let SymbolicationRequestQueryParams { scope, timeout, .. } = params;
     let response = multipart
         .map_err(Error::from)
         .fold(MinidumpRequest::default(), move |request, item| {
             handle_form_field(request, item)
         })
-        .and_then(clone!(symbolication, |mut request| {
-            if request.sources.is_none() {
-                request.sources = Some((*default_sources).clone());
-            }
-
-            process_minidump(&symbolication, request, scope)
+        .and_then(clone!(symbolication, |request| {
+            process_minidump(&symbolication, &config, request, scope, options)
         }))
         .and_then(move |request_id| {
             symbolication
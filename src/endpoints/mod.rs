@@ -1,18 +1,28 @@
 use actix_web::web;
 
 mod applecrashreport;
+mod coredump;
 mod healthcheck;
+mod metrics;
 mod minidump;
 mod proxy;
+mod ready;
 mod requests;
-mod symbolicate;
+mod sources;
+pub(crate) mod symbolicate;
+mod tombstone;
 
 /// Adds all endpoint routes to the app.
 pub fn configure(config: &mut web::ServiceConfig) {
     applecrashreport::configure(config);
+    coredump::configure(config);
     healthcheck::configure(config);
+    metrics::configure(config);
     minidump::configure(config);
     proxy::configure(config);
+    ready::configure(config);
     requests::configure(config);
+    sources::configure(config);
     symbolicate::configure(config);
+    tombstone::configure(config);
 }
@@ -8,9 +8,9 @@ use tokio::codec::{BytesCodec, FramedRead};
 
 use crate::service::objects::{FindObject, ObjectPurpose};
 use crate::service::Service;
-use crate::types::Scope;
+use crate::types::{FileType, ObjectId, Scope};
 use crate::utils::futures::ResultFuture;
-use crate::utils::paths::parse_symstore_path;
+use crate::utils::paths::{parse_debuginfod_path, parse_symstore_path};
 
 /// Path parameters of the symstore proxy request.
 #[derive(Debug, Deserialize)]
@@ -18,25 +18,20 @@ struct ProxyPath {
     pub path: String,
 }
 
-fn get_symstore_proxy(
-    service: web::Data<Service>,
-    path: web::Path<ProxyPath>,
-    request: HttpRequest,
-) -> ResultFuture<HttpResponse, Error> {
-    let is_head = request.method() == http::Method::HEAD;
-
-    if !service.config().symstore_proxy {
-        log::trace!("Ignoring proxy request (disabled in config)");
-        return Box::new(future::ok(HttpResponse::NotFound().finish()));
-    }
-
-    log::trace!("Received proxy {} request", request.method());
-
-    let (filetypes, object_id) = match parse_symstore_path(&path.path) {
-        Some((filetypes, object_id)) => (filetypes, object_id),
-        None => return Box::new(future::ok(HttpResponse::NotFound().finish())),
-    };
+/// Path parameters of the debuginfod proxy request.
+#[derive(Debug, Deserialize)]
+struct DebuginfodPath {
+    pub build_id: String,
+    pub kind: String,
+}
 
+/// Finds an object in the default sources and responds with its contents.
+fn proxy_object(
+    service: &Service,
+    filetypes: &'static [FileType],
+    object_id: ObjectId,
+    is_head: bool,
+) -> ResultFuture<HttpResponse, Error> {
     log::debug!("Searching for {:?} ({:?})", object_id, filetypes);
 
     let objects = service.objects();
@@ -48,7 +43,7 @@ fn get_symstore_proxy(
             scope: Scope::Global,
             purpose: ObjectPurpose::Debug,
         })
-        .and_then(move |meta_opt| match meta_opt {
+        .and_then(move |found| match found.meta {
             Some(meta) => future::Either::A(objects.fetch(meta).map(Some)),
             None => future::Either::B(future::ok(None)),
         })
@@ -85,6 +80,46 @@ fn get_symstore_proxy(
     Box::new(response)
 }
 
+fn get_symstore_proxy(
+    service: web::Data<Service>,
+    path: web::Path<ProxyPath>,
+    request: HttpRequest,
+) -> ResultFuture<HttpResponse, Error> {
+    let is_head = request.method() == http::Method::HEAD;
+
+    if !service.config().symstore_proxy {
+        log::trace!("Ignoring proxy request (disabled in config)");
+        return Box::new(future::ok(HttpResponse::NotFound().finish()));
+    }
+
+    log::trace!("Received proxy {} request", request.method());
+
+    match parse_symstore_path(&path.path) {
+        Some((filetypes, object_id)) => proxy_object(&service, filetypes, object_id, is_head),
+        None => Box::new(future::ok(HttpResponse::NotFound().finish())),
+    }
+}
+
+fn get_debuginfod_proxy(
+    service: web::Data<Service>,
+    path: web::Path<DebuginfodPath>,
+    request: HttpRequest,
+) -> ResultFuture<HttpResponse, Error> {
+    let is_head = request.method() == http::Method::HEAD;
+
+    if !service.config().symstore_proxy {
+        log::trace!("Ignoring debuginfod proxy request (disabled in config)");
+        return Box::new(future::ok(HttpResponse::NotFound().finish()));
+    }
+
+    log::trace!("Received debuginfod proxy {} request", request.method());
+
+    match parse_debuginfod_path(&path.build_id, &path.kind) {
+        Some((filetypes, object_id)) => proxy_object(&service, filetypes, object_id, is_head),
+        None => Box::new(future::ok(HttpResponse::NotFound().finish())),
+    }
+}
+
 pub fn configure(config: &mut web::ServiceConfig) {
     config.route(
         "/symbols/{path:.+}",
@@ -92,6 +127,13 @@ pub fn configure(config: &mut web::ServiceConfig) {
             .guard(guard::Any(guard::Get()).or(guard::Head()))
             .to(get_symstore_proxy),
     );
+
+    config.route(
+        "/buildid/{build_id}/{kind}",
+        web::route()
+            .guard(guard::Any(guard::Get()).or(guard::Head()))
+            .to(get_debuginfod_proxy),
+    );
 }
 
 #[cfg(test)]
@@ -104,6 +146,10 @@ mod tests {
 
     const VALID_PATH: &str = "/symbols/crash.pdb/3249D99D0C4049318610F4E4FB0B69361/crash.pdb";
     const INVALID_PATH: &str = "/symbols/crash.pdb/000000000000000000000000000000000/invalid.pdb";
+    const DEBUGINFOD_VALID_PATH: &str =
+        "/buildid/7b4d92ac02b11256ba5afbc90578af82e85305c7/debuginfo";
+    const DEBUGINFOD_PATH: &str = "/buildid/dfb85de42daffd09640c8fe377d572de3e168920/debuginfo";
+    const DEBUGINFOD_SOURCE_PATH: &str = "/buildid/dfb85de42daffd09640c8fe377d572de3e168920/source";
 
     #[test]
     fn test_head_valid() {
@@ -174,4 +220,55 @@ mod tests {
         let response = test::block_fn(|| server.call(request)).unwrap();
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
+
+    #[test]
+    fn test_debuginfod_get_valid() {
+        test::setup();
+
+        let mut config = Config::default();
+        config.symstore_proxy = true;
+        config.sources = vec![test::local_debuginfod_source()].into();
+        let mut server = test::test_service(config);
+
+        let request = TestRequest::with_uri(DEBUGINFOD_VALID_PATH)
+            .method(Method::GET)
+            .to_request();
+
+        let response = test::block_fn(|| server.call(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response).len(), 16776);
+    }
+
+    #[test]
+    fn test_debuginfod_get_missing() {
+        test::setup();
+
+        let mut config = Config::default();
+        config.symstore_proxy = true;
+        config.sources = vec![test::local_source()].into();
+        let mut server = test::test_service(config);
+
+        let request = TestRequest::with_uri(DEBUGINFOD_PATH)
+            .method(Method::GET)
+            .to_request();
+
+        let response = test::block_fn(|| server.call(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_debuginfod_unsupported_kind() {
+        test::setup();
+
+        let mut config = Config::default();
+        config.symstore_proxy = true;
+        let mut server = test::test_service(config);
+
+        let request = TestRequest::with_uri(DEBUGINFOD_SOURCE_PATH)
+            .method(Method::GET)
+            .to_request();
+
+        let response = test::block_fn(|| server.call(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 }
@@ -0,0 +1,180 @@
+use std::env;
+
+use actix_web::error::BlockingError;
+use actix_web::{web, Error, HttpResponse};
+use failure::Fail;
+use futures::{future, Future};
+use serde::Serialize;
+
+use crate::service::download::{DownloadErrorKind, DownloadPath};
+use crate::service::Service;
+use crate::utils::futures::{FutureExt, ResultFuture};
+
+/// A path that is requested from sources to check whether they can be reached.
+///
+/// The file is not expected to exist, so that the request is cheap.
+const PROBE_PATH: &str = "_symbolicator/ready";
+
+/// The outcome of a single readiness check.
+#[derive(Debug, Serialize)]
+struct Check {
+    /// The name of the check, such as `cache.objects`.
+    name: String,
+    /// Whether the server is not ready if this check fails.
+    required: bool,
+    /// Whether the check succeeded.
+    ok: bool,
+    /// A description of the failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReadyResponse {
+    ready: bool,
+    checks: Vec<Check>,
+}
+
+/// Resolves the future into the outcome of a check.
+fn check<F>(name: String, required: bool, future: F) -> ResultFuture<Check, Error>
+where
+    F: Future<Item = (), Error = String> + 'static,
+{
+    Box::new(future.then(move |result| {
+        Ok(Check {
+            name,
+            required,
+            ok: result.is_ok(),
+            error: result.err(),
+        })
+    }))
+}
+
+/// Renders the error and all of its causes.
+fn error_details(error: &dyn Fail) -> String {
+    let causes: Vec<_> = Fail::iter_chain(error)
+        .map(|cause| cause.to_string())
+        .collect();
+
+    causes.join(": ")
+}
+
+/// Checks whether the server can handle requests.
+///
+/// The server must not be shutting down, all caches must be writable and all thread pools must be
+/// responsive. If configured, default sources are probed with a request for a file that does not
+/// exist.
+fn get_ready(service: web::Data<Service>) -> ResultFuture<HttpResponse, Error> {
+    let config = service.config();
+    let timeout = config.readiness.timeout;
+    let mut checks = Vec::new();
+
+    let result = if service.symbolication().is_shutting_down() {
+        Err("server is shutting down".to_owned())
+    } else {
+        Ok(())
+    };
+    checks.push(check("shutdown".to_owned(), true, future::result(result)));
+
+    for cache in service.caches().all().iter() {
+        let name = format!("cache.{}", cache.name());
+        let cache = (*cache).clone();
+        let future = web::block(move || cache.check_writable())
+            .map_err(|error| match error {
+                BlockingError::Error(e) => format!("cache is not writable: {}", e),
+                BlockingError::Canceled => "cache check was canceled".to_owned(),
+            })
+            .timeout(timeout, || "cache is not responsive".to_owned());
+        checks.push(check(name, true, future));
+    }
+
+    for (name, pool) in service.thread_pools() {
+        let future = pool
+            .ping()
+            .timeout(timeout, || ())
+            .map_err(|()| "thread pool is not responsive".to_owned());
+        checks.push(check(format!("pool.{}", name), true, future));
+    }
+
+    if config.readiness.probe_sources {
+        // Files found by a probe are removed right away. Prefer the objects cache, which is on
+        // the same volume as downloads.
+        let temp_dir = match service.caches().objects.cache_dir() {
+            Some(cache_dir) => cache_dir.to_owned(),
+            None => env::temp_dir(),
+        };
+
+        let downloader = service.downloader();
+        for source in config.default_sources().iter() {
+            let name = format!("source.{}", source.id());
+            let path = DownloadPath::from(PROBE_PATH.to_owned());
+            let future = downloader
+                .probe(source.clone(), path, temp_dir.clone())
+                .timeout(timeout, || DownloadErrorKind::Timeout)
+                .map_err(|e| error_details(&e));
+            checks.push(check(name, config.readiness.require_sources, future));
+        }
+    }
+
+    let response = future::join_all(checks).map(|checks| {
+        let ready = checks.iter().all(|check| check.ok || !check.required);
+        let body = ReadyResponse { ready, checks };
+
+        if ready {
+            HttpResponse::Ok().json(body)
+        } else {
+            HttpResponse::ServiceUnavailable().json(body)
+        }
+    });
+
+    Box::new(response)
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.route("/ready", web::get().to(get_ready));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::Service as _;
+    use actix_web::http::StatusCode;
+
+    use crate::config::Config;
+    use crate::test;
+
+    #[test]
+    fn test_ready() {
+        test::setup();
+
+        let cache_dir = test::tempdir();
+        let config = Config {
+            cache_dir: Some(cache_dir.path().to_owned()),
+            ..Default::default()
+        };
+
+        let mut server = test::test_service(config);
+        let request = test::TestRequest::with_uri("/ready").to_request();
+        let response = test::block_fn(|| server.call(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(response)).unwrap();
+        assert_eq!(body["ready"], true);
+        assert_eq!(body["checks"].as_array().unwrap().len(), 7);
+    }
+
+    #[test]
+    fn test_missing_cache_dir() {
+        test::setup();
+
+        let cache_dir = test::tempdir();
+        let config = Config {
+            cache_dir: Some(cache_dir.path().join("missing")),
+            ..Default::default()
+        };
+
+        let mut server = test::test_service(config);
+        let request = test::TestRequest::with_uri("/ready").to_request();
+        let response = test::block_fn(|| server.call(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
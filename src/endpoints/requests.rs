@@ -1,4 +1,4 @@
-use actix_web::{error, web, Error};
+use actix_web::{error, web, Error, HttpResponse};
 use futures::Future;
 use serde::Deserialize;
 
@@ -38,7 +38,89 @@ fn get_request(
     Box::new(response)
 }
 
-/// Adds the request poll endpoint to the app.
+fn delete_request(
+    service: web::Data<Service>,
+    path: web::Path<PollSymbolicationRequestPath>,
+) -> Result<HttpResponse, Error> {
+    log::trace!("Received cancel request for id {}", path.request_id);
+
+    if service
+        .symbolication()
+        .cancel_request(path.into_inner().request_id)
+    {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(error::ErrorNotFound("Request does not exist"))
+    }
+}
+
+/// Adds the request poll and cancel endpoints to the app.
 pub fn configure(config: &mut web::ServiceConfig) {
-    config.route("/requests/{request_id}", web::get().to(get_request));
+    config.service(
+        web::resource("/requests/{request_id}")
+            .route(web::get().to(get_request))
+            .route(web::delete().to(delete_request)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::dev::Service as _;
+    use actix_web::http::StatusCode;
+
+    use crate::config::Config;
+    use crate::service::symbolication::SymbolicateStacktraces;
+    use crate::service::Service;
+    use crate::test;
+    use crate::types::Scope;
+
+    #[test]
+    fn test_poll_while_shutting_down() {
+        test::setup();
+
+        let cache_dir = test::tempdir();
+        let service = Service::create(Config {
+            cache_dir: Some(cache_dir.path().to_owned()),
+            ..Default::default()
+        });
+
+        let mut server = test::test_app(service.clone());
+        let symbolication = service.symbolication();
+
+        let request_id = test::block_fn(|| {
+            let request_id = symbolication.symbolicate_stacktraces(SymbolicateStacktraces {
+                scope: Scope::Global,
+                signal: None,
+                sources: Arc::new(Vec::new()),
+                stacktraces: Vec::new(),
+                modules: Vec::new(),
+                options: Default::default(),
+            });
+
+            Ok::<_, ()>(request_id)
+        })
+        .unwrap();
+
+        symbolication.shut_down();
+
+        // New requests are rejected.
+        let request = test::TestRequest::post()
+            .uri("/symbolicate")
+            .set_json(&serde_json::json!({"stacktraces": [], "modules": []}))
+            .to_request();
+        let response = test::block_fn(|| server.call(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // The running request can still be polled.
+        let uri = format!("/requests/{}", request_id);
+        let request = test::TestRequest::with_uri(&uri).to_request();
+        let response = test::block_fn(|| server.call(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(response)).unwrap();
+        assert_eq!(body["status"], "completed");
+        assert_eq!(symbolication.running_requests(), 0);
+    }
 }
@@ -0,0 +1,34 @@
+use actix_web::{web, Error};
+
+use crate::service::download::SourceHealth;
+use crate::service::Service;
+
+/// Returns the health of all sources that have been requested recently.
+fn get_source_health(service: web::Data<Service>) -> Result<web::Json<Vec<SourceHealth>>, Error> {
+    Ok(web::Json(service.downloader().source_health()))
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.route("/sources/health", web::get().to(get_source_health));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::Service as _;
+    use actix_web::http::StatusCode;
+    use actix_web::web::Bytes;
+
+    use crate::test;
+
+    #[test]
+    fn test_get() {
+        test::setup();
+
+        let mut server = test::test_service(Default::default());
+        let request = test::TestRequest::with_uri("/sources/health").to_request();
+        let response = test::block_fn(|| server.call(request)).unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response), Bytes::from("[]"));
+    }
+}
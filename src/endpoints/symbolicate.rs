@@ -1,13 +1,12 @@
-use std::sync::Arc;
-
 use actix_web::{error, web, Error, FromRequest};
-use futures::Future;
+use futures::{future, Future};
 use serde::Deserialize;
 
 use crate::service::symbolication::SymbolicateStacktraces;
 use crate::service::Service;
 use crate::types::{
-    RawObjectInfo, RawStacktrace, Scope, Signal, SourceConfig, SymbolicationResponse,
+    RawObjectInfo, RawStacktrace, RequestOptions, RequestSource, Scope, Signal,
+    SymbolicationResponse,
 };
 use crate::utils::futures::ResultFuture;
 use crate::utils::sentry::ToSentryScope;
@@ -19,6 +18,17 @@ pub struct SymbolicationRequestQueryParams {
     pub timeout: Option<u64>,
     #[serde(default)]
     pub scope: Scope,
+    #[serde(default)]
+    pub dif_candidates: bool,
+}
+
+impl SymbolicationRequestQueryParams {
+    /// Returns the options that control the output of the request.
+    pub fn options(&self) -> RequestOptions {
+        RequestOptions {
+            dif_candidates: self.dif_candidates,
+        }
+    }
 }
 
 impl ToSentryScope for SymbolicationRequestQueryParams {
@@ -32,13 +42,24 @@ impl ToSentryScope for SymbolicationRequestQueryParams {
     }
 }
 
+/// Rejects new symbolication requests while the server is shutting down.
+///
+/// Polls for running requests are still served, so that clients can collect their responses.
+pub fn check_accepting(service: &Service) -> Result<(), Error> {
+    if service.symbolication().is_shutting_down() {
+        return Err(error::ErrorServiceUnavailable("server is shutting down"));
+    }
+
+    Ok(())
+}
+
 /// JSON body of the symbolication request.
 #[derive(Debug, Deserialize)]
-struct SymbolicationRequestBody {
+pub struct SymbolicationRequestBody {
     #[serde(default)]
     pub signal: Option<Signal>,
     #[serde(default)]
-    pub sources: Option<Vec<SourceConfig>>,
+    pub sources: Option<Vec<RequestSource>>,
     #[serde(default)]
     pub stacktraces: Vec<RawStacktrace>,
     #[serde(default)]
@@ -52,18 +73,25 @@ fn post_payload(
 ) -> ResultFuture<web::Json<SymbolicationResponse>, Error> {
     log::trace!("Received symbolication request");
 
+    if let Err(error) = check_accepting(&service) {
+        return Box::new(future::err(error));
+    }
+
     let params = params.into_inner();
     params.configure_scope();
 
     let body = body.into_inner();
+    let sources = match service.config().resolve_sources(body.sources) {
+        Ok(sources) => sources,
+        Err(error) => return Box::new(future::err(error::ErrorBadRequest(error))),
+    };
+
     let message = SymbolicateStacktraces {
         signal: body.signal,
-        sources: match body.sources {
-            Some(sources) => Arc::new(sources),
-            None => service.config().default_sources(),
-        },
+        sources,
         stacktraces: body.stacktraces,
         modules: body.modules.into_iter().map(From::from).collect(),
+        options: params.options(),
         scope: params.scope,
     };
 
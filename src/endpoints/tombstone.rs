@@ -0,0 +1,113 @@
+use actix_multipart::{Field, Multipart};
+use actix_web::web::Bytes;
+use actix_web::{error, web, Error};
+use futures::{future, Future, Stream};
+
+use crate::config::Config;
+use crate::endpoints::symbolicate::{check_accepting, SymbolicationRequestQueryParams};
+use crate::service::symbolication::SymbolicationActor;
+use crate::service::Service;
+use crate::types::{RequestId, RequestOptions, RequestSource, Scope, SymbolicationResponse};
+use crate::utils::futures::ResultFuture;
+use crate::utils::multipart::{read_multipart_file, read_multipart_sources};
+use crate::utils::sentry::ToSentryScope;
+
+#[derive(Debug, Default)]
+struct TombstoneRequest {
+    sources: Option<Vec<RequestSource>>,
+    tombstone: Option<Bytes>,
+}
+
+fn handle_form_field(
+    mut request: TombstoneRequest,
+    field: Field,
+) -> ResultFuture<TombstoneRequest, Error> {
+    match field
+        .content_disposition()
+        .as_ref()
+        .and_then(|d| d.get_name())
+    {
+        Some("sources") => {
+            let future = read_multipart_sources(field).map(move |sources| {
+                request.sources = Some(sources);
+                request
+            });
+            Box::new(future)
+        }
+        Some("tombstone") => {
+            let future = read_multipart_file(field).map(move |tombstone| {
+                request.tombstone = Some(tombstone);
+                request
+            });
+            Box::new(future)
+        }
+        _ => {
+            let error = error::ErrorBadRequest("unknown formdata field");
+            Box::new(future::err(error))
+        }
+    }
+}
+
+fn parse_tombstone(
+    symbolication: &SymbolicationActor,
+    config: &Config,
+    request: TombstoneRequest,
+    scope: Scope,
+    options: RequestOptions,
+) -> Result<RequestId, Error> {
+    let tombstone = request
+        .tombstone
+        .ok_or_else(|| error::ErrorBadRequest("missing tombstone"))?;
+
+    let sources = config
+        .resolve_sources(request.sources)
+        .map_err(error::ErrorBadRequest)?;
+
+    Ok(symbolication.process_tombstone(scope, tombstone, (*sources).clone(), options))
+}
+
+fn post_tombstone(
+    service: web::Data<Service>,
+    params: web::Query<SymbolicationRequestQueryParams>,
+    multipart: Multipart,
+) -> ResultFuture<web::Json<SymbolicationResponse>, Error> {
+    log::trace!("Received tombstone");
+
+    if let Err(error) = check_accepting(&service) {
+        return Box::new(future::err(error));
+    }
+
+    let config = service.config();
+    let symbolication = service.symbolication();
+
+    let params = params.into_inner();
+    params.configure_scope();
+
+    let options = params.options();
+    let SymbolicationRequestQueryParams { scope, timeout, .. } = params;
+    let response = multipart
+        .map_err(Error::from)
+        .fold(TombstoneRequest::default(), move |request, item| {
+            handle_form_field(request, item)
+        })
+        .and_then(clone!(symbolication, |request| {
+            parse_tombstone(&symbolication, &config, request, scope, options)
+        }))
+        .and_then(move |request_id| {
+            symbolication
+                .get_response(request_id, timeout)
+                .then(|result| match result {
+                    Ok(Some(response)) => Ok(web::Json(response)),
+                    Ok(None) => Err(error::ErrorInternalServerError(
+                        "symbolication request did not start",
+                    )),
+                    Err(error) => Err(error::ErrorInternalServerError(error)),
+                })
+        });
+
+    Box::new(response)
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.route("/tombstone", web::post().to(post_tombstone));
+}
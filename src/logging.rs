@@ -5,11 +5,17 @@ use std::io::{self, Write};
 use chrono::{DateTime, Utc};
 use failure::AsFail;
 use log::{Level, LevelFilter};
+use parking_lot::RwLock;
 use sentry::integrations::log::{breadcrumb_from_record, event_from_record};
 use serde::{Deserialize, Serialize};
 
 use crate::config::{Config, LogFormat};
 
+lazy_static::lazy_static! {
+    /// The currently active logger, replaced when the configuration is reloaded.
+    static ref LOGGER: RwLock<Option<env_logger::Logger>> = RwLock::new(None);
+}
+
 fn get_rust_log(level: LevelFilter) -> &'static str {
     match level {
         LevelFilter::Off => "",
@@ -122,21 +128,32 @@ where
     }
 }
 
-/// Initializes logging for the symbolicator.
-///
-/// This considers the `RUST_LOG` environment variable and defaults it to the level specified in the
-/// configuration. Additionally, this toggles `RUST_BACKTRACE` based on the `enable_stacktraces`
-/// config value.
-pub fn init_logging(config: &Config) {
-    if config.logging.enable_backtraces {
-        env::set_var("RUST_BACKTRACE", "1");
+/// A logger that delegates to the currently active logger in `LOGGER`.
+struct ReloadableLogger;
+
+impl log::Log for ReloadableLogger {
+    fn enabled(&self, md: &log::Metadata<'_>) -> bool {
+        match *LOGGER.read() {
+            Some(ref logger) => logger.enabled(md),
+            None => false,
+        }
     }
 
-    if env::var("RUST_LOG").is_err() {
-        let rust_log = get_rust_log(config.logging.level);
-        env::set_var("RUST_LOG", rust_log);
+    fn log(&self, record: &log::Record<'_>) {
+        if let Some(ref logger) = *LOGGER.read() {
+            logger.log(record);
+        }
     }
 
+    fn flush(&self) {
+        if let Some(ref logger) = *LOGGER.read() {
+            logger.flush();
+        }
+    }
+}
+
+/// Builds a logger with the format and filters from the configuration.
+fn build_logger(config: &Config) -> env_logger::Logger {
     let mut builder = match (config.logging.format, console::user_attended()) {
         (LogFormat::Auto, true) | (LogFormat::Pretty, _) => pretty_logger(),
         (LogFormat::Auto, false) | (LogFormat::Simplified, _) => simplified_logger(),
@@ -145,16 +162,42 @@ pub fn init_logging(config: &Config) {
 
     match env::var("RUST_LOG") {
         Ok(rust_log) => builder.parse_filters(&rust_log),
-        Err(_) => builder.filter_level(config.logging.level),
+        Err(_) => builder.parse_filters(get_rust_log(config.logging.level)),
     };
 
-    let logger = builder.build();
+    builder.build()
+}
+
+/// Replaces the active logger.
+fn set_logger(logger: env_logger::Logger) {
     log::set_max_level(logger.filter());
+    *LOGGER.write() = Some(logger);
+}
+
+/// Initializes logging for the symbolicator.
+///
+/// This considers the `RUST_LOG` environment variable and falls back to filters for the level
+/// specified in the configuration. Additionally, this toggles `RUST_BACKTRACE` based on the `enable_stacktraces`
+/// config value.
+pub fn init_logging(config: &Config) {
+    if config.logging.enable_backtraces {
+        env::set_var("RUST_BACKTRACE", "1");
+    }
 
-    let breadcrumb_logger = Box::new(BreadcrumbLogger::new(logger));
+    set_logger(build_logger(config));
+
+    let breadcrumb_logger = Box::new(BreadcrumbLogger::new(ReloadableLogger));
     log::set_boxed_logger(breadcrumb_logger).unwrap();
 }
 
+/// Applies the logging configuration of a reloaded config.
+///
+/// The log level and format are replaced, unless filters were given explicitly via `RUST_LOG`.
+/// Records logged concurrently are written by either the old or the new logger.
+pub fn reload_logging(config: &Config) {
+    set_logger(build_logger(config));
+}
+
 /// Returns whether backtrace printing is enabled.
 pub fn backtrace_enabled() -> bool {
     match std::env::var("RUST_BACKTRACE").as_ref().map(String::as_str) {
@@ -19,6 +19,7 @@ mod config;
 mod endpoints;
 mod logging;
 mod middleware;
+mod offline;
 mod server;
 mod service;
 mod types;
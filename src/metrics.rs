@@ -1,12 +1,16 @@
 //! Provides access to the metrics sytem.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
 use std::net::ToSocketAddrs;
 use std::sync::Arc;
 
-use cadence::StatsdClient;
-use parking_lot::RwLock;
+use cadence::{MetricSink, StatsdClient};
+use parking_lot::{Mutex, RwLock};
 
 lazy_static::lazy_static! {
     static ref METRICS_CLIENT: RwLock<Option<Arc<StatsdClient>>> = RwLock::new(None);
+    static ref PROMETHEUS_REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
 }
 
 thread_local! {
@@ -39,6 +43,22 @@ pub fn configure_statsd<A: ToSocketAddrs>(prefix: &str, host: A) {
     set_client(StatsdClient::from_udp_host(prefix, &addrs[..]).unwrap());
 }
 
+/// Tell the metrics system to aggregate metrics in process for Prometheus.
+///
+/// The aggregated metrics can be retrieved in the Prometheus text format with `render_prometheus`.
+pub fn configure_prometheus(prefix: &str) {
+    log::info!("Exposing metrics for Prometheus");
+    *PROMETHEUS_REGISTRY.lock() = Some(Registry::default());
+    set_client(StatsdClient::from_sink(prefix, PrometheusSink));
+}
+
+/// Renders all aggregated metrics in the Prometheus text format.
+///
+/// Returns `None` if Prometheus is not configured.
+pub fn render_prometheus() -> Option<String> {
+    PROMETHEUS_REGISTRY.lock().as_ref().map(Registry::render)
+}
+
 /// Invoke a callback with the current statsd client.
 ///
 /// If statsd is not configured the callback is not invoked.  For the most part
@@ -119,5 +139,293 @@ macro_rules! metric {
                 .send();
         })
     }};
+}
+
+/// Upper bounds of the buckets of all histograms.
+///
+/// Timers are reported in milliseconds, but `time_raw` is also used for sizes in bytes. The buckets
+/// grow exponentially to cover both.
+const HISTOGRAM_BUCKETS: &[f64] = &[
+    1.0,
+    4.0,
+    16.0,
+    64.0,
+    256.0,
+    1024.0,
+    4096.0,
+    16_384.0,
+    65_536.0,
+    262_144.0,
+    1_048_576.0,
+    4_194_304.0,
+    16_777_216.0,
+    67_108_864.0,
+    268_435_456.0,
+    1_073_741_824.0,
+];
+
+/// A metric sink that records metrics in the global Prometheus registry.
+#[derive(Debug)]
+struct PrometheusSink;
+
+impl MetricSink for PrometheusSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        if let Some(ref mut registry) = *PROMETHEUS_REGISTRY.lock() {
+            registry.record(metric);
+        }
+
+        Ok(metric.len())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricType {
+    fn name(self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Histogram => "histogram",
+        }
+    }
+}
+
+/// The value of a single series, that is a metric with a specific set of labels.
+#[derive(Clone, Debug)]
+enum SeriesValue {
+    Value(f64),
+    Histogram {
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+type Labels = Vec<(String, String)>;
+
+/// All series of a metric.
+#[derive(Debug)]
+struct Family {
+    ty: MetricType,
+    series: BTreeMap<Labels, SeriesValue>,
+}
+
+/// Aggregates metrics emitted in the statsd format for Prometheus.
+#[derive(Debug, Default)]
+struct Registry {
+    families: BTreeMap<String, Family>,
+}
+
+impl Registry {
+    /// Records a single metric in the statsd format, such as `name:1|c|#tag:value`.
+    ///
+    /// Tags are converted into labels. Malformed metrics and unsupported metric types are ignored.
+    fn record(&mut self, metric: &str) {
+        let mut parts = metric.split('|');
+        let (name, value) = match parts.next() {
+            Some(part) => match part.rfind(':') {
+                Some(index) => (&part[..index], &part[index + 1..]),
+                None => return,
+            },
+            None => return,
+        };
+
+        let value = match value.parse::<f64>() {
+            Ok(value) => value,
+            Err(_) => return,
+        };
+
+        let ty = match parts.next() {
+            Some("c") | Some("m") => MetricType::Counter,
+            Some("g") => MetricType::Gauge,
+            Some("ms") | Some("h") => MetricType::Histogram,
+            _ => return,
+        };
+
+        let mut labels = Labels::new();
+        for part in parts {
+            if part.starts_with('#') {
+                for tag in part[1..].split(',') {
+                    let mut split = tag.splitn(2, ':');
+                    let key = split.next().unwrap_or_default();
+                    let value = split.next().unwrap_or_default();
+                    if !key.is_empty() {
+                        labels.push((sanitize_name(key, false), value.to_owned()));
+                    }
+                }
+            }
+        }
+        labels.sort();
+
+        let mut name = sanitize_name(name, true);
+        if ty == MetricType::Counter && !name.ends_with("_total") {
+            name.push_str("_total");
+        }
+
+        let family = self.families.entry(name).or_insert_with(|| Family {
+            ty,
+            series: BTreeMap::new(),
+        });
+
+        // The same name cannot be exposed with different types.
+        if family.ty != ty {
+            return;
+        }
+
+        let series = family.series.entry(labels).or_insert_with(|| match ty {
+            MetricType::Histogram => SeriesValue::Histogram {
+                buckets: vec![0; HISTOGRAM_BUCKETS.len()],
+                sum: 0.0,
+                count: 0,
+            },
+            _ => SeriesValue::Value(0.0),
+        });
+
+        match *series {
+            SeriesValue::Value(ref mut current) if ty == MetricType::Counter => *current += value,
+            SeriesValue::Value(ref mut current) => *current = value,
+            SeriesValue::Histogram {
+                ref mut buckets,
+                ref mut sum,
+                ref mut count,
+            } => {
+                for (bucket, bound) in buckets.iter_mut().zip(HISTOGRAM_BUCKETS) {
+                    if value <= *bound {
+                        *bucket += 1;
+                    }
+                }
+
+                *sum += value;
+                *count += 1;
+            }
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    fn render(&self) -> String {
+        let mut output = String::new();
+
+        for (name, family) in &self.families {
+            writeln!(output, "# TYPE {} {}", name, family.ty.name()).ok();
+
+            for (labels, value) in &family.series {
+                match value {
+                    SeriesValue::Value(value) => {
+                        writeln!(output, "{}{} {}", name, format_labels(labels, None), value).ok();
+                    }
+                    SeriesValue::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        for (bucket, bound) in buckets.iter().zip(HISTOGRAM_BUCKETS) {
+                            let labels = format_labels(labels, Some(&bound.to_string()));
+                            writeln!(output, "{}_bucket{} {}", name, labels, bucket).ok();
+                        }
+
+                        let inf_labels = format_labels(labels, Some("+Inf"));
+                        writeln!(output, "{}_bucket{} {}", name, inf_labels, count).ok();
+                        let labels = format_labels(labels, None);
+                        writeln!(output, "{}_sum{} {}", name, labels, sum).ok();
+                        writeln!(output, "{}_count{} {}", name, labels, count).ok();
+                    }
+                }
+            }
+        }
+
+        output
+    }
+}
+
+/// Replaces all characters that are not allowed in Prometheus metric or label names.
+///
+/// Metric names may additionally contain colons. Dots are replaced with underscores.
+fn sanitize_name(name: &str, metric: bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+            ':' if metric => c,
+            _ => '_',
+        })
+        .collect();
+
+    if sanitized
+        .chars()
+        .next()
+        .map_or(true, |c| c.is_ascii_digit())
+    {
+        sanitized.insert(0, '_');
+    }
+
+    sanitized
+}
 
+/// Formats labels of a series, including the `le` label of histogram buckets.
+fn format_labels(labels: &[(String, String)], le: Option<&str>) -> String {
+    let le = le.map(|le| ("le", le));
+    let pairs: Vec<_> = labels
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .chain(le)
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_registry() {
+        let mut registry = Registry::default();
+        registry.record("symbolicator.requests:1|c");
+        registry.record("symbolicator.requests:2|c");
+        registry.record("symbolicator.debug_status:1|c|#status:found");
+        registry.record("symbolicator.debug_status:1|c|#status:missing");
+        registry.record("symbolicator.pending:7|g");
+        registry.record("symbolicator.pending:3|g");
+        registry.record("symbolicator.duration:5|ms|#type:\"quoted\"");
+        registry.record("symbolicator.duration:2000|ms|#type:\"quoted\"");
+        registry.record("malformed");
+
+        let output = registry.render();
+        let expected = r#"# TYPE symbolicator_debug_status_total counter
+symbolicator_debug_status_total{status="found"} 1
+symbolicator_debug_status_total{status="missing"} 1
+# TYPE symbolicator_duration histogram
+symbolicator_duration_bucket{type="\"quoted\"",le="1"} 0
+symbolicator_duration_bucket{type="\"quoted\"",le="4"} 0
+symbolicator_duration_bucket{type="\"quoted\"",le="16"} 1
+symbolicator_duration_bucket{type="\"quoted\"",le="64"} 1
+symbolicator_duration_bucket{type="\"quoted\"",le="256"} 1
+symbolicator_duration_bucket{type="\"quoted\"",le="1024"} 1
+symbolicator_duration_bucket{type="\"quoted\"",le="4096"} 2
+"#;
+        assert!(output.starts_with(expected), "{}", output);
+        assert!(output
+            .contains("symbolicator_duration_bucket{type=\"\\\"quoted\\\"\",le=\"+Inf\"} 2\n"));
+        assert!(output.contains("symbolicator_duration_sum{type=\"\\\"quoted\\\"\"} 2005\n"));
+        assert!(output.contains("# TYPE symbolicator_pending gauge\nsymbolicator_pending 3\n"));
+        assert!(output.contains(
+            "# TYPE symbolicator_requests_total counter\nsymbolicator_requests_total 3\n"
+        ));
+    }
 }
@@ -0,0 +1,210 @@
+//! Symbolicates a single crash report from the command line, without starting the HTTP server.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use actix_rt::System;
+use actix_web::web::Bytes;
+use failure::{Fail, ResultExt};
+use futures::{future, Future};
+
+use crate::config::Config;
+use crate::endpoints::symbolicate::SymbolicationRequestBody;
+use crate::service::symbolication::SymbolicateStacktraces;
+use crate::service::Service;
+use crate::types::{RequestId, RequestOptions, Scope, SymbolicationResponse};
+use crate::utils::coredump::CoreFile;
+use crate::utils::ips::IpsReport;
+
+/// Variants of `SymbolicateError`.
+#[derive(Clone, Copy, Debug, Fail)]
+pub enum SymbolicateErrorKind {
+    /// Failed to read the input file.
+    #[fail(display = "failed to read the input file")]
+    Read,
+
+    /// Failed to parse the JSON symbolication request.
+    #[fail(display = "failed to parse the symbolication request")]
+    Parse,
+
+    /// The symbolication request references an unknown source.
+    #[fail(display = "failed to resolve sources of the symbolication request")]
+    Sources,
+
+    /// The symbolication request did not complete.
+    #[fail(display = "failed to symbolicate")]
+    Symbolicate,
+
+    /// Failed to write the response.
+    #[fail(display = "failed to write the response")]
+    Write,
+}
+
+symbolic::common::derive_failure!(
+    SymbolicateError,
+    SymbolicateErrorKind,
+    doc = "Error when symbolicating from the command line."
+);
+
+/// The kind of payload passed to the `symbolicate` command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum InputFormat {
+    Minidump,
+    Coredump,
+    AppleCrashReport,
+    Tombstone,
+    Stacktraces,
+}
+
+impl InputFormat {
+    /// Detects the format of the payload from its first bytes.
+    fn detect(data: &[u8]) -> Self {
+        if data.starts_with(b"MDMP") || data.starts_with(b"PMDM") {
+            return InputFormat::Minidump;
+        }
+
+        if CoreFile::test(data) {
+            return InputFormat::Coredump;
+        }
+
+        let start = data
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(data.len());
+
+        if IpsReport::test(&data[start..]) {
+            InputFormat::AppleCrashReport
+        } else if data[start..].starts_with(b"{") {
+            InputFormat::Stacktraces
+        } else if data[start..].starts_with(b"*** *** ***") {
+            InputFormat::Tombstone
+        } else {
+            InputFormat::AppleCrashReport
+        }
+    }
+}
+
+/// Starts the symbolication request for the given payload.
+fn start_request(
+    service: &Service,
+    format: InputFormat,
+    data: Vec<u8>,
+) -> Result<RequestId, SymbolicateError> {
+    let symbolication = service.symbolication();
+    let config = service.config();
+    let sources = config.default_sources();
+    let scope = Scope::Global;
+    let options = RequestOptions::default();
+
+    let request_id = match format {
+        InputFormat::Minidump => {
+            symbolication.process_minidump(scope, Bytes::from(data), (*sources).clone(), options)
+        }
+        InputFormat::Coredump => {
+            symbolication.process_coredump(scope, Bytes::from(data), (*sources).clone(), options)
+        }
+        InputFormat::AppleCrashReport => symbolication.process_apple_crash_report(
+            scope,
+            Bytes::from(data),
+            (*sources).clone(),
+            options,
+        ),
+        InputFormat::Tombstone => {
+            symbolication.process_tombstone(scope, Bytes::from(data), (*sources).clone(), options)
+        }
+        InputFormat::Stacktraces => {
+            let body: SymbolicationRequestBody =
+                serde_json::from_slice(&data).context(SymbolicateErrorKind::Parse)?;
+            let sources = config
+                .resolve_sources(body.sources)
+                .context(SymbolicateErrorKind::Sources)?;
+
+            symbolication.symbolicate_stacktraces(SymbolicateStacktraces {
+                signal: body.signal,
+                sources,
+                stacktraces: body.stacktraces,
+                modules: body.modules.into_iter().map(From::from).collect(),
+                scope,
+                options,
+            })
+        }
+    };
+
+    Ok(request_id)
+}
+
+/// Symbolicates the crash report or JSON payload at `input` and prints the result to stdout.
+pub fn symbolicate(config: Config, input: &Path) -> Result<(), SymbolicateError> {
+    let data = fs::read(input).context(SymbolicateErrorKind::Read)?;
+    let format = InputFormat::detect(&data);
+    log::info!("Symbolicating {} as {:?}", input.display(), format);
+
+    let mut sys = System::new("symbolicator");
+    let service = Service::create(config);
+
+    // Start the request and wait for its response on the runtime of the actix system.
+    let response = sys.block_on(future::lazy(move || {
+        let symbolication = service.symbolication();
+        future::result(start_request(&service, format, data)).and_then(move |request_id| {
+            symbolication
+                .get_response(request_id, None)
+                .map_err(|error| error.context(SymbolicateErrorKind::Symbolicate).into())
+        })
+    }))?;
+
+    let response = match response {
+        Some(SymbolicationResponse::Completed(response)) => response,
+        Some(SymbolicationResponse::Failed { message }) => {
+            let error = failure::err_msg(message);
+            return Err(error.context(SymbolicateErrorKind::Symbolicate).into());
+        }
+        _ => return Err(SymbolicateErrorKind::Symbolicate.into()),
+    };
+
+    let stdout = io::stdout();
+    serde_json::to_writer_pretty(stdout.lock(), &response).context(SymbolicateErrorKind::Write)?;
+    println!();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_minidump() {
+        let data = b"MDMP\x93\xa7\x00\x00";
+        assert_eq!(InputFormat::detect(data), InputFormat::Minidump);
+    }
+
+    #[test]
+    fn test_detect_coredump() {
+        let data = b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x04\x00\x3e\x00";
+        assert_eq!(InputFormat::detect(data), InputFormat::Coredump);
+    }
+
+    #[test]
+    fn test_detect_stacktraces() {
+        let data = b"\n  {\"stacktraces\": []}";
+        assert_eq!(InputFormat::detect(data), InputFormat::Stacktraces);
+    }
+
+    #[test]
+    fn test_detect_tombstone() {
+        let data = b"*** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***\n";
+        assert_eq!(InputFormat::detect(data), InputFormat::Tombstone);
+    }
+
+    #[test]
+    fn test_detect_ips_report() {
+        let data = b"{\"bug_type\":\"309\",\"os_version\":\"iPhone OS 15.0 (19A346)\"}\n{}";
+        assert_eq!(InputFormat::detect(data), InputFormat::AppleCrashReport);
+    }
+
+    #[test]
+    fn test_detect_apple_crash_report() {
+        let data = b"Incident Identifier: 5C32DF84-31A0-43E7-87D0-239F7F594940";
+        assert_eq!(InputFormat::detect(data), InputFormat::AppleCrashReport);
+    }
+}
@@ -1,12 +1,19 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use actix_rt::System;
 use actix_service::NewService;
-use actix_web::dev::{Body, ServiceRequest, ServiceResponse};
+use actix_web::dev::{Body, Server, ServiceRequest, ServiceResponse};
 use actix_web::{App, Error, HttpServer};
 use failure::{Fail, ResultExt};
+use futures::Future;
 use sentry::integrations::failure::capture_fail;
 
+use crate::cache;
 use crate::config::Config;
 use crate::endpoints;
+use crate::logging::{self, LogError};
 use crate::metrics;
 use crate::middleware;
 use crate::service::Service;
@@ -58,11 +65,127 @@ pub(crate) fn create_app(
         .configure(endpoints::configure)
 }
 
+/// Reloads the configuration file and applies it to the running service.
+///
+/// If the new configuration cannot be loaded, the error is logged and the previous configuration
+/// remains active. Requests that are already running continue with the previous configuration.
+pub fn reload_config(path: Option<&Path>, service: &Service) {
+    match Config::get(path) {
+        Ok(config) => {
+            logging::reload_logging(&config);
+            service.reload_config(config);
+            metric!(counter("server.config_reloaded") += 1);
+            log::info!("Reloaded configuration");
+        }
+        Err(e) => {
+            metric!(counter("server.config_reload_failed") += 1);
+            log::error!("Failed to reload configuration: {}", LogError(&e));
+        }
+    }
+}
+
+/// Spawns a background thread that reloads the configuration on `SIGHUP`.
+#[cfg(unix)]
+fn spawn_reload_handler(path: Option<PathBuf>, service: Service) {
+    let signals = match signal_hook::iterator::Signals::new(&[signal_hook::SIGHUP]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("Failed to register SIGHUP handler: {}", LogError(&e));
+            return;
+        }
+    };
+
+    let result = thread::Builder::new()
+        .name("symbolicator-reload".into())
+        .spawn(move || {
+            for _ in signals.forever() {
+                log::info!("Received SIGHUP, reloading configuration");
+                reload_config(path.as_ref().map(PathBuf::as_path), &service);
+            }
+        });
+
+    if let Err(e) = result {
+        log::error!("Failed to spawn config reload thread: {}", LogError(&e));
+    }
+}
+
+/// Configuration reloading via signals is only supported on Unix.
+#[cfg(not(unix))]
+fn spawn_reload_handler(_path: Option<PathBuf>, _service: Service) {}
+
+/// Waits for running symbolication requests and stops the server.
+///
+/// New symbolication requests are rejected, while polls are still served until all running
+/// requests have finished or `shutdown_timeout` has elapsed. Another signal stops waiting
+/// immediately.
+#[cfg(unix)]
+fn shutdown(server: &Server, service: &Service, signals: &signal_hook::iterator::Signals) {
+    let symbolication = service.symbolication();
+    symbolication.shut_down();
+    metric!(counter("server.shutting_down") += 1);
+
+    let deadline = Instant::now() + service.config().shutdown_timeout;
+    loop {
+        let running = symbolication.running_requests();
+        if running == 0 {
+            log::info!("All running requests finished");
+            break;
+        }
+
+        if Instant::now() >= deadline || signals.pending().next().is_some() {
+            log::warn!("Shutting down with {} running requests", running);
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    server.stop(true).wait().ok();
+}
+
+/// Spawns a background thread that shuts down the server gracefully on `SIGTERM` or `SIGINT`.
+#[cfg(unix)]
+fn spawn_shutdown_handler(server: Server, service: Service) {
+    let signals =
+        match signal_hook::iterator::Signals::new(&[signal_hook::SIGTERM, signal_hook::SIGINT]) {
+            Ok(signals) => signals,
+            Err(e) => {
+                log::error!("Failed to register shutdown handler: {}", LogError(&e));
+                return;
+            }
+        };
+
+    let result = thread::Builder::new()
+        .name("symbolicator-shutdown".into())
+        .spawn(move || {
+            if signals.forever().next().is_some() {
+                log::info!("Received shutdown signal, waiting for running requests");
+                shutdown(&server, &service, &signals);
+            }
+        });
+
+    if let Err(e) = result {
+        log::error!("Failed to spawn shutdown thread: {}", LogError(&e));
+    }
+}
+
+/// Graceful shutdown is only supported on Unix. Elsewhere, the server stops immediately.
+#[cfg(not(unix))]
+fn spawn_shutdown_handler(_server: Server, _service: Service) {}
+
 /// Starts all actors and HTTP server based on loaded config.
-pub fn run(config: Config) -> Result<(), ServerError> {
+///
+/// The configuration at `config_path` is reloaded when the process receives `SIGHUP`. On `SIGTERM`
+/// or `SIGINT`, the server waits for running symbolication requests before it stops.
+pub fn run(config: Config, config_path: Option<&Path>) -> Result<(), ServerError> {
     let sys = System::new("symbolicator");
 
-    if let Some(ref statsd) = config.metrics.statsd {
+    if config.metrics.prometheus {
+        if config.metrics.statsd.is_some() {
+            log::warn!("Metrics are exposed for Prometheus, ignoring the statsd server");
+        }
+        metrics::configure_prometheus(&config.metrics.prefix);
+    } else if let Some(ref statsd) = config.metrics.statsd {
         metrics::configure_statsd(&config.metrics.prefix, statsd);
     }
     metric!(counter("server.starting") += 1);
@@ -70,13 +193,29 @@ pub fn run(config: Config) -> Result<(), ServerError> {
     let bind = config.bind.clone();
     let service = Service::create(config);
 
-    HttpServer::new(move || create_app(service.clone()))
+    cache::spawn_periodic_cleanup(&service.config(), service.caches());
+    spawn_reload_handler(config_path.map(Path::to_owned), service.clone());
+
+    let mut server = HttpServer::new(clone!(service, || create_app(service.clone())));
+    if cfg!(unix) {
+        server = server.disable_signals();
+    }
+
+    let server = server
+        .system_exit()
         .bind(&bind)
         .context(ServerErrorKind::Bind)?
         .start();
 
     log::info!("Started http server: {}", bind);
+    spawn_shutdown_handler(server, service.clone());
 
     sys.run().context(ServerErrorKind::Run)?;
+
+    // Downloads and computations that did not complete leave temporary files behind. Caches that
+    // are still being written to by computations past the shutdown timeout are skipped.
+    service
+        .caches()
+        .remove_temp_files(cache::TEMP_FILE_GRACE_PERIOD);
     Ok(())
 }
@@ -3,9 +3,10 @@ use std::fmt;
 use std::fs;
 use std::io;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
 
-use futures::future::{self, Future, Shared};
+use futures::future::{self, Either, Future, Shared};
 use futures::sync::oneshot;
 use parking_lot::Mutex;
 use sentry::Hub;
@@ -21,7 +22,22 @@ use crate::utils::sentry::SentryFutureExt;
 // newtype around it.
 type ComputationChannel<T, E> = Shared<oneshot::Receiver<Result<Arc<T>, Arc<E>>>>;
 
-type ComputationMap<T, E> = Arc<Mutex<BTreeMap<CacheKey, ComputationChannel<T, E>>>>;
+type ComputationMap<T, E> = Arc<Mutex<BTreeMap<CacheKey, Computation<T, E>>>>;
+
+/// Source of unique identifiers for computations.
+static NEXT_COMPUTATION_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A running computation of a cache item.
+///
+/// Every future waiting for the computation holds a strong reference to the `cancel` token. Once
+/// all of them have been dropped, the token aborts the computation, which drops its in-flight
+/// downloads and conversions.
+#[derive(Debug)]
+struct Computation<T, E> {
+    id: usize,
+    channel: ComputationChannel<T, E>,
+    cancel: Weak<CallOnDrop>,
+}
 
 /// Manages a filesystem cache of any kind of data that can be serialized into bytes and read from
 /// it:
@@ -151,6 +167,9 @@ impl<T: CacheItemRequest> Cacher<T> {
         // just got pruned.
         metric!(counter(&format!("caches.{}.file.miss", name)) += 1);
 
+        // Temporary files are not removed on shutdown while this guard is alive.
+        let write_guard = self.config.begin_write();
+
         let temp_file = if let Some(ref path) = cache_path {
             let dir = path.parent().unwrap();
             tryf!(fs::create_dir_all(dir));
@@ -190,23 +209,55 @@ impl<T: CacheItemRequest> Cacher<T> {
             Ok(item)
         });
 
-        Box::new(future)
+        Box::new(future.then(move |result| {
+            drop(write_guard);
+            result
+        }))
     }
 
     /// Creates a shareable channel that computes an item.
-    fn create_channel(&self, request: T, key: CacheKey) -> ComputationChannel<T::Item, T::Error> {
+    ///
+    /// The computation is aborted when the returned cancel token is dropped.
+    fn create_channel(
+        &self,
+        request: T,
+        key: CacheKey,
+    ) -> (Computation<T::Item, T::Error>, Arc<CallOnDrop>) {
         let (sender, receiver) = oneshot::channel();
+        let (cancel_sender, cancel_receiver) = oneshot::channel::<()>();
+        let id = NEXT_COMPUTATION_ID.fetch_add(1, Ordering::Relaxed);
 
         let slf = self.clone();
+        let name = self.config.name();
         let current_computations = self.current_computations.clone();
         let remove_computation_token = CallOnDrop::new(clone!(key, || {
-            current_computations.lock().remove(&key);
+            // The entry may have been replaced by a new computation after this one was canceled.
+            let mut current_computations = current_computations.lock();
+            if current_computations.get(&key).map(|c| c.id) == Some(id) {
+                current_computations.remove(&key);
+            }
+        }));
+
+        let cancel = Arc::new(CallOnDrop::new(move || {
+            cancel_sender.send(()).ok();
         }));
 
         // Run the computation and wrap the result in Arcs to make them clonable.
         let channel = future::lazy(move || slf.compute(request, key))
+            .select2(cancel_receiver)
             .then(move |result| {
                 drop(remove_computation_token);
+                let result = match result {
+                    Ok(Either::A((item, _))) => Ok(item),
+                    Err(Either::A((error, _))) => Err(error),
+                    Ok(Either::B(_)) | Err(Either::B(_)) => {
+                        // Nobody is waiting for the result anymore. Dropping the sender notifies
+                        // requests that raced with the cancellation.
+                        metric!(counter(&format!("caches.{}.channel.canceled", name)) += 1);
+                        return Ok(());
+                    }
+                };
+
                 sender.send(result.map(Arc::new).map_err(Arc::new)).ok();
                 Ok(())
             })
@@ -214,40 +265,135 @@ impl<T: CacheItemRequest> Cacher<T> {
 
         self.threadpool.spawn(channel);
 
-        receiver.shared()
+        let computation = Computation {
+            id,
+            channel: receiver.shared(),
+            cancel: Arc::downgrade(&cancel),
+        };
+
+        (computation, cancel)
     }
 
     /// Computes an item by loading from or populating the cache.
     ///
     /// The actual computation is deduplicated between concurrent requests. Finally, the result is
     /// inserted into the cache and all subsequent calls fetch from the cache.
+    ///
+    /// If all futures returned for the same item are dropped before the computation finishes, it is
+    /// aborted.
     pub fn compute_memoized(&self, request: T) -> SendFuture<Arc<T::Item>, Arc<T::Error>> {
         let key = request.get_cache_key();
         let name = self.config.name();
 
-        let channel = {
+        let (channel, cancel) = {
             let mut current_computations = self.current_computations.lock();
-            if let Some(channel) = current_computations.get(&key) {
+            let running = current_computations
+                .get(&key)
+                .and_then(|c| Some((c.channel.clone(), c.cancel.upgrade()?)));
+
+            if let Some(running) = running {
                 // A concurrent cache lookup was deduplicated.
                 metric!(counter(&format!("caches.{}.channel.hit", name)) += 1);
-                channel.clone()
+                running
             } else {
                 // A concurrent cache lookup is considered new. This does not imply a cache miss.
+                // This also replaces computations that are being canceled.
                 metric!(counter(&format!("caches.{}.channel.miss", name)) += 1);
-                let channel = self.create_channel(request, key.clone());
-                let evicted = current_computations.insert(key.clone(), channel.clone());
-                debug_assert!(evicted.is_none());
-                channel
+                let (computation, cancel) = self.create_channel(request, key.clone());
+                let channel = computation.channel.clone();
+                current_computations.insert(key.clone(), computation);
+                (channel, cancel)
             }
         };
 
+        // Keep the computation alive for as long as this future has not been dropped.
         let future = channel
             .map_err(move |_cancelled_error| {
                 let message = format!("{} computation channel dropped", name);
                 Arc::new(io::Error::new(io::ErrorKind::Interrupted, message).into())
             })
-            .and_then(|shared| (*shared).clone());
+            .and_then(move |shared| {
+                drop(cancel);
+                (*shared).clone()
+            });
 
         Box::new(future)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    use tokio::timer::Delay;
+
+    use crate::config::CacheConfig;
+    use crate::test;
+
+    /// A request that takes a while to compute and counts its computations.
+    #[derive(Clone, Debug)]
+    struct SlowRequest {
+        computations: Arc<AtomicUsize>,
+    }
+
+    impl CacheItemRequest for SlowRequest {
+        type Item = CacheStatus;
+        type Error = io::Error;
+
+        fn get_cache_key(&self) -> CacheKey {
+            CacheKey {
+                cache_key: "slow".to_owned(),
+                scope: Scope::Global,
+            }
+        }
+
+        fn compute(&self, path: &Path) -> SendFuture<CacheStatus, Self::Error> {
+            self.computations.fetch_add(1, Ordering::SeqCst);
+
+            let path = path.to_owned();
+            let future = Delay::new(Instant::now() + Duration::from_millis(50))
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+                .and_then(move |()| {
+                    fs::write(path, "contents")?;
+                    Ok(CacheStatus::Positive)
+                });
+
+            Box::new(future)
+        }
+
+        fn load(&self, _scope: Scope, status: CacheStatus, _data: ByteView<'static>) -> Self::Item {
+            status
+        }
+    }
+
+    #[test]
+    fn test_cancel_shared_computation() {
+        test::setup();
+
+        let cache_dir = test::tempdir();
+        let cache = Cache::new(
+            "test",
+            Some(cache_dir.path()),
+            CacheConfig::default_downloaded(),
+        );
+        let cacher = Cacher::new(cache, ThreadPool::new());
+        let request = SlowRequest {
+            computations: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let result = test::block_fn(|| {
+            let canceled = cacher.compute_memoized(request.clone());
+            let waiting = cacher.compute_memoized(request.clone());
+
+            // The second request still holds the cancel token, so the computation continues.
+            drop(canceled);
+            waiting
+        });
+
+        assert_eq!(*result.unwrap(), CacheStatus::Positive);
+        assert_eq!(request.computations.load(Ordering::SeqCst), 1);
+        assert!(cacher.current_computations.lock().is_empty());
+    }
+}
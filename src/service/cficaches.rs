@@ -15,7 +15,7 @@ use crate::service::cache::{CacheItemRequest, Cacher};
 use crate::service::objects::{
     FindObject, ObjectFile, ObjectFileMeta, ObjectPurpose, ObjectsActor,
 };
-use crate::types::{FileType, ObjectId, ObjectType, Scope, SourceConfig};
+use crate::types::{FileType, ObjectCandidate, ObjectId, ObjectType, Scope, SourceConfig};
 use crate::utils::futures::{FutureExt, SendFuture, ThreadPool};
 use crate::utils::sentry::ToSentryScope;
 
@@ -71,6 +71,7 @@ pub struct CfiCacheFile {
     scope: Scope,
     data: ByteView<'static>,
     status: CacheStatus,
+    candidates: Vec<ObjectCandidate>,
 }
 
 impl CfiCacheFile {
@@ -78,11 +79,17 @@ impl CfiCacheFile {
         match self.status {
             CacheStatus::Negative => Ok(None),
             CacheStatus::Malformed => Err(CfiCacheErrorKind::ObjectParsing.into()),
+            CacheStatus::DownloadError => Err(CfiCacheErrorKind::Fetching.into()),
             CacheStatus::Positive => Ok(Some(
                 CfiCache::from_bytes(self.data.clone()).context(CfiCacheErrorKind::Parsing)?,
             )),
         }
     }
+
+    /// Returns all locations that were searched for the object file of this cficache.
+    pub fn candidates(&self) -> &[ObjectCandidate] {
+        &self.candidates
+    }
 }
 
 fn compute_cficache(
@@ -157,6 +164,7 @@ impl CacheItemRequest for FetchCfiCacheInternal {
             scope,
             data,
             status,
+            candidates: Vec::new(),
         }
     }
 }
@@ -193,24 +201,34 @@ impl CfiCacheActor {
         let identifier = request.identifier.clone();
         let scope = request.scope.clone();
 
-        let future = object.and_then(move |object| {
-            object
-                .map(move |object| {
-                    Either::A(cficaches.compute_memoized(FetchCfiCacheInternal {
-                        request,
-                        objects_actor: objects,
-                        object_meta: object,
-                    }))
-                })
-                .unwrap_or_else(move || {
-                    Either::B(future::ok(Arc::new(CfiCacheFile {
-                        object_type,
-                        identifier,
-                        scope,
-                        data: ByteView::from_slice(b""),
-                        status: CacheStatus::Negative,
-                    })))
-                })
+        let future = object.and_then(move |found| {
+            let candidates = found.candidates;
+            match found.meta {
+                Some(object) => Either::A(
+                    cficaches
+                        .compute_memoized(FetchCfiCacheInternal {
+                            request,
+                            objects_actor: objects,
+                            object_meta: object,
+                        })
+                        .map(move |cficache| {
+                            // The cached cficache is shared, so attach the candidates of this
+                            // lookup to a copy.
+                            Arc::new(CfiCacheFile {
+                                candidates,
+                                ..(*cficache).clone()
+                            })
+                        }),
+                ),
+                None => Either::B(future::ok(Arc::new(CfiCacheFile {
+                    object_type,
+                    identifier,
+                    scope,
+                    data: ByteView::from_slice(b""),
+                    status: CacheStatus::Negative,
+                    candidates,
+                }))),
+            }
         });
 
         Box::new(future)
@@ -2,13 +2,16 @@ use std::fmt;
 use std::fs::File;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use failure::{Fail, ResultExt};
 use futures::{Future, IntoFuture, Stream};
 use serde::Deserialize;
 use tempfile::NamedTempFile;
+use tokio_retry::strategy::jitter;
+use tokio_retry::{Action, Retry};
 
-use crate::types::{DirectoryLayout, FileType, ObjectId, SourceFilters};
+use crate::types::{DirectoryLayout, DownloadConfig, FileType, ObjectId, SourceFilters};
 use crate::utils::futures::ResultFuture;
 use crate::utils::paths::get_directory_path;
 
@@ -24,13 +27,19 @@ impl fmt::Display for DisplayError {
     }
 }
 
-#[derive(Debug, Fail, Clone, Copy)]
+#[derive(Debug, Fail, Clone, Copy, Eq, PartialEq)]
 pub enum DownloadErrorKind {
     #[fail(display = "failed to download")]
     Io,
 
     #[fail(display = "download canceled due to shutdown")]
     Canceled,
+
+    #[fail(display = "download timed out")]
+    Timeout,
+
+    #[fail(display = "source is temporarily unavailable")]
+    Unavailable,
 }
 
 symbolic::common::derive_failure!(
@@ -68,31 +77,74 @@ impl From<io::Error> for DownloadError {
     }
 }
 
+/// Runs the action and retries it according to the source's retry policy.
+///
+/// Between retries, the action waits for `retry_backoff` milliseconds, doubling with every retry.
+/// If all retries fail, the error of the last attempt is returned.
+pub fn retry<A>(config: &DownloadConfig, action: A) -> impl Future<Item = A::Item, Error = A::Error>
+where
+    A: Action,
+{
+    let backoff = config.retry_backoff;
+    let strategy = (0..config.retries as u32)
+        .map(move |retry| Duration::from_millis(backoff.saturating_mul(1 << retry.min(16))))
+        .map(jitter);
+
+    Retry::spawn(strategy, action).map_err(|e| match e {
+        tokio_retry::Error::OperationError(e) => e,
+        tokio_retry::Error::TimerError(_) => unreachable!(),
+    })
+}
+
 /// A relative path of an object file in a source.
 #[derive(Debug, Clone, Eq, Hash, PartialEq, Deserialize)]
-pub struct DownloadPath(String);
+#[serde(from = "String")]
+pub struct DownloadPath {
+    path: String,
+    filetype: Option<FileType>,
+}
+
+impl DownloadPath {
+    /// Creates a path at which a file of the given type is expected.
+    pub fn with_filetype(path: String, filetype: FileType) -> Self {
+        DownloadPath {
+            path,
+            filetype: Some(filetype),
+        }
+    }
+
+    /// The file type that was requested at this path, if it is known.
+    ///
+    /// This is `None` for sources that list files without a layout, such as Sentry.
+    pub fn filetype(&self) -> Option<FileType> {
+        self.filetype
+    }
+}
 
 impl From<String> for DownloadPath {
     fn from(path: String) -> Self {
-        Self(path)
+        DownloadPath {
+            path,
+            filetype: None,
+        }
     }
 }
 
 impl fmt::Display for DownloadPath {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.fmt(f)
+        self.path.fmt(f)
     }
 }
 
 impl AsRef<str> for DownloadPath {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.path
     }
 }
 
 impl AsRef<Path> for DownloadPath {
     fn as_ref(&self) -> &Path {
-        self.0.as_ref()
+        self.path.as_ref()
     }
 }
 
@@ -100,7 +152,33 @@ impl std::ops::Deref for DownloadPath {
     type Target = str;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.path
+    }
+}
+
+/// The outcome of a download from a source.
+pub struct Download {
+    /// The downloaded file, or `None` if it does not exist in the source.
+    pub file: Option<DownloadedFile>,
+    /// The HTTP status code of the response, if the source was accessed via HTTP.
+    pub status_code: Option<u16>,
+}
+
+impl Download {
+    /// A file that was found in the source.
+    pub fn found(file: DownloadedFile, status_code: Option<u16>) -> Self {
+        Download {
+            file: Some(file),
+            status_code,
+        }
+    }
+
+    /// A file that does not exist in the source.
+    pub fn not_found(status_code: Option<u16>) -> Self {
+        Download {
+            file: None,
+            status_code,
+        }
     }
 }
 
@@ -168,7 +246,7 @@ pub struct DownloadPathIter<'a> {
     filetypes: std::slice::Iter<'a, FileType>,
     filters: &'a SourceFilters,
     object_id: &'a ObjectId,
-    layout: DirectoryLayout,
+    layout: &'a DirectoryLayout,
     next: Option<DownloadPath>,
 }
 
@@ -196,10 +274,10 @@ impl Iterator for DownloadPathIter<'_> {
                 compressed_path.push('_');
 
                 debug_assert!(self.next.is_none());
-                self.next = Some(DownloadPath(compressed_path));
+                self.next = Some(DownloadPath::with_filetype(compressed_path, filetype));
             }
 
-            return Some(DownloadPath(path));
+            return Some(DownloadPath::with_filetype(path, filetype));
         }
 
         self.next.take()
@@ -216,7 +294,7 @@ pub fn prepare_download_paths<'a>(
     object_id: &'a ObjectId,
     filetypes: &'a [FileType],
     filters: &'a SourceFilters,
-    layout: DirectoryLayout,
+    layout: &'a DirectoryLayout,
 ) -> DownloadPathIter<'a> {
     DownloadPathIter {
         filetypes: filetypes.iter(),
@@ -236,7 +314,7 @@ pub trait ObjectDownloader {
     type ListResponse: IntoFuture<Item = Vec<DownloadPath>, Error = DownloadError>;
 
     /// The type returned from the `download_files` operation.
-    type DownloadResponse: IntoFuture<Item = Option<DownloadedFile>, Error = DownloadError>;
+    type DownloadResponse: IntoFuture<Item = Download, Error = DownloadError>;
 
     /// Lists files that can be downloaded from the given source.
     fn list_files(
@@ -254,3 +332,42 @@ pub trait ObjectDownloader {
         temp_dir: PathBuf,
     ) -> Self::DownloadResponse;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::test;
+
+    fn count_attempts(retries: usize) -> usize {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let config = DownloadConfig {
+            retries,
+            retry_backoff: 1,
+            ..Default::default()
+        };
+
+        let result = test::block_fn(clone!(attempts, || {
+            retry(&config, move || {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(DownloadError::io("connection reset"))
+            })
+        }));
+
+        assert!(result.is_err());
+        attempts.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_retry() {
+        assert_eq!(count_attempts(2), 3);
+    }
+
+    #[test]
+    fn test_retry_disabled() {
+        assert_eq!(count_attempts(0), 1);
+    }
+}
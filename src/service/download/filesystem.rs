@@ -4,8 +4,8 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::service::download::common::{
-    prepare_download_paths, DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile,
-    ObjectDownloader,
+    prepare_download_paths, Download, DownloadError, DownloadErrorKind, DownloadPath,
+    DownloadedFile, ObjectDownloader,
 };
 use crate::types::{FileType, FilesystemSourceConfig, ObjectId};
 
@@ -20,7 +20,7 @@ impl FilesystemDownloader {
 impl ObjectDownloader for FilesystemDownloader {
     type Config = Arc<FilesystemSourceConfig>;
     type ListResponse = Result<Vec<DownloadPath>, DownloadError>;
-    type DownloadResponse = Result<Option<DownloadedFile>, DownloadError>;
+    type DownloadResponse = Result<Download, DownloadError>;
 
     fn list_files(
         &self,
@@ -32,7 +32,7 @@ impl ObjectDownloader for FilesystemDownloader {
             object_id,
             filetypes,
             &source.files.filters,
-            source.files.layout,
+            &source.files.layout,
         );
 
         Ok(paths.collect())
@@ -48,9 +48,11 @@ impl ObjectDownloader for FilesystemDownloader {
         log::debug!("Fetching debug file from {:?}", download_abspath);
 
         match File::open(download_abspath.clone()) {
-            Ok(_) => DownloadedFile::local(download_abspath).map(Some),
+            Ok(_) => {
+                DownloadedFile::local(download_abspath).map(|file| Download::found(file, None))
+            }
             Err(e) => match e.kind() {
-                io::ErrorKind::NotFound => Ok(None),
+                io::ErrorKind::NotFound => Ok(Download::not_found(None)),
                 _ => Err(DownloadError::from(DownloadErrorKind::Io)),
             },
         }
@@ -1,3 +1,5 @@
+use std::env;
+use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -7,18 +9,31 @@ use failure::ResultExt;
 use futures::{future, future::Either, Future, Stream};
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use tokio_retry::strategy::{jitter, ExponentialBackoff};
-use tokio_retry::Retry;
 use url::percent_encoding::{percent_encode, PATH_SEGMENT_ENCODE_SET};
 
 use crate::service::download::common::{
-    prepare_download_paths, DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile,
-    ObjectDownloader,
+    prepare_download_paths, retry, Download, DownloadError, DownloadErrorKind, DownloadPath,
+    DownloadedFile, ObjectDownloader,
 };
-use crate::types::{FileType, GcsSourceConfig, GcsSourceKey, ObjectId};
+use crate::types::{FileType, GcsCredentialsProvider, GcsSourceConfig, GcsSourceKey, ObjectId};
 use crate::utils::futures::{RemoteThread, ResultFuture, SendFuture};
 use crate::utils::http;
 
+/// Endpoint for exchanging JWTs and refresh tokens for access tokens.
+const TOKEN_URL: &str = "https://www.googleapis.com/oauth2/v4/token";
+
+/// Default base URL of the GCE metadata server.
+const METADATA_URL: &str = "http://metadata.google.internal";
+
+/// Path of the access token of the default service account on the metadata server.
+const METADATA_TOKEN_PATH: &str = "/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Lifetime of self-signed JWTs, which is also the lifetime of the resulting access token.
+const JWT_LIFETIME: i64 = 3600;
+
+/// Time before their expiry at which access tokens are refreshed.
+const TOKEN_EXPIRY_MARGIN: i64 = 120;
+
 #[derive(Serialize)]
 struct JwtClaims {
     #[serde(rename = "iss")]
@@ -38,9 +53,46 @@ struct OAuth2Grant {
     assertion: String,
 }
 
+#[derive(Serialize)]
+struct RefreshTokenGrant {
+    grant_type: String,
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+}
+
 #[derive(Deserialize)]
 struct GcsTokenResponse {
     access_token: String,
+    #[serde(default)]
+    expires_in: Option<i64>,
+}
+
+/// Contents of an application default credentials file.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ApplicationCredentials {
+    /// A service account key, as created in the cloud console.
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+    },
+    /// User credentials, as created by `gcloud auth application-default login`.
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    },
+}
+
+/// A request for a GCS access token.
+enum TokenRequest {
+    /// Exchanges a self-signed JWT of a service account.
+    Jwt(String),
+    /// Exchanges the refresh token of an authorized user.
+    RefreshToken(RefreshTokenGrant),
+    /// Fetches a token from the metadata server at the given URL.
+    Metadata(String),
 }
 
 #[derive(Debug)]
@@ -64,26 +116,102 @@ fn key_from_string(mut s: &str) -> Result<Vec<u8>, DownloadError> {
     Ok(base64::decode(bytes).context(DownloadErrorKind::Io)?)
 }
 
-fn get_auth_jwt(source_key: &GcsSourceKey, expiration: i64) -> Result<String, DownloadError> {
+fn get_auth_jwt(
+    client_email: &str,
+    private_key: &str,
+    expiration: i64,
+) -> Result<String, DownloadError> {
     let header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
 
     let jwt_claims = JwtClaims {
-        issuer: source_key.client_email.clone(),
+        issuer: client_email.to_owned(),
         scope: "https://www.googleapis.com/auth/devstorage.read_only".into(),
         audience: "https://www.googleapis.com/oauth2/v4/token".into(),
         expiration,
         issued_at: Utc::now().timestamp(),
     };
 
-    let key = key_from_string(&source_key.private_key)?;
+    let key = key_from_string(private_key)?;
     let pkcs8 = jsonwebtoken::Key::Pkcs8(&key);
 
     Ok(jsonwebtoken::encode(&header, &jwt_claims, pkcs8).context(DownloadErrorKind::Io)?)
 }
 
-fn request_token(auth_jwt: String) -> ResultFuture<Bytes, DownloadError> {
-    let future = http::default_client()
-        .post("https://www.googleapis.com/oauth2/v4/token")
+/// Returns the path to the application default credentials file.
+fn application_credentials_path(source_key: &GcsSourceKey) -> Result<PathBuf, DownloadError> {
+    if let Some(ref path) = source_key.credentials_file {
+        return Ok(path.clone());
+    }
+
+    if let Some(path) = env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+        return Ok(path.into());
+    }
+
+    match env::var_os("HOME") {
+        Some(home) => {
+            Ok(PathBuf::from(home).join(".config/gcloud/application_default_credentials.json"))
+        }
+        None => Err(DownloadError::io("missing application default credentials")),
+    }
+}
+
+/// Creates a token request for a service account key.
+fn service_account_request(
+    client_email: &str,
+    private_key: &str,
+) -> Result<TokenRequest, DownloadError> {
+    let expiration = Utc::now().timestamp() + JWT_LIFETIME;
+    let auth_jwt = get_auth_jwt(client_email, private_key, expiration)?;
+    Ok(TokenRequest::Jwt(auth_jwt))
+}
+
+/// Creates the token request for the credentials provider configured in the key.
+fn token_request(source_key: &GcsSourceKey) -> Result<TokenRequest, DownloadError> {
+    match source_key.gcs_credentials_provider {
+        GcsCredentialsProvider::ServiceAccount => {
+            service_account_request(&source_key.client_email, &source_key.private_key)
+        }
+        GcsCredentialsProvider::ApplicationDefault => {
+            let path = application_credentials_path(source_key)?;
+            let data = fs::read(&path).context(DownloadErrorKind::Io)?;
+            let credentials: ApplicationCredentials =
+                serde_json::from_slice(&data).context(DownloadErrorKind::Io)?;
+
+            match credentials {
+                ApplicationCredentials::ServiceAccount {
+                    client_email,
+                    private_key,
+                } => service_account_request(&client_email, &private_key),
+                ApplicationCredentials::AuthorizedUser {
+                    client_id,
+                    client_secret,
+                    refresh_token,
+                } => Ok(TokenRequest::RefreshToken(RefreshTokenGrant {
+                    grant_type: "refresh_token".into(),
+                    client_id,
+                    client_secret,
+                    refresh_token,
+                })),
+            }
+        }
+        GcsCredentialsProvider::Metadata => {
+            let base_url = match source_key.metadata_url {
+                Some(ref url) => url.clone(),
+                None => match env::var("GCE_METADATA_HOST") {
+                    Ok(host) => format!("http://{}", host),
+                    Err(_) => METADATA_URL.to_owned(),
+                },
+            };
+
+            let url = format!("{}{}", base_url.trim_end_matches('/'), METADATA_TOKEN_PATH);
+            Ok(TokenRequest::Metadata(url))
+        }
+    }
+}
+
+fn request_jwt_token(auth_jwt: String) -> ResultFuture<Bytes, DownloadError> {
+    let future = http::default_client(TOKEN_URL)
+        .post(TOKEN_URL)
         // for some inexplicable reason we otherwise get gzipped data back that actix-web client has
         // no idea what to do with.
         .header(header::ACCEPT_ENCODING, "identity")
@@ -100,14 +228,55 @@ fn request_token(auth_jwt: String) -> ResultFuture<Bytes, DownloadError> {
     Box::new(future)
 }
 
+fn request_refresh_token(grant: RefreshTokenGrant) -> ResultFuture<Bytes, DownloadError> {
+    let future = http::default_client(TOKEN_URL)
+        .post(TOKEN_URL)
+        .header(header::ACCEPT_ENCODING, "identity")
+        .send_form(&grant)
+        .map_err(|err| {
+            log::debug!("Failed to refresh GCS user credentials: {}", err);
+            DownloadError::io(err)
+        })
+        .and_then(move |mut response| response.body().map_err(DownloadError::io));
+
+    Box::new(future)
+}
+
+fn request_metadata_token(url: String) -> ResultFuture<Bytes, DownloadError> {
+    // The metadata server is a link-local address, which the default client refuses to connect to.
+    // It is never reachable through a proxy.
+    let future = http::direct_client()
+        .get(url)
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .map_err(|err| {
+            log::debug!("Failed to fetch GCS token from metadata server: {}", err);
+            DownloadError::io(err)
+        })
+        .and_then(move |mut response| response.body().map_err(DownloadError::io));
+
+    Box::new(future)
+}
+
+fn request_token(request: TokenRequest) -> ResultFuture<Bytes, DownloadError> {
+    match request {
+        TokenRequest::Jwt(auth_jwt) => request_jwt_token(auth_jwt),
+        TokenRequest::RefreshToken(grant) => request_refresh_token(grant),
+        TokenRequest::Metadata(url) => request_metadata_token(url),
+    }
+}
+
 fn download(
     source: Arc<GcsSourceConfig>,
     token: Arc<GcsToken>,
     url: String,
     key: String,
     temp_dir: PathBuf,
-) -> ResultFuture<Option<DownloadedFile>, DownloadError> {
-    let future = http::default_client()
+) -> ResultFuture<Download, DownloadError> {
+    let proxy = source.files.proxy.as_ref();
+    let connect_timeout = source.files.download.connect_timeout;
+    let connect_timeout = connect_timeout.map(std::time::Duration::from_secs);
+    let future = http::source_client(&url, proxy, None, connect_timeout)
         .get(&url)
         .header(
             header::AUTHORIZATION,
@@ -117,10 +286,18 @@ fn download(
         .map_err(DownloadError::io)
         .then(clone!(temp_dir, |result| match result {
             Ok(mut response) => {
+                let status_code = Some(response.status().as_u16());
                 if response.status().is_success() {
                     log::trace!("Success hitting GCS {} (from {})", &key, source.bucket);
                     let stream = response.take_payload().map_err(DownloadError::io);
-                    Either::A(DownloadedFile::streaming(&temp_dir, stream).map(Some))
+                    Either::A(
+                        DownloadedFile::streaming(&temp_dir, stream)
+                            .map(move |file| Download::found(file, status_code)),
+                    )
+                } else if response.status().is_server_error() {
+                    let message = format!("GCS returned status code {}", response.status());
+                    log::debug!("{} for {} (from {})", message, &key, source.bucket);
+                    Either::B(future::err(DownloadError::io(message)))
                 } else {
                     log::trace!(
                         "Unexpected status code from GCS {} (from {}): {}",
@@ -128,18 +305,18 @@ fn download(
                         source.bucket,
                         response.status()
                     );
-                    Either::B(future::ok(None))
+                    Either::B(future::ok(Download::not_found(status_code)))
                 }
             }
             Err(e) => {
-                log::trace!(
-                    "Skipping response from GCS {} (from {}): {} ({:?})",
+                log::debug!(
+                    "Failed to fetch GCS {} (from {}): {} ({:?})",
                     &key,
                     source.bucket,
                     &e,
                     &e
                 );
-                Either::B(future::ok(None))
+                Either::B(future::err(e))
             }
         }));
 
@@ -156,22 +333,26 @@ struct GcsDownloaderHandle {
 
 impl GcsDownloaderHandle {
     fn request_token(&self, source_key: &GcsSourceKey) -> SendFuture<GcsToken, DownloadError> {
-        let expires_at = Utc::now() + Duration::minutes(58);
-        let auth_jwt = match get_auth_jwt(source_key, expires_at.timestamp() + 30) {
-            Ok(auth_jwt) => auth_jwt,
+        let requested_at = Utc::now();
+        let request = match token_request(source_key) {
+            Ok(request) => request,
             Err(err) => return Box::new(future::err(err)),
         };
 
         let response = self
             .thread
-            .spawn(move || request_token(auth_jwt))
+            .spawn(move || request_token(request))
             .map_err(|e| e.map_canceled(|| DownloadErrorKind::Canceled))
             .and_then(move |data| {
                 serde_json::from_slice::<GcsTokenResponse>(&data)
                     .map_err(DownloadError::io)
-                    .map(move |token| GcsToken {
-                        access_token: token.access_token,
-                        expires_at,
+                    .map(move |token| {
+                        let expires_in = token.expires_in.unwrap_or(JWT_LIFETIME);
+                        GcsToken {
+                            access_token: token.access_token,
+                            expires_at: requested_at
+                                + Duration::seconds(expires_in - TOKEN_EXPIRY_MARGIN),
+                        }
                     })
             });
 
@@ -183,7 +364,7 @@ impl GcsDownloaderHandle {
         source_key: &Arc<GcsSourceKey>,
     ) -> SendFuture<Arc<GcsToken>, DownloadError> {
         if let Some(token) = self.tokens.lock().get(source_key) {
-            if token.expires_at < Utc::now() {
+            if token.expires_at > Utc::now() {
                 return Box::new(future::ok(token.clone()));
             }
         }
@@ -218,7 +399,7 @@ impl GcsDownloader {
 impl ObjectDownloader for GcsDownloader {
     type Config = Arc<GcsSourceConfig>;
     type ListResponse = Result<Vec<DownloadPath>, DownloadError>;
-    type DownloadResponse = SendFuture<Option<DownloadedFile>, DownloadError>;
+    type DownloadResponse = SendFuture<Download, DownloadError>;
 
     fn list_files(
         &self,
@@ -230,7 +411,7 @@ impl ObjectDownloader for GcsDownloader {
             object_id,
             filetypes,
             &source.files.filters,
-            source.files.layout,
+            &source.files.layout,
         );
 
         Ok(paths.collect())
@@ -254,6 +435,7 @@ impl ObjectDownloader for GcsDownloader {
         log::debug!("Fetching from GCS: {} (from {})", &key, source.bucket);
 
         let handle = self.handle.clone();
+        let config = source.files.download.clone();
         let try_response = move || {
             let source = source.clone();
             let key = key.clone();
@@ -275,14 +457,58 @@ impl ObjectDownloader for GcsDownloader {
                 }))
         };
 
-        let response = Retry::spawn(
-            ExponentialBackoff::from_millis(10).map(jitter).take(3),
-            try_response,
-        );
+        Box::new(retry(&config, try_response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::{web, App, HttpRequest, HttpResponse};
+
+    use crate::test;
+
+    fn metadata_token(request: HttpRequest) -> HttpResponse {
+        match request.headers().get("Metadata-Flavor") {
+            Some(flavor) if flavor == "Google" => HttpResponse::Ok().json(serde_json::json!({
+                "access_token": "ya29.token",
+                "expires_in": 3599,
+                "token_type": "Bearer",
+            })),
+            _ => HttpResponse::Forbidden().finish(),
+        }
+    }
+
+    #[test]
+    fn test_metadata_token() {
+        test::setup();
+
+        let server = actix_http_test::TestServer::new(|| {
+            actix_http::HttpService::new(
+                App::new().route(METADATA_TOKEN_PATH, web::get().to(metadata_token)),
+            )
+        });
+
+        let handle = GcsDownloaderHandle {
+            thread: RemoteThread::new(),
+            tokens: Arc::new(Mutex::new(TokenCache::new(100))),
+        };
+
+        let source_key = Arc::new(GcsSourceKey {
+            gcs_credentials_provider: GcsCredentialsProvider::Metadata,
+            private_key: String::new(),
+            client_email: String::new(),
+            credentials_file: None,
+            metadata_url: Some(server.url("/")),
+        });
+
+        let token = test::block_fn(|| handle.get_token(&source_key)).unwrap();
+        assert_eq!(token.access_token, "ya29.token");
+        assert!(token.expires_at > Utc::now());
 
-        Box::new(response.map_err(|e| match e {
-            tokio_retry::Error::OperationError(e) => e,
-            tokio_retry::Error::TimerError(_) => unreachable!(),
-        }))
+        // The second request is served from the token cache.
+        let cached = test::block_fn(|| handle.get_token(&source_key)).unwrap();
+        assert!(Arc::ptr_eq(&token, &cached));
     }
 }
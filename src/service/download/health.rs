@@ -0,0 +1,236 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::service::download::common::{DownloadError, DownloadErrorKind};
+use crate::types::SourceConfig;
+
+/// The health of a source as reported by the sources health endpoint.
+#[derive(Clone, Debug, Serialize)]
+pub struct SourceHealth {
+    /// Unique source identifier.
+    pub id: String,
+    /// Whether requests are sent to the source. `false` while the circuit is open.
+    pub available: bool,
+    /// The number of failed requests since the last successful request.
+    pub consecutive_failures: usize,
+    /// Seconds until requests are sent to the source again, if the circuit is open.
+    pub retry_after: Option<u64>,
+    /// The error of the last failed request.
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    consecutive_failures: usize,
+    open_until: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl HealthState {
+    fn retry_after(&self, now: Instant) -> Option<Duration> {
+        match self.open_until {
+            Some(open_until) if open_until > now => Some(open_until - now),
+            _ => None,
+        }
+    }
+}
+
+type HealthCache = lru::LruCache<SourceConfig, HealthState>;
+
+/// Tracks the health of sources and opens a circuit for sources that fail repeatedly.
+///
+/// Once a source has failed `max_consecutive_failures` times in a row, no requests are sent to it
+/// for `failure_cooldown` seconds. After that, requests are sent again. The circuit closes with the
+/// next successful request, or opens again if it fails.
+///
+/// Sources with the same identifier but a different configuration are tracked separately, so that
+/// inline sources cannot open the circuit of a configured source.
+#[derive(Debug)]
+pub struct HealthTracker {
+    sources: Mutex<HealthCache>,
+}
+
+impl HealthTracker {
+    pub fn new() -> Self {
+        HealthTracker {
+            sources: Mutex::new(HealthCache::new(1000)),
+        }
+    }
+
+    /// Returns an `Unavailable` error if the circuit of the source is open.
+    pub fn check(&self, source: &SourceConfig) -> Result<(), DownloadError> {
+        let mut sources = self.sources.lock();
+        let state = match sources.get(source) {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        match state.retry_after(Instant::now()) {
+            Some(_) => Err(DownloadErrorKind::Unavailable.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Records the outcome of a request to the source.
+    ///
+    /// Canceled requests and requests that were rejected by an open circuit are not recorded.
+    pub fn record<T>(&self, source: &SourceConfig, result: &Result<T, DownloadError>) {
+        let error = match result {
+            Ok(_) => {
+                let mut sources = self.sources.lock();
+                if let Some(state) = sources.get_mut(source) {
+                    if state.consecutive_failures > 0 {
+                        log::info!("Source {} recovered", source.id());
+                    }
+                    state.consecutive_failures = 0;
+                    state.open_until = None;
+                }
+                return;
+            }
+            Err(error) => error,
+        };
+
+        match error.kind() {
+            DownloadErrorKind::Canceled | DownloadErrorKind::Unavailable => return,
+            _ => (),
+        }
+
+        let config = source.download_config();
+        let mut sources = self.sources.lock();
+        if !sources.contains(source) {
+            sources.put(source.clone(), HealthState::default());
+        }
+
+        let state = sources.get_mut(source).unwrap();
+        state.consecutive_failures += 1;
+        state.last_error = Some(error_details(error));
+
+        let threshold = config.max_consecutive_failures;
+        if threshold > 0 && state.consecutive_failures >= threshold {
+            if state.open_until.is_none() {
+                log::warn!("Source {} failed repeatedly, skipping it", source.id());
+                metric!(counter("downloads.circuit_opened") += 1, "source" => source.id());
+            }
+
+            let cooldown = Duration::from_secs(config.failure_cooldown);
+            state.open_until = Some(Instant::now() + cooldown);
+        }
+    }
+
+    /// Returns the health of all sources that have been requested recently.
+    pub fn sources(&self) -> Vec<SourceHealth> {
+        let now = Instant::now();
+        let sources = self.sources.lock();
+
+        let mut health: Vec<_> = sources
+            .iter()
+            .map(|(key, state)| {
+                let retry_after = state.retry_after(now);
+                SourceHealth {
+                    id: key.id.clone(),
+                    available: retry_after.is_none(),
+                    consecutive_failures: state.consecutive_failures,
+                    retry_after: retry_after.map(|d| d.as_secs() + 1),
+                    last_error: state.last_error.clone(),
+                }
+            })
+            .collect();
+
+        health.sort_by(|a, b| a.id.cmp(&b.id));
+        health
+    }
+}
+
+/// Renders the error and all of its causes.
+fn error_details(error: &DownloadError) -> String {
+    let causes: Vec<_> = failure::Fail::iter_chain(error)
+        .map(|cause| cause.to_string())
+        .collect();
+
+    causes.join(": ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::types::{CommonSourceConfig, DownloadConfig, FilesystemSourceConfig};
+
+    fn source(max_consecutive_failures: usize) -> SourceConfig {
+        SourceConfig::Filesystem(Arc::new(FilesystemSourceConfig {
+            id: "flaky".to_owned(),
+            path: "/".into(),
+            files: CommonSourceConfig {
+                download: DownloadConfig {
+                    max_consecutive_failures,
+                    failure_cooldown: 60,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        }))
+    }
+
+    fn failure() -> Result<(), DownloadError> {
+        Err(DownloadError::io("connection reset"))
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let tracker = HealthTracker::new();
+        let source = source(2);
+
+        tracker.record(&source, &failure());
+        assert!(tracker.check(&source).is_ok());
+
+        tracker.record(&source, &failure());
+        assert!(tracker.check(&source).is_err());
+
+        let health = tracker.sources();
+        assert_eq!(health.len(), 1);
+        assert!(!health[0].available);
+        assert_eq!(health[0].consecutive_failures, 2);
+        assert!(health[0].retry_after.is_some());
+
+        tracker.record(&source, &Ok(()));
+        assert!(tracker.check(&source).is_ok());
+        assert_eq!(tracker.sources()[0].consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_circuit_breaker_disabled() {
+        let tracker = HealthTracker::new();
+        let source = source(0);
+
+        for _ in 0..10 {
+            tracker.record(&source, &failure());
+        }
+
+        assert!(tracker.check(&source).is_ok());
+    }
+
+    #[test]
+    fn test_circuit_breaker_inline_source() {
+        let tracker = HealthTracker::new();
+        let configured = source(2);
+        let inline = SourceConfig::Filesystem(Arc::new(FilesystemSourceConfig {
+            id: "flaky".to_owned(),
+            path: "/inline".into(),
+            files: CommonSourceConfig {
+                download: DownloadConfig {
+                    max_consecutive_failures: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        }));
+
+        tracker.record(&inline, &failure());
+        assert!(tracker.check(&inline).is_err());
+        assert!(tracker.check(&configured).is_ok());
+    }
+}
@@ -1,17 +1,18 @@
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use actix_web::{http::header, HttpMessage};
 use futures::{future, future::Either, Future, Stream};
-use tokio_retry::strategy::{jitter, ExponentialBackoff};
-use tokio_retry::Retry;
+use openssl::ssl::SslConnector;
+use parking_lot::Mutex;
 use url::Url;
 
 use crate::service::download::common::{
-    prepare_download_paths, DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile,
-    ObjectDownloader, USER_AGENT,
+    prepare_download_paths, retry, Download, DownloadError, DownloadErrorKind, DownloadPath,
+    DownloadedFile, ObjectDownloader, USER_AGENT,
 };
-use crate::types::{FileType, HttpSourceConfig, ObjectId};
+use crate::types::{FileType, HttpAuth, HttpSourceConfig, HttpTlsConfig, ObjectId};
 use crate::utils::futures::{RemoteThread, ResultFuture, SendFuture};
 use crate::utils::http;
 
@@ -19,15 +20,25 @@ const MAX_HTTP_REDIRECTS: usize = 10;
 
 fn download(
     source: Arc<HttpSourceConfig>,
+    tls: Option<SslConnector>,
     download_url: Url,
     temp_dir: PathBuf,
-) -> ResultFuture<Option<DownloadedFile>, DownloadError> {
-    let try_download = clone!(download_url, source, || {
+) -> ResultFuture<Download, DownloadError> {
+    let connect_timeout = source
+        .files
+        .download
+        .connect_timeout
+        .map(Duration::from_secs);
+
+    let try_download = clone!(download_url, source, tls, || {
         http::follow_redirects(
             download_url.clone(),
             MAX_HTTP_REDIRECTS,
-            clone!(source, |url| {
-                let mut request = http::default_client().get(url);
+            source.redirects,
+            clone!(source, tls, |url| {
+                let proxy = source.files.proxy.as_ref();
+                let mut request =
+                    http::source_client(url, proxy, tls.clone(), connect_timeout).get(url);
 
                 for (key, value) in &source.headers {
                     if let Ok(header) = header::HeaderName::from_bytes(key.as_bytes()) {
@@ -35,55 +46,94 @@ fn download(
                     }
                 }
 
+                match source.auth {
+                    Some(HttpAuth::Basic {
+                        ref username,
+                        ref password,
+                    }) => {
+                        request =
+                            request.basic_auth(username, password.as_ref().map(String::as_str))
+                    }
+                    Some(HttpAuth::Bearer { ref token }) => request = request.bearer_auth(token),
+                    None => (),
+                }
+
                 request.header(header::USER_AGENT, USER_AGENT)
             }),
         )
     });
 
-    let retries = ExponentialBackoff::from_millis(10).map(jitter).take(3);
-    let response = Retry::spawn(retries, try_download)
-        .map_err(|e| match e {
-            tokio_retry::Error::OperationError(e) => e,
-            tokio_retry::Error::TimerError(_) => unreachable!(),
-        })
-        .then(move |result| match result {
-            Ok(mut response) => {
-                if response.status().is_success() {
-                    log::trace!("Success hitting {}", download_url);
-                    let stream = response.take_payload().map_err(DownloadError::io);
-                    Either::A(DownloadedFile::streaming(&temp_dir, stream).map(Some))
-                } else {
-                    log::trace!(
-                        "Unexpected status code from {}: {}",
-                        download_url,
-                        response.status()
-                    );
-                    Either::B(future::ok(None))
-                }
-            }
-            Err(e) => {
-                log::trace!("Skipping response from {}: {}", download_url, e);
-                Either::B(future::ok(None))
+    let response = retry(&source.files.download, try_download).then(move |result| match result {
+        Ok(mut response) => {
+            let status_code = Some(response.status().as_u16());
+            if response.status().is_success() {
+                log::trace!("Success hitting {}", download_url);
+                let stream = response.take_payload().map_err(DownloadError::io);
+                Either::A(
+                    DownloadedFile::streaming(&temp_dir, stream)
+                        .map(move |file| Download::found(file, status_code)),
+                )
+            } else if response.status().is_server_error() {
+                // Server errors are transient and must not be mistaken for missing files.
+                let message = format!("{} returned status {}", download_url, response.status());
+                log::debug!("{}", message);
+                Either::B(future::err(DownloadError::io(message)))
+            } else {
+                log::trace!(
+                    "Unexpected status code from {}: {}",
+                    download_url,
+                    response.status()
+                );
+                Either::B(future::ok(Download::not_found(status_code)))
             }
-        });
+        }
+        Err(e) => {
+            log::debug!("Failed to fetch {}: {}", download_url, e);
+            Either::B(future::err(DownloadError::io(e)))
+        }
+    });
 
     Box::new(response)
 }
 
+type TlsCache = lru::LruCache<Arc<HttpTlsConfig>, SslConnector>;
+
 pub struct HttpDownloader {
     thread: RemoteThread,
+    tls_connectors: Arc<Mutex<TlsCache>>,
 }
 
 impl HttpDownloader {
     pub fn new(thread: RemoteThread) -> Self {
-        Self { thread }
+        Self {
+            thread,
+            tls_connectors: Arc::new(Mutex::new(TlsCache::new(100))),
+        }
+    }
+
+    fn get_tls_connector(
+        &self,
+        config: &Arc<HttpTlsConfig>,
+    ) -> Result<SslConnector, DownloadError> {
+        let mut container = self.tls_connectors.lock();
+        if let Some(connector) = container.get(config) {
+            return Ok(connector.clone());
+        }
+
+        let connector = http::tls_connector(config).map_err(|e| {
+            log::debug!("Invalid TLS configuration: {}", e);
+            DownloadError::io(e)
+        })?;
+
+        container.put(config.clone(), connector.clone());
+        Ok(connector)
     }
 }
 
 impl ObjectDownloader for HttpDownloader {
     type Config = Arc<HttpSourceConfig>;
     type ListResponse = Result<Vec<DownloadPath>, DownloadError>;
-    type DownloadResponse = SendFuture<Option<DownloadedFile>, DownloadError>;
+    type DownloadResponse = SendFuture<Download, DownloadError>;
 
     fn list_files(
         &self,
@@ -95,7 +145,7 @@ impl ObjectDownloader for HttpDownloader {
             object_id,
             filetypes,
             &source.files.filters,
-            source.files.layout,
+            &source.files.layout,
         );
 
         Ok(paths.collect())
@@ -110,14 +160,22 @@ impl ObjectDownloader for HttpDownloader {
         // XXX: Probably should send an error if the URL turns out to be invalid
         let download_url = match source.url.join(&download_path) {
             Ok(x) => x,
-            Err(_) => return Box::new(future::ok(None)),
+            Err(_) => return Box::new(future::ok(Download::not_found(None))),
+        };
+
+        let tls = match source.tls {
+            Some(ref config) => match self.get_tls_connector(config) {
+                Ok(connector) => Some(connector),
+                Err(e) => return Box::new(future::err(e)),
+            },
+            None => None,
         };
 
         log::debug!("Fetching debug file from {}", download_url);
 
         let future = self
             .thread
-            .spawn(move || download(source, download_url, temp_dir))
+            .spawn(move || download(source, tls, download_url, temp_dir))
             .map_err(|e| e.map_canceled(|| DownloadErrorKind::Canceled));
 
         Box::new(future)
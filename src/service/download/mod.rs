@@ -1,22 +1,34 @@
 use std::fmt;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use futures::future;
+use futures::{future, Future};
+use parking_lot::Mutex;
 
 use crate::types::{FileType, ObjectId, SourceConfig};
-use crate::utils::futures::{RemoteThread, SendFuture};
+use crate::utils::futures::{ConcurrencyLimit, FutureExt, RemoteThread, SendFuture};
 
 mod common;
 mod filesystem;
 mod gcs;
+mod health;
 mod http;
 mod s3;
 mod sentry;
 
 use self::common::ObjectDownloader;
-pub use self::common::{DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile};
+pub use self::common::{Download, DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile};
+use self::health::HealthTracker;
+pub use self::health::SourceHealth;
 
-pub struct Downloader {
+/// Concurrency limits of sources, keyed by their entire configuration.
+///
+/// Inline sources in requests may use the identifier of a configured source. Since their
+/// configurations differ, they do not share the concurrency limit of that source.
+type LimitCache = lru::LruCache<SourceConfig, ConcurrencyLimit>;
+
+struct Downloaders {
     fs: self::filesystem::FilesystemDownloader,
     gcs: self::gcs::GcsDownloader,
     http: self::http::HttpDownloader,
@@ -24,20 +36,8 @@ pub struct Downloader {
     sentry: self::sentry::SentryDownloader,
 }
 
-impl Downloader {
-    pub fn new() -> Self {
-        let thread = RemoteThread::new();
-
-        Self {
-            fs: self::filesystem::FilesystemDownloader::new(),
-            gcs: self::gcs::GcsDownloader::new(thread.clone()),
-            http: self::http::HttpDownloader::new(thread.clone()),
-            s3: self::s3::S3Downloader::new(thread.clone()),
-            sentry: self::sentry::SentryDownloader::new(thread),
-        }
-    }
-
-    pub fn list_files(
+impl Downloaders {
+    fn list_files(
         &self,
         source: SourceConfig,
         filetypes: &[FileType],
@@ -67,12 +67,12 @@ impl Downloader {
         }
     }
 
-    pub fn download(
+    fn download(
         &self,
         source: SourceConfig,
         download_path: DownloadPath,
         temp_dir: PathBuf,
-    ) -> SendFuture<Option<DownloadedFile>, DownloadError> {
+    ) -> SendFuture<Download, DownloadError> {
         match source {
             SourceConfig::Sentry(source) => {
                 let files = self.sentry.download(source, download_path, temp_dir);
@@ -98,8 +98,269 @@ impl Downloader {
     }
 }
 
+/// Downloads files from all supported source types.
+///
+/// Requests to a source are subject to its `DownloadConfig`. Concurrency limits and health apply
+/// per source and are shared by all requests using the same identifier and configuration.
+pub struct Downloader {
+    downloaders: Arc<Downloaders>,
+    limits: Arc<Mutex<LimitCache>>,
+    health: Arc<HealthTracker>,
+}
+
+impl Downloader {
+    pub fn new() -> Self {
+        let thread = RemoteThread::new();
+
+        let downloaders = Downloaders {
+            fs: self::filesystem::FilesystemDownloader::new(),
+            gcs: self::gcs::GcsDownloader::new(thread.clone()),
+            http: self::http::HttpDownloader::new(thread.clone()),
+            s3: self::s3::S3Downloader::new(thread.clone()),
+            sentry: self::sentry::SentryDownloader::new(thread),
+        };
+
+        Self {
+            downloaders: Arc::new(downloaders),
+            limits: Arc::new(Mutex::new(LimitCache::new(1000))),
+            health: Arc::new(HealthTracker::new()),
+        }
+    }
+
+    /// Returns the health of all sources that have been requested recently.
+    pub fn source_health(&self) -> Vec<SourceHealth> {
+        self.health.sources()
+    }
+
+    /// Returns the concurrency limit of the source, if it has one.
+    ///
+    /// If the configuration of the source has changed since the last request, for instance after
+    /// reloading the configuration, it receives a new limit. Requests that are already running keep
+    /// their slots in the old limit.
+    fn concurrency_limit(&self, source: &SourceConfig) -> Option<ConcurrencyLimit> {
+        let max = source.download_config().max_concurrent_requests?;
+
+        let mut limits = self.limits.lock();
+        if let Some(limit) = limits.get(source) {
+            return Some(limit.clone());
+        }
+
+        let limit = ConcurrencyLimit::new(max);
+        limits.put(source.clone(), limit.clone());
+        Some(limit)
+    }
+
+    /// Runs a request to the source, subject to its health, concurrency limit and download timeout.
+    ///
+    /// Requests to sources that have failed repeatedly are rejected with `Unavailable`. Otherwise,
+    /// the request is started once a slot is available. The timeout includes the time spent
+    /// waiting for a slot. The outcome of the request is recorded in the source's health.
+    fn run_limited<F, T>(&self, source: SourceConfig, request: F) -> SendFuture<T, DownloadError>
+    where
+        F: FnOnce(SourceConfig) -> SendFuture<T, DownloadError> + Send + 'static,
+        T: Send + 'static,
+    {
+        if let Err(error) = self.health.check(&source) {
+            return Box::new(future::err(error));
+        }
+
+        let health = self.health.clone();
+        let timeout = source.download_config().download_timeout;
+        let limit = self.concurrency_limit(&source);
+        let request_source = source.clone();
+
+        let future: SendFuture<T, DownloadError> = match limit {
+            Some(limit) => Box::new(limit.run(future::lazy(move || request(request_source)))),
+            None => request(request_source),
+        };
+
+        let future: SendFuture<T, DownloadError> = match timeout {
+            Some(timeout) => Box::new(
+                future.timeout(Duration::from_secs(timeout), || DownloadErrorKind::Timeout),
+            ),
+            None => future,
+        };
+
+        Box::new(future.then(move |result| {
+            health.record(&source, &result);
+            result
+        }))
+    }
+
+    pub fn list_files(
+        &self,
+        source: SourceConfig,
+        filetypes: &[FileType],
+        object_id: &ObjectId,
+    ) -> SendFuture<Vec<DownloadPath>, DownloadError> {
+        // Only Sentry sources list files remotely. All other sources compute their paths locally,
+        // which does not count towards limits and health.
+        if let SourceConfig::Sentry(_) = source {
+            let downloaders = self.downloaders.clone();
+            let filetypes = filetypes.to_vec();
+            let object_id = object_id.clone();
+
+            return self.run_limited(source, move |source| {
+                downloaders.list_files(source, &filetypes, &object_id)
+            });
+        }
+
+        match self.health.check(&source) {
+            Ok(()) => self.downloaders.list_files(source, filetypes, object_id),
+            Err(error) => Box::new(future::err(error)),
+        }
+    }
+
+    pub fn download(
+        &self,
+        source: SourceConfig,
+        download_path: DownloadPath,
+        temp_dir: PathBuf,
+    ) -> SendFuture<Download, DownloadError> {
+        let downloaders = self.downloaders.clone();
+
+        self.run_limited(source, move |source| {
+            downloaders.download(source, download_path, temp_dir)
+        })
+    }
+
+    /// Requests a file from the source to check whether it can be reached.
+    ///
+    /// Unlike `download`, this neither waits for a request slot of the source nor records the
+    /// outcome in its health, so that probes cannot delay downloads or trip the source. Files
+    /// that are found are written into `temp_dir` and removed right away.
+    pub fn probe(
+        &self,
+        source: SourceConfig,
+        download_path: DownloadPath,
+        temp_dir: PathBuf,
+    ) -> SendFuture<(), DownloadError> {
+        let future = self.downloaders.download(source, download_path, temp_dir);
+        Box::new(future.map(|_| ()))
+    }
+}
+
 impl fmt::Debug for Downloader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Downloader")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test;
+    use crate::types::{CommonSourceConfig, DownloadConfig, FilesystemSourceConfig};
+
+    fn source(download: DownloadConfig) -> SourceConfig {
+        SourceConfig::Filesystem(Arc::new(FilesystemSourceConfig {
+            id: "slow".to_owned(),
+            path: "/".into(),
+            files: CommonSourceConfig {
+                download,
+                ..Default::default()
+            },
+        }))
+    }
+
+    #[test]
+    fn test_download_timeout() {
+        test::setup();
+
+        let downloader = Downloader::new();
+        let source = source(DownloadConfig {
+            download_timeout: Some(1),
+            max_concurrent_requests: Some(1),
+            ..Default::default()
+        });
+
+        // The first request never completes and holds the only slot. The second request times out
+        // while waiting for that slot.
+        let (first, second) = test::block_fn(|| {
+            let first = downloader.run_limited(source.clone(), |_| {
+                Box::new(future::empty()) as SendFuture<(), DownloadError>
+            });
+            let second = downloader.run_limited(source.clone(), |_| {
+                Box::new(future::ok(())) as SendFuture<(), DownloadError>
+            });
+
+            first.then(Ok::<_, ()>).join(second.then(Ok::<_, ()>))
+        })
+        .unwrap();
+
+        assert_eq!(first.unwrap_err().kind(), DownloadErrorKind::Timeout);
+        assert_eq!(second.unwrap_err().kind(), DownloadErrorKind::Timeout);
+    }
+
+    #[test]
+    fn test_probe_outside_limits() {
+        test::setup();
+
+        let temp_dir = test::tempdir();
+        let file_path = temp_dir.path().join("file");
+        std::fs::write(&file_path, b"not a directory").unwrap();
+
+        let downloader = Downloader::new();
+        let source = SourceConfig::Filesystem(Arc::new(FilesystemSourceConfig {
+            id: "broken".to_owned(),
+            path: file_path,
+            files: CommonSourceConfig {
+                download: DownloadConfig {
+                    max_concurrent_requests: Some(1),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        }));
+
+        // The pending request holds the only slot. The probe does not wait for it, nor is its
+        // failure recorded in the health of the source.
+        let _pending = downloader.run_limited(source.clone(), |_| {
+            Box::new(future::empty()) as SendFuture<(), DownloadError>
+        });
+
+        let result = test::block_fn(|| {
+            let path = DownloadPath::from("ready".to_owned());
+            downloader
+                .probe(source.clone(), path, temp_dir.path().to_owned())
+                .then(Ok::<_, ()>)
+        })
+        .unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), DownloadErrorKind::Io);
+        assert!(downloader.source_health().is_empty());
+    }
+
+    #[test]
+    fn test_source_config_eq() {
+        let configured = source(DownloadConfig::default());
+        let inline = SourceConfig::Filesystem(Arc::new(FilesystemSourceConfig {
+            id: "slow".to_owned(),
+            path: "/inline".into(),
+            files: Default::default(),
+        }));
+
+        assert_eq!(configured, configured.clone());
+        assert_eq!(configured, source(DownloadConfig::default()));
+        assert_ne!(configured, inline);
+    }
+
+    #[test]
+    fn test_limits_per_source_config() {
+        let downloader = Downloader::new();
+        let configured = source(DownloadConfig {
+            max_concurrent_requests: Some(1),
+            ..Default::default()
+        });
+        let inline = source(DownloadConfig {
+            max_concurrent_requests: Some(100),
+            ..Default::default()
+        });
+
+        let limit = downloader.concurrency_limit(&configured).unwrap();
+        assert_eq!(limit.max(), 1);
+        assert_eq!(downloader.concurrency_limit(&inline).unwrap().max(), 100);
+        assert_eq!(downloader.concurrency_limit(&configured).unwrap().max(), 1);
+    }
+}
@@ -1,38 +1,300 @@
+use std::env;
+use std::fs;
+use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+use actix_web::http::header;
 use bytes::BytesMut;
+use chrono::{DateTime, Utc};
 use futures::{future, future::Either, Future, Stream};
+use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
 use parking_lot::Mutex;
-use rusoto_s3::S3;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{DispatchSignedRequest, Region, RusotoError};
+use rusoto_credential::{
+    AutoRefreshingProvider, AwsCredentials, ContainerProvider, CredentialsError,
+    EnvironmentProvider, InstanceMetadataProvider, ProfileProvider, ProvideAwsCredentials,
+    StaticProvider,
+};
+use rusoto_s3::{GetObjectError, S3};
 use tokio::codec::{BytesCodec, FramedRead};
+use tokio::net::TcpStream;
+use url::Url;
 
 use crate::service::download::common::{
-    prepare_download_paths, DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile,
-    ObjectDownloader,
+    prepare_download_paths, retry, Download, DownloadError, DownloadErrorKind, DownloadPath,
+    DownloadedFile, ObjectDownloader,
+};
+use crate::types::{
+    AwsCredentialsProvider, DownloadConfig, FileType, ObjectId, ProxyConfig, S3SourceConfig,
+    S3SourceKey,
 };
-use crate::types::{FileType, ObjectId, S3SourceConfig, S3SourceKey};
 use crate::utils::futures::{RemoteThread, ResultFuture, SendFuture};
+use crate::utils::http;
+
+/// Endpoint of the global AWS Security Token Service, unless configured in the source key.
+const STS_ENDPOINT: &str = "https://sts.amazonaws.com/";
+
+/// Version of the STS query API.
+const STS_API_VERSION: &str = "2011-06-15";
+
+/// Returns the text of the first element with the given name in an XML document.
+///
+/// STS responses contain neither attributes nor nested elements of the same name, so this does not
+/// require a full XML parser.
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start_tag = format!("<{}>", name);
+    let end_tag = format!("</{}>", name);
+
+    let start = xml.find(&start_tag)? + start_tag.len();
+    let end = start + xml[start..].find(&end_tag)?;
+    Some(xml[start..end].trim())
+}
+
+/// Parses temporary credentials from an `AssumeRoleWithWebIdentity` response.
+fn parse_sts_credentials(xml: &str) -> Result<AwsCredentials, CredentialsError> {
+    let element = |name: &str| {
+        xml_element(xml, name)
+            .ok_or_else(|| CredentialsError::new(format!("missing {} in STS response", name)))
+    };
+
+    let expires_at = element("Expiration")?
+        .parse::<DateTime<Utc>>()
+        .map_err(|e| CredentialsError::new(format!("invalid expiration: {}", e)))?;
+
+    Ok(AwsCredentials::new(
+        element("AccessKeyId")?,
+        element("SecretAccessKey")?,
+        Some(element("SessionToken")?.to_owned()),
+        Some(expires_at),
+    ))
+}
+
+/// Obtains temporary credentials by assuming a role with an OIDC web identity token.
+///
+/// This is how EKS provides IAM roles to service accounts. The token file is read on every refresh,
+/// since it is rotated regularly.
+struct WebIdentityProvider {
+    thread: RemoteThread,
+    role_arn: String,
+    token_file: PathBuf,
+    sts_endpoint: Option<String>,
+}
+
+impl WebIdentityProvider {
+    fn new(thread: RemoteThread, key: &S3SourceKey) -> Result<Self, CredentialsError> {
+        let role_arn = match key.role_arn {
+            Some(ref role_arn) => role_arn.clone(),
+            None => env::var("AWS_ROLE_ARN")
+                .map_err(|_| CredentialsError::new("missing role_arn for web identity"))?,
+        };
+
+        let token_file = match key.web_identity_token_file {
+            Some(ref token_file) => token_file.clone(),
+            None => env::var_os("AWS_WEB_IDENTITY_TOKEN_FILE")
+                .map(PathBuf::from)
+                .ok_or_else(|| CredentialsError::new("missing web_identity_token_file"))?,
+        };
+
+        Ok(WebIdentityProvider {
+            thread,
+            role_arn,
+            token_file,
+            sts_endpoint: key.sts_endpoint.clone(),
+        })
+    }
+}
+
+impl ProvideAwsCredentials for WebIdentityProvider {
+    type Future = SendFuture<AwsCredentials, CredentialsError>;
+
+    fn credentials(&self) -> Self::Future {
+        let token = match fs::read_to_string(&self.token_file) {
+            Ok(token) => token,
+            Err(e) => {
+                let message = format!("failed to read web identity token: {}", e);
+                return Box::new(future::err(CredentialsError::new(message)));
+            }
+        };
+
+        let params = &[
+            ("Action", "AssumeRoleWithWebIdentity"),
+            ("Version", STS_API_VERSION),
+            ("RoleArn", self.role_arn.as_str()),
+            ("RoleSessionName", "symbolicator"),
+            ("WebIdentityToken", token.trim()),
+        ];
+
+        let endpoint = match self.sts_endpoint {
+            Some(ref endpoint) => endpoint.as_str(),
+            None => STS_ENDPOINT,
+        };
+
+        let url = match Url::parse_with_params(endpoint, params) {
+            Ok(url) => url.into_string(),
+            Err(e) => return Box::new(future::err(CredentialsError::new(e))),
+        };
+
+        // A custom endpoint comes from the configuration file and may point to an internal host.
+        let custom_endpoint = self.sts_endpoint.is_some();
+
+        let response = self
+            .thread
+            .spawn(move || {
+                let client = if custom_endpoint {
+                    http::unsafe_client(&url, None)
+                } else {
+                    http::default_client(&url)
+                };
+
+                client
+                    .get(url)
+                    .header(header::ACCEPT_ENCODING, "identity")
+                    .send()
+                    .map_err(CredentialsError::new)
+                    .and_then(|mut response| {
+                        let status = response.status();
+                        response
+                            .body()
+                            .map_err(CredentialsError::new)
+                            .and_then(move |body| {
+                                if !status.is_success() {
+                                    let message = format!("STS request failed: {}", status);
+                                    return Err(CredentialsError::new(message));
+                                }
+
+                                parse_sts_credentials(&String::from_utf8_lossy(&body))
+                            })
+                    })
+            })
+            .map_err(|e| e.map_canceled(|| CredentialsError::new("STS request canceled")));
+
+        Box::new(response)
+    }
+}
+
+/// Formats a host and port for use in a URL or `CONNECT` request.
+fn format_address(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// A connector for rusoto that routes connections through an HTTP proxy.
+///
+/// Without a proxy override, the global proxy is used. Connections to hosts that are not proxied
+/// are established directly. HTTPS connections are tunneled through the proxy, while plain HTTP
+/// requests are sent to the proxy in absolute-form. See `ProxyDispatcher` for their credentials.
+#[derive(Clone)]
+struct ProxyConnector {
+    proxy: Option<ProxyConfig>,
+    inner: HttpConnector,
+}
+
+impl Connect for ProxyConnector {
+    type Transport = TcpStream;
+    type Error = io::Error;
+    type Future = SendFuture<(TcpStream, Connected), io::Error>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let proxy = match http::proxy_for(dst.scheme(), dst.host(), self.proxy.as_ref()) {
+            Some(proxy) => proxy,
+            None => return Box::new(self.inner.connect(dst)),
+        };
+
+        let (proxy_host, proxy_port) = proxy.address();
+        let proxy_uri = format!("http://{}", format_address(proxy_host, proxy_port));
+        let proxy_dst = match proxy_uri.parse().map(Destination::try_from_uri) {
+            Ok(Ok(proxy_dst)) => proxy_dst,
+            _ => {
+                let message = format!("invalid proxy address {}", proxy_uri);
+                return Box::new(future::err(io::Error::new(io::ErrorKind::Other, message)));
+            }
+        };
+
+        // Plain HTTP requests are forwarded by the proxy, only HTTPS requires a tunnel.
+        let future = self.inner.connect(proxy_dst);
+        if dst.scheme() != "https" {
+            return Box::new(future.map(|(stream, connected)| (stream, connected.proxy(true))));
+        }
+
+        let target = format_address(dst.host(), dst.port().unwrap_or(443));
+        let authorization = proxy.authorization();
+        let future = future.and_then(move |(stream, connected)| {
+            http::tunnel(stream, target, authorization).map(|stream| (stream, connected))
+        });
+
+        Box::new(future)
+    }
+}
+
+type ProxyHttpClient = rusoto_core::HttpClient<HttpsConnector<ProxyConnector>>;
+
+/// Dispatches requests to S3 and adds proxy credentials to plain HTTP requests.
+///
+/// The proxy forwards plain HTTP requests instead of tunneling them, so it expects credentials in
+/// every request rather than in a `CONNECT` request.
+struct ProxyDispatcher {
+    proxy: Option<ProxyConfig>,
+    inner: ProxyHttpClient,
+}
+
+impl DispatchSignedRequest for ProxyDispatcher {
+    type Future = <ProxyHttpClient as DispatchSignedRequest>::Future;
+
+    fn dispatch(&self, mut request: SignedRequest, timeout: Option<Duration>) -> Self::Future {
+        if request.scheme() == "http" {
+            let url = Url::parse(&format!("http://{}", request.hostname())).ok();
+            let host = url.as_ref().and_then(Url::host_str).unwrap_or_default();
+            let authorization = http::proxy_for("http", host, self.proxy.as_ref())
+                .and_then(|proxy| proxy.authorization());
+
+            if let Some(authorization) = authorization {
+                let value = format!("Basic {}", authorization);
+                request.add_header("Proxy-Authorization", &value);
+            }
+        }
+
+        self.inner.dispatch(request, timeout)
+    }
+}
 
 fn get_object(
     client: Arc<rusoto_s3::S3Client>,
     bucket: String,
     key: String,
+    config: DownloadConfig,
     temp_dir: PathBuf,
-) -> ResultFuture<Option<DownloadedFile>, DownloadError> {
-    let future = client
-        .get_object(rusoto_s3::GetObjectRequest {
-            key: key.clone(),
-            bucket: bucket.clone(),
-            ..Default::default()
-        })
+) -> ResultFuture<Download, DownloadError> {
+    // Only retry failed connections. All other errors are returned as result of the first attempt.
+    let try_get_object = clone!(bucket, key, || {
+        client
+            .get_object(rusoto_s3::GetObjectRequest {
+                key: key.clone(),
+                bucket: bucket.clone(),
+                ..Default::default()
+            })
+            .then(|result| match result {
+                Err(RusotoError::HttpDispatch(e)) => Err(RusotoError::HttpDispatch(e)),
+                result => Ok(result),
+            })
+    });
+
+    let future = retry(&config, try_get_object)
+        .then(|result| result.and_then(|result| result))
         .then(move |result| match result {
             Ok(mut result) => {
                 let body_read = match result.body.take() {
                     Some(body) => body.into_async_read(),
                     None => {
                         log::debug!("Empty response from s3:{}{}", bucket, &key);
-                        return Either::B(future::ok(None));
+                        return Either::B(future::ok(Download::not_found(None)));
                     }
                 };
 
@@ -40,62 +302,182 @@ fn get_object(
                     .map(BytesMut::freeze)
                     .map_err(|_err| DownloadError::from(DownloadErrorKind::Io));
 
-                Either::A(DownloadedFile::streaming(&temp_dir, stream).map(Some))
+                // The client only returns a body for successful responses.
+                Either::A(
+                    DownloadedFile::streaming(&temp_dir, stream)
+                        .map(|file| Download::found(file, Some(200))),
+                )
             }
             Err(err) => {
+                // Connection failures and server errors are transient and must not be mistaken
+                // for missing files.
+                let transient = match err {
+                    RusotoError::HttpDispatch(_) => true,
+                    RusotoError::Unknown(ref response) => response.status.is_server_error(),
+                    _ => false,
+                };
+
+                if transient {
+                    log::debug!("Failed to fetch s3:{}{}: {}", bucket, &key, err);
+                    return Either::B(future::err(DownloadError::io(err)));
+                }
+
                 // For missing files, Amazon returns different status codes based on the given
                 // permissions.
                 // - To fetch existing objects, `GetObject` is required.
                 // - If `ListBucket` is premitted, a 404 is returned for missing objects.
                 // - Otherwise, a 403 ("access denied") is returned.
                 log::debug!("Skipping response from s3:{}{}: {}", bucket, &key, err);
-                Either::B(future::ok(None))
+                let status_code = match err {
+                    RusotoError::Service(GetObjectError::NoSuchKey(_)) => Some(404),
+                    RusotoError::Unknown(ref response) => Some(response.status.as_u16()),
+                    _ => None,
+                };
+                Either::B(future::ok(Download::not_found(status_code)))
             }
         });
 
     Box::new(future)
 }
 
-type ClientCache = lru::LruCache<Arc<S3SourceKey>, Arc<rusoto_s3::S3Client>>;
+type ClientKey = (Arc<S3SourceKey>, Option<ProxyConfig>, Option<u64>);
+type ClientCache = lru::LruCache<ClientKey, Arc<rusoto_s3::S3Client>>;
 
 pub struct S3Downloader {
     thread: RemoteThread,
-    http_client: Arc<rusoto_core::HttpClient>,
+    connector: HttpConnector,
     s3_clients: Arc<Mutex<ClientCache>>,
 }
 
 impl S3Downloader {
     pub fn new(thread: RemoteThread) -> Self {
+        let mut connector = HttpConnector::new(4);
+        connector.enforce_http(false);
+
         Self {
             thread,
-            http_client: Arc::new(rusoto_core::HttpClient::new().unwrap()),
+            connector,
             s3_clients: Arc::new(Mutex::new(ClientCache::new(100))),
         }
     }
 
-    fn get_client(&self, key: &Arc<S3SourceKey>) -> Arc<rusoto_s3::S3Client> {
+    fn create_client<P>(
+        &self,
+        connector: &ProxyConnector,
+        provider: P,
+        region: Region,
+    ) -> Result<Arc<rusoto_s3::S3Client>, CredentialsError>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+        P::Future: Send,
+    {
+        let tls = native_tls::TlsConnector::new().map_err(CredentialsError::new)?;
+        let dispatcher = ProxyDispatcher {
+            proxy: connector.proxy.clone(),
+            inner: rusoto_core::HttpClient::from_connector(HttpsConnector::from((
+                connector.clone(),
+                tls,
+            ))),
+        };
+
+        Ok(Arc::new(rusoto_s3::S3Client::new_with(
+            dispatcher, provider, region,
+        )))
+    }
+
+    /// Creates a client with the credentials provider configured in the key.
+    ///
+    /// Providers of temporary credentials cache them until they expire. Since clients are cached
+    /// per key and connection settings, credentials are shared by all sources with the same key
+    /// and connection settings.
+    fn new_client(
+        &self,
+        key: &S3SourceKey,
+        connector: &ProxyConnector,
+    ) -> Result<Arc<rusoto_s3::S3Client>, CredentialsError> {
+        let region = match key.end_point {
+            Some(ref endpoint) => Region::Custom {
+                name: key.region.name().to_owned(),
+                endpoint: endpoint.clone(),
+            },
+            None => key.region.clone(),
+        };
+
+        match key.aws_credentials_provider {
+            AwsCredentialsProvider::Static => {
+                if key.access_key.is_empty() || key.secret_key.is_empty() {
+                    return Err(CredentialsError::new("missing access_key or secret_key"));
+                }
+
+                let provider =
+                    StaticProvider::new_minimal(key.access_key.clone(), key.secret_key.clone());
+                self.create_client(connector, provider, region)
+            }
+            AwsCredentialsProvider::Environment => {
+                self.create_client(connector, EnvironmentProvider::default(), region)
+            }
+            AwsCredentialsProvider::Profile => {
+                let mut provider = ProfileProvider::new()?;
+                if let Some(ref credentials_file) = key.credentials_file {
+                    provider.set_file_path(credentials_file.clone());
+                }
+                if let Some(ref profile) = key.profile {
+                    provider.set_profile(profile.clone());
+                }
+                self.create_client(connector, provider, region)
+            }
+            AwsCredentialsProvider::WebIdentity => {
+                let provider = WebIdentityProvider::new(self.thread.clone(), key)?;
+                self.create_client(connector, AutoRefreshingProvider::new(provider)?, region)
+            }
+            AwsCredentialsProvider::Container => {
+                let provider = AutoRefreshingProvider::new(ContainerProvider::new())?;
+                self.create_client(connector, provider, region)
+            }
+            AwsCredentialsProvider::Instance => {
+                let provider = AutoRefreshingProvider::new(InstanceMetadataProvider::new())?;
+                self.create_client(connector, provider, region)
+            }
+        }
+    }
+
+    fn get_client(
+        &self,
+        source: &S3SourceConfig,
+    ) -> Result<Arc<rusoto_s3::S3Client>, DownloadError> {
+        let proxy = source.files.proxy.clone();
+        let connect_timeout = source.files.download.connect_timeout;
+        let client_key = (source.source_key.clone(), proxy.clone(), connect_timeout);
+
         let mut container = self.s3_clients.lock();
-        if let Some(client) = container.get(&*key) {
-            client.clone()
-        } else {
-            let s3 = Arc::new(rusoto_s3::S3Client::new_with(
-                self.http_client.clone(),
-                rusoto_credential::StaticProvider::new_minimal(
-                    key.access_key.clone(),
-                    key.secret_key.clone(),
-                ),
-                key.region.clone(),
-            ));
-            container.put(key.clone(), s3.clone());
-            s3
+        if let Some(client) = container.get(&client_key) {
+            return Ok(client.clone());
         }
+
+        let mut connector = ProxyConnector {
+            proxy,
+            inner: self.connector.clone(),
+        };
+        connector
+            .inner
+            .set_connect_timeout(connect_timeout.map(Duration::from_secs));
+
+        let client = self
+            .new_client(&source.source_key, &connector)
+            .map_err(|e| {
+                log::debug!("Failed to create S3 client: {}", e);
+                DownloadError::io(e)
+            })?;
+
+        container.put(client_key, client.clone());
+        Ok(client)
     }
 }
 
 impl ObjectDownloader for S3Downloader {
     type Config = Arc<S3SourceConfig>;
     type ListResponse = Result<Vec<DownloadPath>, DownloadError>;
-    type DownloadResponse = SendFuture<Option<DownloadedFile>, DownloadError>;
+    type DownloadResponse = SendFuture<Download, DownloadError>;
 
     fn list_files(
         &self,
@@ -107,7 +489,7 @@ impl ObjectDownloader for S3Downloader {
             object_id,
             filetypes,
             &source.files.filters,
-            source.files.layout,
+            &source.files.layout,
         );
 
         Ok(paths.collect())
@@ -131,14 +513,234 @@ impl ObjectDownloader for S3Downloader {
         log::debug!("Fetching from s3: {} (from {})", &key, source.bucket);
 
         let bucket = source.bucket.clone();
-        let source_key = &source.source_key;
-        let client = self.get_client(&source_key);
+        let client = match self.get_client(&source) {
+            Ok(client) => client,
+            Err(e) => return Box::new(future::err(e)),
+        };
 
+        let config = source.files.download.clone();
         let response = self
             .thread
-            .spawn(move || get_object(client, bucket, key, temp_dir))
+            .spawn(move || get_object(client, bucket, key, config, temp_dir))
             .map_err(|e| e.map_canceled(|| DownloadErrorKind::Canceled));
 
         Box::new(response)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net;
+    use std::thread;
+
+    use actix_web::{web, App, HttpResponse};
+
+    use crate::test;
+
+    const STS_RESPONSE: &str = r#"
+        <AssumeRoleWithWebIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+          <AssumeRoleWithWebIdentityResult>
+            <Credentials>
+              <AccessKeyId>ASIAEXAMPLE</AccessKeyId>
+              <SecretAccessKey>c2VjcmV0</SecretAccessKey>
+              <SessionToken>dG9rZW4=</SessionToken>
+              <Expiration>2100-01-01T00:00:00Z</Expiration>
+            </Credentials>
+          </AssumeRoleWithWebIdentityResult>
+        </AssumeRoleWithWebIdentityResponse>
+    "#;
+
+    fn assume_role(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+        let param = |name: &str| query.get(name).map(String::as_str);
+
+        if param("Action") == Some("AssumeRoleWithWebIdentity")
+            && param("RoleArn") == Some("arn:aws:iam::123456789012:role/symbolicator")
+            && param("WebIdentityToken") == Some("oidc-token")
+        {
+            HttpResponse::Ok()
+                .content_type("text/xml")
+                .body(STS_RESPONSE)
+        } else {
+            HttpResponse::Forbidden().finish()
+        }
+    }
+
+    fn container_credentials() -> HttpResponse {
+        HttpResponse::Ok().json(serde_json::json!({
+            "AccessKeyId": "ASIACONTAINER",
+            "SecretAccessKey": "c2VjcmV0",
+            "Token": "dG9rZW4=",
+            "Expiration": "2100-01-01T00:00:00Z",
+        }))
+    }
+
+    fn source_key(value: serde_json::Value) -> S3SourceKey {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_web_identity_provider() {
+        test::setup();
+
+        let server = actix_http_test::TestServer::new(|| {
+            actix_http::HttpService::new(App::new().route("/", web::get().to(assume_role)))
+        });
+
+        let token_dir = test::tempdir();
+        let token_file = token_dir.path().join("token");
+        fs::write(&token_file, "oidc-token\n").unwrap();
+
+        let key = source_key(serde_json::json!({
+            "region": "us-east-1",
+            "aws_credentials_provider": "web_identity",
+            "role_arn": "arn:aws:iam::123456789012:role/symbolicator",
+            "web_identity_token_file": token_file,
+            "sts_endpoint": server.url("/"),
+        }));
+
+        let provider = WebIdentityProvider::new(RemoteThread::new(), &key).unwrap();
+        let credentials = test::block_fn(|| provider.credentials()).unwrap();
+        assert_eq!(credentials.aws_access_key_id(), "ASIAEXAMPLE");
+        assert_eq!(
+            credentials.token().as_ref().map(String::as_str),
+            Some("dG9rZW4=")
+        );
+    }
+
+    #[test]
+    fn test_web_identity_provider_rejected() {
+        test::setup();
+
+        let server = actix_http_test::TestServer::new(|| {
+            actix_http::HttpService::new(App::new().route("/", web::get().to(assume_role)))
+        });
+
+        let token_dir = test::tempdir();
+        let token_file = token_dir.path().join("token");
+        fs::write(&token_file, "expired-token").unwrap();
+
+        let key = source_key(serde_json::json!({
+            "region": "us-east-1",
+            "aws_credentials_provider": "web_identity",
+            "role_arn": "arn:aws:iam::123456789012:role/symbolicator",
+            "web_identity_token_file": token_file,
+            "sts_endpoint": server.url("/"),
+        }));
+
+        let provider = WebIdentityProvider::new(RemoteThread::new(), &key).unwrap();
+        assert!(test::block_fn(|| provider.credentials()).is_err());
+    }
+
+    #[test]
+    fn test_container_provider() {
+        test::setup();
+
+        let server = actix_http_test::TestServer::new(|| {
+            actix_http::HttpService::new(
+                App::new().route("/credentials", web::get().to(container_credentials)),
+            )
+        });
+
+        // The full URI is only used if no relative URI for the ECS endpoint is set.
+        env::remove_var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI");
+        env::set_var(
+            "AWS_CONTAINER_CREDENTIALS_FULL_URI",
+            server.url("/credentials"),
+        );
+
+        let provider = ContainerProvider::new();
+        let credentials = test::block_fn(|| provider.credentials());
+        env::remove_var("AWS_CONTAINER_CREDENTIALS_FULL_URI");
+
+        let credentials = credentials.unwrap();
+        assert_eq!(credentials.aws_access_key_id(), "ASIACONTAINER");
+        assert_eq!(credentials.aws_secret_access_key(), "c2VjcmV0");
+    }
+
+    #[test]
+    fn test_proxy_forward() {
+        test::setup();
+
+        // A proxy that records the head of a single request and responds with 404.
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_address = listener.local_addr().unwrap();
+        let proxy = thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0; 1];
+                client.read_exact(&mut byte).unwrap();
+                head.push(byte[0]);
+            }
+
+            client
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(head).unwrap()
+        });
+
+        let source: S3SourceConfig = serde_json::from_value(serde_json::json!({
+            "id": "s3",
+            "bucket": "symbols",
+            "region": "us-east-1",
+            "access_key": "access",
+            "secret_key": "secret",
+            "end_point": "http://symbols.example.com",
+            "proxy": {
+                "http_proxy": format!("http://user:pass@{}", proxy_address),
+            },
+        }))
+        .unwrap();
+
+        let temp_dir = test::tempdir();
+        let downloader = S3Downloader::new(RemoteThread::new());
+        let download_path = DownloadPath::from("foo/bar".to_owned());
+        test::block_fn(|| {
+            downloader.download(Arc::new(source), download_path, temp_dir.path().to_owned())
+        })
+        .ok();
+
+        // The request is forwarded by the proxy in absolute-form, carrying the proxy credentials.
+        let head = proxy.join().unwrap().to_lowercase();
+        assert!(head.starts_with("get http://symbols.example.com/"));
+        assert!(head.contains("proxy-authorization: basic dxnlcjpwyxnz\r\n"));
+    }
+
+    #[test]
+    fn test_parse_sts_credentials() {
+        let xml = r#"
+            <AssumeRoleWithWebIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+              <AssumeRoleWithWebIdentityResult>
+                <Credentials>
+                  <AccessKeyId>ASIAEXAMPLE</AccessKeyId>
+                  <SecretAccessKey>c2VjcmV0</SecretAccessKey>
+                  <SessionToken>dG9rZW4=</SessionToken>
+                  <Expiration>2019-11-01T12:00:00Z</Expiration>
+                </Credentials>
+              </AssumeRoleWithWebIdentityResult>
+            </AssumeRoleWithWebIdentityResponse>
+        "#;
+
+        let credentials = parse_sts_credentials(xml).unwrap();
+        assert_eq!(credentials.aws_access_key_id(), "ASIAEXAMPLE");
+        assert_eq!(credentials.aws_secret_access_key(), "c2VjcmV0");
+        assert_eq!(
+            credentials.token().as_ref().map(String::as_str),
+            Some("dG9rZW4=")
+        );
+        assert_eq!(
+            credentials.expires_at().map(|t| t.timestamp()),
+            Some(1_572_609_600)
+        );
+    }
+
+    #[test]
+    fn test_parse_sts_error() {
+        let xml = "<ErrorResponse><Error><Code>AccessDenied</Code></Error></ErrorResponse>";
+        assert!(parse_sts_credentials(xml).is_err());
+    }
+}
@@ -6,14 +6,13 @@ use actix_web::{http::header, web::Bytes, HttpMessage};
 use futures::{future, future::Either, Future, Stream};
 use parking_lot::Mutex;
 use serde::Deserialize;
-use tokio_retry::strategy::{jitter, ExponentialBackoff};
-use tokio_retry::Retry;
 use url::Url;
 
 use crate::service::download::common::{
-    DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile, ObjectDownloader, USER_AGENT,
+    retry, Download, DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile,
+    ObjectDownloader, USER_AGENT,
 };
-use crate::types::{FileType, ObjectId, SentrySourceConfig};
+use crate::types::{DownloadConfig, FileType, ObjectId, SentrySourceConfig};
 use crate::utils::futures::{FutureExt, RemoteThread, ResultFuture, SendFuture};
 use crate::utils::http;
 
@@ -29,9 +28,14 @@ struct SearchQuery {
     token: String,
 }
 
-fn search(index_url: Url, token: String) -> ResultFuture<Bytes, DownloadError> {
+fn search(
+    index_url: Url,
+    token: String,
+    config: DownloadConfig,
+) -> ResultFuture<Bytes, DownloadError> {
+    let connect_timeout = config.connect_timeout.map(Duration::from_secs);
     let index_request = move || {
-        http::unsafe_client()
+        http::unsafe_client(index_url.as_str(), connect_timeout)
             .get(index_url.as_str())
             .header(header::USER_AGENT, USER_AGENT)
             .header(header::AUTHORIZATION, format!("Bearer {}", token.clone()))
@@ -49,56 +53,50 @@ fn search(index_url: Url, token: String) -> ResultFuture<Bytes, DownloadError> {
             })
     };
 
-    let retries = ExponentialBackoff::from_millis(10).map(jitter).take(3);
-    let index_request = Retry::spawn(retries, index_request).map_err(|e| match e {
-        tokio_retry::Error::OperationError(e) => e,
-        tokio_retry::Error::TimerError(_) => unreachable!(),
-    });
-
-    Box::new(index_request)
+    Box::new(retry(&config, index_request))
 }
 
 fn download(
     download_url: Arc<Url>,
     token: String,
+    config: DownloadConfig,
     temp_dir: PathBuf,
-) -> ResultFuture<Option<DownloadedFile>, DownloadError> {
+) -> ResultFuture<Download, DownloadError> {
+    let connect_timeout = config.connect_timeout.map(Duration::from_secs);
     let try_download = clone!(download_url, || {
-        http::unsafe_client()
+        http::unsafe_client(download_url.as_str(), connect_timeout)
             .get(download_url.as_str())
             .header(header::USER_AGENT, USER_AGENT)
             .header(header::AUTHORIZATION, format!("Bearer {}", token))
             .send()
     });
 
-    let response = Retry::spawn(
-        ExponentialBackoff::from_millis(10).map(jitter).take(3),
-        try_download,
-    );
-
-    let response = response.map_err(|e| match e {
-        tokio_retry::Error::OperationError(e) => e,
-        tokio_retry::Error::TimerError(_) => unreachable!(),
-    });
-
-    let response = response.then(move |result| match result {
+    let response = retry(&config, try_download).then(move |result| match result {
         Ok(mut response) => {
+            let status_code = Some(response.status().as_u16());
             if response.status().is_success() {
                 log::trace!("Success hitting {}", download_url);
                 let stream = response.take_payload().map_err(DownloadError::io);
-                Either::A(DownloadedFile::streaming(&temp_dir, stream).map(Some))
+                Either::A(
+                    DownloadedFile::streaming(&temp_dir, stream)
+                        .map(move |file| Download::found(file, status_code)),
+                )
+            } else if response.status().is_server_error() {
+                let message = format!("Sentry returned status code {}", response.status());
+                log::warn!("{}", message);
+                Either::B(future::err(DownloadError::io(message)))
             } else {
                 log::debug!(
                     "Unexpected status code from {}: {}",
                     download_url,
                     response.status()
                 );
-                Either::B(future::ok(None))
+                Either::B(future::ok(Download::not_found(status_code)))
             }
         }
         Err(e) => {
-            log::warn!("Skipping response from {}: {}", download_url, e);
-            Either::B(future::ok(None))
+            log::warn!("Failed to fetch {}: {}", download_url, e);
+            Either::B(future::err(DownloadError::io(e)))
         }
     });
 
@@ -123,6 +121,7 @@ impl SentryDownloader {
     fn perform_search(
         &self,
         query: SearchQuery,
+        config: DownloadConfig,
     ) -> SendFuture<Arc<Vec<SearchResult>>, DownloadError> {
         if let Some((created, entries)) = self.cache.lock().get(&query) {
             if created.elapsed() < Duration::from_secs(3600) {
@@ -138,7 +137,7 @@ impl SentryDownloader {
 
         let future = self
             .thread
-            .spawn(move || search(index_url, token))
+            .spawn(move || search(index_url, token, config))
             .map_err(|e| e.map_canceled(|| DownloadErrorKind::Canceled))
             .and_then(|data| {
                 serde_json::from_slice::<Vec<SearchResult>>(&data)
@@ -157,7 +156,7 @@ impl SentryDownloader {
 impl ObjectDownloader for SentryDownloader {
     type Config = Arc<SentrySourceConfig>;
     type ListResponse = SendFuture<Vec<DownloadPath>, DownloadError>;
-    type DownloadResponse = SendFuture<Option<DownloadedFile>, DownloadError>;
+    type DownloadResponse = SendFuture<Download, DownloadError>;
 
     fn list_files(
         &self,
@@ -181,10 +180,13 @@ impl ObjectDownloader for SentryDownloader {
         };
 
         let future = self
-            .perform_search(SearchQuery {
-                index_url,
-                token: source.token.clone(),
-            })
+            .perform_search(
+                SearchQuery {
+                    index_url,
+                    token: source.token.clone(),
+                },
+                source.download.clone(),
+            )
             .map(|results| results.iter().map(|res| res.id.clone()).collect());
 
         Box::new(future)
@@ -202,10 +204,11 @@ impl ObjectDownloader for SentryDownloader {
 
         log::debug!("Fetching debug file from {}", url);
         let token = source.token.clone();
+        let config = source.download.clone();
 
         let future = self
             .thread
-            .spawn(move || download(url, token, temp_dir))
+            .spawn(move || download(url, token, config, temp_dir))
             .map_err(|e| e.map_canceled(|| DownloadErrorKind::Canceled));
 
         Box::new(future)
@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use parking_lot::RwLock;
+
 use crate::cache::Caches;
 use crate::config::Config;
 use crate::utils::futures::ThreadPool;
@@ -20,9 +22,13 @@ use self::symcaches::SymCacheActor;
 
 #[derive(Clone, Debug)]
 pub struct Service {
-    config: Arc<Config>,
+    config: Arc<RwLock<Arc<Config>>>,
+    caches: Caches,
     symbolication: Arc<SymbolicationActor>,
     objects: Arc<ObjectsActor>,
+    downloader: Arc<Downloader>,
+    cache_pool: ThreadPool,
+    symbolication_pool: ThreadPool,
 }
 
 impl Service {
@@ -30,28 +36,29 @@ impl Service {
         let config = Arc::new(config);
 
         http::allow_reserved_ips(config.connect_to_reserved_ips);
+        http::set_proxy(config.proxy.clone());
 
         let caches = Caches::new(&config);
 
         let cache_pool = ThreadPool::new();
         let symbolication_pool = ThreadPool::new();
-        let downloader = Downloader::new();
+        let downloader = Arc::new(Downloader::new());
 
         let objects = Arc::new(ObjectsActor::new(
-            caches.object_meta,
-            caches.objects,
+            caches.object_meta.clone(),
+            caches.objects.clone(),
             cache_pool.clone(),
-            downloader,
+            downloader.clone(),
         ));
 
         let symcaches = Arc::new(SymCacheActor::new(
-            caches.symcaches,
+            caches.symcaches.clone(),
             objects.clone(),
             cache_pool.clone(),
         ));
 
         let cficaches = Arc::new(CfiCacheActor::new(
-            caches.cficaches,
+            caches.cficaches.clone(),
             objects.clone(),
             cache_pool.clone(),
         ));
@@ -60,18 +67,43 @@ impl Service {
             objects.clone(),
             symcaches,
             cficaches,
-            symbolication_pool,
+            symbolication_pool.clone(),
         ));
 
         Self {
             symbolication,
             objects,
-            config,
+            downloader,
+            caches,
+            cache_pool,
+            symbolication_pool,
+            config: Arc::new(RwLock::new(config)),
         }
     }
 
+    /// Returns the currently active configuration.
+    ///
+    /// Requests should hold on to the returned config for their entire lifetime, so that a
+    /// concurrent reload does not change their behavior midway.
     pub fn config(&self) -> Arc<Config> {
-        self.config.clone()
+        self.config.read().clone()
+    }
+
+    /// Returns the caches shared by all actors of this service.
+    pub fn caches(&self) -> Caches {
+        self.caches.clone()
+    }
+
+    /// Atomically replaces the active configuration.
+    ///
+    /// This applies default sources, the symstore proxy toggle and cache expiry settings. Settings
+    /// that are bound at startup, such as the bind address and cache directory, remain unchanged
+    /// until the next restart.
+    pub fn reload_config(&self, config: Config) {
+        http::allow_reserved_ips(config.connect_to_reserved_ips);
+        http::set_proxy(config.proxy.clone());
+        self.caches.reload(&config);
+        *self.config.write() = Arc::new(config);
     }
 
     pub fn symbolication(&self) -> Arc<SymbolicationActor> {
@@ -81,4 +113,16 @@ impl Service {
     pub fn objects(&self) -> Arc<ObjectsActor> {
         self.objects.clone()
     }
+
+    pub fn downloader(&self) -> Arc<Downloader> {
+        self.downloader.clone()
+    }
+
+    /// Returns all thread pools of this service along with their names.
+    pub fn thread_pools(&self) -> Vec<(&'static str, ThreadPool)> {
+        vec![
+            ("cache", self.cache_pool.clone()),
+            ("symbolication", self.symbolication_pool.clone()),
+        ]
+    }
 }
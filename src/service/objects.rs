@@ -1,5 +1,5 @@
 use std::cmp;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read, Seek, SeekFrom};
 use std::path::Path;
 use std::process;
@@ -9,16 +9,22 @@ use std::time::Duration;
 use ::sentry::integrations::failure::capture_fail;
 use ::sentry::{configure_scope, Hub};
 use failure::{Fail, ResultExt};
-use futures::{future, Future};
+use futures::{future, future::Either, Future};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use symbolic::common::ByteView;
 use symbolic::debuginfo::{Archive, Object};
 use tempfile::tempfile_in;
 
 use crate::cache::{Cache, CacheKey, CacheStatus};
+use crate::logging::LogError;
 use crate::service::cache::{CacheItemRequest, Cacher};
-use crate::service::download::{DownloadPath, DownloadedFile, Downloader};
-use crate::types::{ArcFail, FileType, ObjectId, Scope, SourceConfig};
+use crate::service::download::{
+    Download, DownloadError, DownloadErrorKind, DownloadPath, DownloadedFile, Downloader,
+};
+use crate::types::{
+    ArcFail, CandidateStatus, FileType, ObjectCandidate, ObjectId, Scope, SourceConfig,
+};
 use crate::utils::futures::{FutureExt, SendFuture, TagMap, ThreadPool};
 use crate::utils::objects;
 use crate::utils::sentry::{SentryFutureExt, ToSentryScope};
@@ -45,6 +51,9 @@ pub enum ObjectErrorKind {
 
     #[fail(display = "object download took too long")]
     Timeout,
+
+    #[fail(display = "a source is temporarily unavailable")]
+    Unavailable,
 }
 
 symbolic::common::derive_failure!(
@@ -73,6 +82,16 @@ struct FetchFileMetaRequest {
     // state for computing.
     data_cache: Arc<Cacher<FetchFileDataRequest>>,
     downloader: Arc<Downloader>,
+
+    /// Set when the file is downloaded for this request instead of being loaded from the cache.
+    download_info: Arc<Mutex<Option<DownloadInfo>>>,
+}
+
+/// Describes the download of a file for a `FetchFileMetaRequest`.
+#[derive(Clone, Copy, Debug, Default)]
+struct DownloadInfo {
+    /// The HTTP status code of the response, if the source was accessed via HTTP.
+    status_code: Option<u16>,
 }
 
 impl CacheItemRequest for FetchFileMetaRequest {
@@ -104,6 +123,7 @@ impl CacheItemRequest for FetchFileMetaRequest {
                             has_unwind_info: object.has_unwind_info(),
                             has_symbols: object.has_symbols(),
                             has_sources: object.has_sources(),
+                            file_format: Some(object.file_format().name().to_owned()),
                         };
 
                         log::trace!("Persisting object meta for {}: {:?}", cache_key, meta);
@@ -160,13 +180,27 @@ impl CacheItemRequest for FetchFileDataRequest {
         });
 
         let downloader = &self.0.downloader;
+        let download_info = self.0.download_info.clone();
         let temp_dir = tryf!(path.parent().ok_or(ObjectErrorKind::NoTempDir)).to_owned();
 
         let future = downloader
             .download(source, download_path, temp_dir.clone())
-            .map_err(|e| e.context(ObjectErrorKind::DownloadFailed).into())
-            .and_then(move |downloaded_file| {
-                handle_object(downloaded_file, &temp_dir, &path, object_id, cache_key)
+            .then(move |result| {
+                let status_code = result.as_ref().ok().and_then(|d| d.status_code);
+                *download_info.lock() = Some(DownloadInfo { status_code });
+                result
+            })
+            .then(move |result| match result {
+                Ok(download) => {
+                    handle_object(download.file, &temp_dir, &path, object_id, cache_key)
+                }
+                // Transient failures are cached briefly, so that they are not mistaken for
+                // missing files and the download is retried soon.
+                Err(ref e) if should_cache_error(e) => {
+                    log::debug!("Failed to download {}: {}", cache_key, LogError(e));
+                    Ok(CacheStatus::DownloadError)
+                }
+                Err(e) => Err(e.context(ObjectErrorKind::DownloadFailed).into()),
             })
             .timeout(Duration::from_secs(600), || ObjectErrorKind::Timeout)
             .measure_tagged("objects", TagMap::new().add("source_type", type_name))
@@ -195,6 +229,69 @@ impl CacheItemRequest for FetchFileDataRequest {
     }
 }
 
+/// The maximum size of a source file that is fetched from a source.
+const MAX_SOURCE_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// This requests a single source file at a specific path/url.
+///
+/// Source files are stored in the objects cache next to the debug files they belong to.
+#[derive(Clone, Debug)]
+struct FetchSourceFileRequest {
+    /// The scope that the file should be stored under.
+    scope: Scope,
+    source: SourceConfig,
+    path: DownloadPath,
+    downloader: Arc<Downloader>,
+}
+
+impl CacheItemRequest for FetchSourceFileRequest {
+    type Item = Option<String>;
+    type Error = ObjectError;
+
+    fn get_cache_key(&self) -> CacheKey {
+        CacheKey {
+            cache_key: self.source.cache_key(&self.path),
+            scope: self.scope.clone(),
+        }
+    }
+
+    fn compute(&self, path: &Path) -> SendFuture<CacheStatus, Self::Error> {
+        let cache_key = self.get_cache_key();
+        log::trace!("Fetching source file for {}", cache_key);
+
+        let path = path.to_owned();
+        let type_name = self.source.type_name();
+        let temp_dir = tryf!(path.parent().ok_or(ObjectErrorKind::NoTempDir)).to_owned();
+
+        let future = self
+            .downloader
+            .download(self.source.clone(), self.path.clone(), temp_dir)
+            .then(move |result| match result {
+                Ok(Download {
+                    file: Some(downloaded_file),
+                    ..
+                }) => handle_source_file(downloaded_file, &path),
+                Ok(_) => Ok(CacheStatus::Negative),
+                Err(ref e) if should_cache_error(e) => {
+                    log::debug!("Failed to download {}: {}", cache_key, LogError(e));
+                    Ok(CacheStatus::DownloadError)
+                }
+                Err(e) => Err(e.context(ObjectErrorKind::DownloadFailed).into()),
+            })
+            .timeout(Duration::from_secs(60), || ObjectErrorKind::Timeout)
+            .measure_tagged("source_files", TagMap::new().add("source_type", type_name));
+
+        Box::new(future)
+    }
+
+    fn load(&self, _scope: Scope, status: CacheStatus, data: ByteView<'static>) -> Self::Item {
+        match status {
+            CacheStatus::Positive => String::from_utf8(data.to_vec()).ok(),
+            _ => None,
+        }
+    }
+}
+
 /// Handle to local metadata file of an object. Having an instance of this type does not mean there
 /// is a downloaded object file behind it. We cache metadata separately (ObjectFileMetaInner) because
 /// every symcache lookup requires reading this metadata.
@@ -210,15 +307,29 @@ impl ObjectFileMeta {
     pub fn cache_key(&self) -> CacheKey {
         self.request.get_cache_key()
     }
+
+    /// Rates how well this file serves the given purpose. Lower is better.
+    fn score(&self, purpose: ObjectPurpose) -> u8 {
+        // Prefer object files with debug/unwind info over object files without
+        match purpose {
+            ObjectPurpose::Unwind if self.meta.has_unwind_info => 0,
+            ObjectPurpose::Debug if self.meta.has_debug_info => 0,
+            ObjectPurpose::Debug if self.meta.has_symbols => 1,
+            ObjectPurpose::Source if self.meta.has_sources => 0,
+            _ => 2,
+        }
+    }
 }
 
-#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 struct ObjectFileMetaInner {
     has_debug_info: bool,
     has_unwind_info: bool,
     has_symbols: bool,
     #[serde(default)]
     has_sources: bool,
+    #[serde(default)]
+    file_format: Option<String>,
 }
 
 /// Handle to local cache file of an object.
@@ -252,6 +363,7 @@ impl ObjectFile {
             )),
             CacheStatus::Negative => Ok(None),
             CacheStatus::Malformed => Err(ObjectErrorKind::Parsing.into()),
+            CacheStatus::DownloadError => Err(ObjectErrorKind::DownloadFailed.into()),
         }
     }
 
@@ -293,6 +405,15 @@ pub enum ObjectPurpose {
     Source,
 }
 
+/// The result of `ObjectsActor::find`.
+#[derive(Debug, Clone)]
+pub struct FoundObject {
+    /// The best matching object file, if any.
+    pub meta: Option<Arc<ObjectFileMeta>>,
+    /// All locations that were searched for the object file.
+    pub candidates: Vec<ObjectCandidate>,
+}
+
 /// Fetch a Object from external sources or internal cache.
 #[derive(Debug, Clone)]
 pub struct FindObject {
@@ -307,6 +428,7 @@ pub struct FindObject {
 pub struct ObjectsActor {
     meta_cache: Arc<Cacher<FetchFileMetaRequest>>,
     data_cache: Arc<Cacher<FetchFileDataRequest>>,
+    source_cache: Arc<Cacher<FetchSourceFileRequest>>,
     downloader: Arc<Downloader>,
 }
 
@@ -315,12 +437,13 @@ impl ObjectsActor {
         meta_cache: Cache,
         data_cache: Cache,
         cache_pool: ThreadPool,
-        downloader: Downloader,
+        downloader: Arc<Downloader>,
     ) -> Self {
         ObjectsActor {
             meta_cache: Arc::new(Cacher::new(meta_cache, cache_pool.clone())),
-            data_cache: Arc::new(Cacher::new(data_cache, cache_pool)),
-            downloader: Arc::new(downloader),
+            data_cache: Arc::new(Cacher::new(data_cache.clone(), cache_pool.clone())),
+            source_cache: Arc::new(Cacher::new(data_cache, cache_pool)),
+            downloader,
         }
     }
 
@@ -336,10 +459,7 @@ impl ObjectsActor {
         Box::new(future)
     }
 
-    pub fn find(
-        &self,
-        request: FindObject,
-    ) -> SendFuture<Option<Arc<ObjectFileMeta>>, ObjectError> {
+    pub fn find(&self, request: FindObject) -> SendFuture<FoundObject, ObjectError> {
         let FindObject {
             filetypes,
             scope,
@@ -358,7 +478,12 @@ impl ObjectsActor {
                 let source = source.clone();
                 downloader
                     .list_files(source.clone(), filetypes, &identifier)
-                    .map_err(|e| e.context(ObjectErrorKind::ListFailed).into())
+                    .then(|result| match result {
+                        Ok(download_paths) => Ok(Some(download_paths)),
+                        // Sources that are failing repeatedly are skipped for a while.
+                        Err(ref e) if e.kind() == DownloadErrorKind::Unavailable => Ok(None),
+                        Err(e) => Err(e.context(ObjectErrorKind::ListFailed).into()),
+                    })
                     .and_then(clone!(
                         meta_cache,
                         data_cache,
@@ -366,6 +491,11 @@ impl ObjectsActor {
                         identifier,
                         scope,
                         |download_paths| {
+                            let download_paths = match download_paths {
+                                Some(download_paths) => download_paths,
+                                None => return Either::B(future::ok((Vec::new(), true))),
+                            };
+
                             let fetch_futures =
                                 download_paths.into_iter().map(move |download_path| {
                                     let request = FetchFileMetaRequest {
@@ -379,6 +509,7 @@ impl ObjectsActor {
                                         object_id: identifier.clone(),
                                         data_cache: data_cache.clone(),
                                         downloader: downloader.clone(),
+                                        download_info: Default::default(),
                                     };
 
                                     meta_cache
@@ -388,11 +519,13 @@ impl ObjectsActor {
                                                 ArcFail(e).context(ObjectErrorKind::Caching),
                                             )
                                         })
-                                        .then(Ok)
+                                        .then(move |result| Ok((request, result)))
                                         .bind_hub(Hub::new_from_top(Hub::current()))
                                 });
 
-                            future::join_all(fetch_futures)
+                            Either::A(
+                                future::join_all(fetch_futures).map(|responses| (responses, false)),
+                            )
                         }
                     ))
                     .bind_hub(Hub::new_from_top(Hub::current()))
@@ -400,34 +533,160 @@ impl ObjectsActor {
             .collect::<Vec<_>>();
 
         let selected_future = future::join_all(prepare_futures).and_then(move |responses| {
-            responses
-                .into_iter()
-                .flatten()
+            let (responses, unavailable): (Vec<_>, Vec<_>) = responses.into_iter().unzip();
+            let responses: Vec<_> = responses.into_iter().flatten().collect();
+            let unavailable = unavailable.contains(&true);
+
+            let selected = responses
+                .iter()
                 .enumerate()
-                .min_by_key(|(i, response)| {
+                .min_by_key(|(i, (_, response))| {
                     // Prefer files that contain an object over unparseable files
                     let object = match response {
                         Ok(object) => object,
                         _ => return (3, *i),
                     };
 
-                    // Prefer object files with debug/unwind info over object files without
-                    let score = match purpose {
-                        ObjectPurpose::Unwind if object.meta.has_unwind_info => 0,
-                        ObjectPurpose::Debug if object.meta.has_debug_info => 0,
-                        ObjectPurpose::Debug if object.meta.has_symbols => 1,
-                        ObjectPurpose::Source if object.meta.has_sources => 0,
-                        _ => 2,
-                    };
+                    (object.score(purpose), *i)
+                })
+                .map(|(i, _)| i);
+
+            // Failed downloads may be transient, so they must not be reported as missing files.
+            // If no object was found, prefer the failure instead.
+            let selected = selected.map(|i| match responses[i].1 {
+                Ok(ref object) if object.status != CacheStatus::Positive => responses
+                    .iter()
+                    .position(|(_, response)| match response {
+                        Ok(object) => object.status == CacheStatus::DownloadError,
+                        Err(_) => true,
+                    })
+                    .unwrap_or(i),
+                _ => i,
+            });
 
-                    (score, *i)
+            let candidates = responses
+                .iter()
+                .enumerate()
+                .map(|(i, (request, response))| {
+                    get_candidate(request, response, purpose, selected == Some(i))
                 })
-                .map(|(_, response)| response)
-                .transpose()
+                .collect();
+
+            let meta = match selected {
+                Some(i) => Some(responses.into_iter().nth(i).unwrap().1?),
+                None => None,
+            };
+
+            // The object might be in a source that was skipped.
+            let found = meta
+                .as_ref()
+                .map_or(false, |m| m.status == CacheStatus::Positive);
+            if unavailable && !found {
+                return Err(ObjectErrorKind::Unavailable.into());
+            }
+
+            Ok(FoundObject { meta, candidates })
         });
 
         Box::new(selected_future)
     }
+
+    /// Downloads a single source file from the given source.
+    ///
+    /// The file is only returned if it contains valid UTF-8 and does not exceed
+    /// `MAX_SOURCE_FILE_SIZE`.
+    pub fn fetch_source_file(
+        &self,
+        scope: Scope,
+        source: SourceConfig,
+        path: DownloadPath,
+    ) -> SendFuture<Option<String>, ObjectError> {
+        let request = FetchSourceFileRequest {
+            scope,
+            source,
+            path,
+            downloader: self.downloader.clone(),
+        };
+
+        let future = self
+            .source_cache
+            .compute_memoized(request)
+            .map(|file| (*file).clone())
+            .map_err(|e| ArcFail(e).context(ObjectErrorKind::Caching).into());
+
+        Box::new(future)
+    }
+}
+
+/// Describes the outcome of fetching a single file during `ObjectsActor::find`.
+fn get_candidate(
+    request: &FetchFileMetaRequest,
+    response: &Result<Arc<ObjectFileMeta>, ObjectError>,
+    purpose: ObjectPurpose,
+    selected: bool,
+) -> ObjectCandidate {
+    let (file_format, status) = match response {
+        Ok(object) => {
+            let status = match object.status {
+                CacheStatus::Negative => CandidateStatus::NotFound,
+                CacheStatus::Malformed => CandidateStatus::Malformed,
+                CacheStatus::DownloadError => CandidateStatus::Error {
+                    details: ObjectErrorKind::DownloadFailed.to_string(),
+                },
+                CacheStatus::Positive if selected => CandidateStatus::Selected,
+                CacheStatus::Positive if object.score(purpose) < 2 => CandidateStatus::Ok,
+                CacheStatus::Positive => CandidateStatus::NoDebugInfo,
+            };
+            (object.meta.file_format.clone(), status)
+        }
+        Err(error) => {
+            let causes: Vec<_> = Fail::iter_chain(error as &dyn Fail)
+                .map(|cause| cause.to_string())
+                .collect();
+
+            let details = causes.join(": ");
+            (None, CandidateStatus::Error { details })
+        }
+    };
+
+    // Requests that joined a running computation receive the item loaded by the request that
+    // computed it, so take the download info from there. Errors are never loaded from the cache.
+    let (download_info, cache_hit) = match response {
+        Ok(object) => {
+            let download_info = *object.request.download_info.lock();
+            (download_info, download_info.is_none())
+        }
+        Err(_) => (*request.download_info.lock(), false),
+    };
+
+    ObjectCandidate {
+        source: request.source.id().to_owned(),
+        location: request.path.to_string(),
+        filetype: request.path.filetype(),
+        file_format,
+        status_code: download_info.and_then(|info| info.status_code),
+        cache_hit,
+        status,
+    }
+}
+
+/// Copies a downloaded source file into the cache if it is valid UTF-8 and small enough.
+fn handle_source_file(
+    downloaded_file: DownloadedFile,
+    target_path: &Path,
+) -> Result<CacheStatus, ObjectError> {
+    let mut contents = Vec::new();
+    downloaded_file
+        .reopen()?
+        .take(MAX_SOURCE_FILE_SIZE + 1)
+        .read_to_end(&mut contents)?;
+
+    if contents.len() as u64 > MAX_SOURCE_FILE_SIZE || std::str::from_utf8(&contents).is_err() {
+        return Ok(CacheStatus::Malformed);
+    }
+
+    fs::write(target_path, &contents)?;
+    Ok(CacheStatus::Positive)
 }
 
 fn decompress_file(
@@ -519,6 +778,17 @@ fn decompress_file(
     }
 }
 
+/// Returns whether a failed download should be cached as `CacheStatus::DownloadError`.
+///
+/// Canceled downloads and sources skipped by their circuit breaker are not cached, since no request
+/// was completed.
+fn should_cache_error(error: &DownloadError) -> bool {
+    match error.kind() {
+        DownloadErrorKind::Canceled | DownloadErrorKind::Unavailable => false,
+        _ => true,
+    }
+}
+
 fn handle_object(
     file_opt: Option<DownloadedFile>,
     temp_path: &Path,
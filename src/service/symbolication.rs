@@ -1,6 +1,8 @@
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryInto;
 use std::iter::FromIterator;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -26,18 +28,25 @@ use crate::logging::LogError;
 use crate::service::cficaches::{
     CfiCacheActor, CfiCacheError, CfiCacheErrorKind, CfiCacheFile, FetchCfiCache,
 };
+use crate::service::download::DownloadPath;
 use crate::service::objects::{FindObject, ObjectError, ObjectPurpose, ObjectsActor};
 use crate::service::symcaches::{
     FetchSymCache, SymCacheActor, SymCacheError, SymCacheErrorKind, SymCacheFile,
 };
 use crate::types::{
-    CompleteObjectInfo, CompleteStacktrace, CompletedSymbolicationResponse, FileType, FrameStatus,
-    ObjectFileStatus, ObjectId, ObjectType, RawFrame, RawObjectInfo, RawStacktrace, Registers,
-    RequestId, Scope, Signal, SourceConfig, SymbolicatedFrame, SymbolicationResponse, SystemInfo,
+    CompleteObjectInfo, CompleteStacktrace, CompletedSymbolicationResponse, DirectoryLayoutType,
+    FileType, FrameStatus, ObjectCandidate, ObjectFileStatus, ObjectId, ObjectType, RawFrame,
+    RawObjectInfo, RawStacktrace, Registers, RequestId, RequestOptions, Scope, Signal,
+    SourceConfig, SymbolicatedFrame, SymbolicationResponse, SystemInfo,
 };
+use crate::utils::coredump::{CoreError, CoreFile, CoreModule};
 use crate::utils::futures::{CallOnDrop, FutureExt, SendFuture, ThreadPool};
 use crate::utils::hex::HexValue;
+use crate::utils::ips::{IpsError, IpsException, IpsFrame, IpsImage, IpsReport};
+use crate::utils::paths::get_debuginfod_source_path;
 use crate::utils::sentry::SentryFutureExt;
+use crate::utils::tombstone::{debug_id_from_build_id, Tombstone, TombstoneError};
+use crate::utils::unwind::{walk_stack, CfiRules, UnwindModule};
 
 /// Options for demangling all symbols.
 const DEMANGLE_OPTIONS: DemangleOptions = DemangleOptions {
@@ -70,6 +79,12 @@ pub enum SymbolicationErrorKind {
 
     #[fail(display = "failed to parse apple crash report")]
     AppleCrashReport,
+
+    #[fail(display = "failed to parse tombstone")]
+    Tombstone,
+
+    #[fail(display = "failed to process core dump")]
+    Coredump,
 }
 
 symbolic::common::derive_failure!(
@@ -96,6 +111,24 @@ impl From<apple_crash_report_parser::ParseError> for SymbolicationError {
     }
 }
 
+impl From<IpsError> for SymbolicationError {
+    fn from(err: IpsError) -> Self {
+        err.context(SymbolicationErrorKind::AppleCrashReport).into()
+    }
+}
+
+impl From<CoreError> for SymbolicationError {
+    fn from(err: CoreError) -> Self {
+        err.context(SymbolicationErrorKind::Coredump).into()
+    }
+}
+
+impl From<TombstoneError> for SymbolicationError {
+    fn from(err: TombstoneError) -> Self {
+        err.context(SymbolicationErrorKind::Tombstone).into()
+    }
+}
+
 impl From<&SymbolicationError> for SymbolicationResponse {
     fn from(err: &SymbolicationError) -> SymbolicationResponse {
         match err.kind() {
@@ -108,6 +141,12 @@ impl From<&SymbolicationError> for SymbolicationResponse {
             SymbolicationErrorKind::AppleCrashReport => SymbolicationResponse::Failed {
                 message: err.to_string(),
             },
+            SymbolicationErrorKind::Tombstone => SymbolicationResponse::Failed {
+                message: err.to_string(),
+            },
+            SymbolicationErrorKind::Coredump => SymbolicationResponse::Failed {
+                message: err.to_string(),
+            },
         }
     }
 }
@@ -116,7 +155,17 @@ impl From<&SymbolicationError> for SymbolicationResponse {
 // global write lock.
 type ComputationChannel = Shared<oneshot::Receiver<(Instant, SymbolicationResponse)>>;
 
-type ComputationMap = Arc<Mutex<BTreeMap<RequestId, ComputationChannel>>>;
+type ComputationMap = Arc<Mutex<BTreeMap<RequestId, RequestComputation>>>;
+
+/// A running symbolication request.
+#[derive(Debug)]
+struct RequestComputation {
+    channel: ComputationChannel,
+    /// Aborts the request. `None` once the request has been canceled.
+    cancel: Option<oneshot::Sender<()>>,
+    /// Whether the computation has finished and its response can be polled.
+    finished: Arc<AtomicBool>,
+}
 
 #[derive(Clone, Debug)]
 pub struct SymbolicationActor {
@@ -125,6 +174,8 @@ pub struct SymbolicationActor {
     cficaches: Arc<CfiCacheActor>,
     threadpool: ThreadPool,
     requests: ComputationMap,
+    /// Whether new requests are rejected because the server is shutting down.
+    shutting_down: Arc<AtomicBool>,
 }
 
 impl SymbolicationActor {
@@ -142,6 +193,7 @@ impl SymbolicationActor {
             cficaches,
             threadpool,
             requests,
+            shutting_down: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -190,26 +242,43 @@ impl SymbolicationActor {
             + 'static,
     {
         let (sender, receiver) = oneshot::channel();
+        let (cancel_sender, cancel_receiver) = oneshot::channel();
 
         let requests = self.requests.clone();
         let request_id = RequestId::new(uuid::Uuid::new_v4());
-        let evicted = requests.lock().insert(request_id, receiver.shared());
+        let finished = Arc::new(AtomicBool::new(false));
+        let computation = RequestComputation {
+            channel: receiver.shared(),
+            cancel: Some(cancel_sender),
+            finished: finished.clone(),
+        };
+        let evicted = requests.lock().insert(request_id, computation);
         debug_assert!(evicted.is_none());
 
         let remove_symbolication_token = CallOnDrop::new(move || {
             requests.lock().remove(&request_id);
         });
 
+        // Canceling drops the request future, along with all downloads and computations that are
+        // not shared with other requests.
         let request_future = future::lazy(f)
+            .select2(cancel_receiver)
             .then(move |result| {
                 let response = match result {
-                    Ok(response) => SymbolicationResponse::Completed(Box::new(response)),
-                    Err(ref error) => {
-                        capture_fail(error);
-                        error.into()
+                    Ok(Either::A((response, _))) => {
+                        SymbolicationResponse::Completed(Box::new(response))
+                    }
+                    Err(Either::A((error, _))) => {
+                        capture_fail(&error);
+                        (&error).into()
+                    }
+                    Ok(Either::B(_)) | Err(Either::B(_)) => {
+                        metric!(counter("symbolication.canceled") += 1);
+                        SymbolicationResponse::Canceled
                     }
                 };
 
+                finished.store(true, Ordering::Relaxed);
                 sender.send((Instant::now(), response)).ok();
                 Delay::new(Instant::now() + MAX_POLL_DELAY)
             })
@@ -270,6 +339,8 @@ pub struct SourceObject(SelfCell<ByteView<'static>, Object<'static>>);
 
 struct SourceLookup {
     inner: Vec<(CompleteObjectInfo, Option<Arc<SourceObject>>)>,
+    /// Source files fetched from debuginfod, keyed by object index and absolute path.
+    files: BTreeMap<(usize, String), String>,
 }
 
 impl SourceLookup {
@@ -310,8 +381,8 @@ impl SourceLookup {
                             identifier: object_id_from_object_info(&object_info.raw),
                             sources: sources.clone(),
                         })
-                        .and_then(clone!(objects, |opt_object_file_meta| {
-                            match opt_object_file_meta {
+                        .and_then(clone!(objects, |found| {
+                            match found.meta {
                                 None => Either::A(future::ok(None)),
                                 Some(object_file_meta) => {
                                     Either::B(objects.fetch(object_file_meta).and_then(
@@ -335,12 +406,107 @@ impl SourceLookup {
         let joined = future::join_all(futures)
             .map(|results| SourceLookup {
                 inner: results.into_iter().collect(),
+                files: BTreeMap::new(),
             })
             .measure("fetch_sources");
 
         Box::new(joined)
     }
 
+    /// Fetches source files of ELF objects from debuginfod sources.
+    ///
+    /// This is a fallback for frames whose source file is not contained in a source bundle.
+    pub fn fetch_debuginfod_sources(
+        mut self,
+        objects: Arc<ObjectsActor>,
+        scope: Scope,
+        sources: Arc<Vec<SourceConfig>>,
+        response: &CompletedSymbolicationResponse,
+    ) -> SendFuture<Self, SymbolicationError> {
+        let debuginfod_sources: Vec<_> = sources
+            .iter()
+            .filter(|source| match source.directory_layout() {
+                Some(layout) => layout.ty == DirectoryLayoutType::Debuginfod,
+                None => false,
+            })
+            .cloned()
+            .collect();
+
+        if debuginfod_sources.is_empty() {
+            return Box::new(future::ok(self));
+        }
+
+        let mut missing_files = BTreeSet::new();
+        {
+            let debug_sessions = self.prepare_debug_sessions();
+            for stacktrace in &response.stacktraces {
+                for frame in &stacktrace.frames {
+                    let abs_path = match (&frame.raw.abs_path, frame.raw.lineno) {
+                        (&Some(ref abs_path), Some(_)) => abs_path,
+                        _ => continue,
+                    };
+
+                    let index = match self.get_object_index_by_addr(frame.raw.instruction_addr.0) {
+                        Some(index) => index,
+                        None => continue,
+                    };
+
+                    let has_source = debug_sessions[index]
+                        .as_ref()
+                        .and_then(|session| session.source_by_path(abs_path).ok())
+                        .map_or(false, |source| source.is_some());
+
+                    if !has_source && self.inner[index].0.raw.ty.0 == "elf" {
+                        missing_files.insert((index, abs_path.clone()));
+                    }
+                }
+            }
+        }
+
+        let futures = missing_files
+            .into_iter()
+            .filter_map(|(index, abs_path)| {
+                let object_id = object_id_from_object_info(&self.inner[index].0.raw);
+                let code_id = object_id.code_id?;
+
+                let fetch_futures = debuginfod_sources.iter().filter_map(|source| {
+                    // Only URLs need encoding, other sources use the file name verbatim.
+                    let encode = match source {
+                        SourceConfig::Http(_) => true,
+                        _ => false,
+                    };
+                    let path = get_debuginfod_source_path(&code_id, &abs_path, encode)?;
+
+                    let future = objects
+                        .fetch_source_file(scope.clone(), source.clone(), DownloadPath::from(path))
+                        .or_else(|_| Ok(None));
+
+                    Some(future)
+                });
+
+                // Prefer sources in the order they were configured.
+                let future = join_all(fetch_futures)
+                    .map(move |files| (index, abs_path, files.into_iter().flatten().next()));
+
+                Some(future)
+            })
+            .collect::<Vec<_>>();
+
+        let future = join_all(futures)
+            .map(move |results| {
+                for (index, abs_path, file) in results {
+                    if let Some(file) = file {
+                        self.files.insert((index, abs_path), file);
+                    }
+                }
+
+                self
+            })
+            .measure("fetch_debuginfod_sources");
+
+        Box::new(future)
+    }
+
     pub fn prepare_debug_sessions(&self) -> Vec<Option<ObjectDebugSession<'_>>> {
         self.inner
             .iter()
@@ -357,8 +523,14 @@ impl SourceLookup {
         n: usize,
     ) -> Option<(Vec<String>, String, Vec<String>)> {
         let index = self.get_object_index_by_addr(addr)?;
-        let session = debug_sessions[index].as_ref()?;
-        let source = session.source_by_path(abs_path).ok()??;
+        let bundled_source = debug_sessions[index]
+            .as_ref()
+            .and_then(|session| session.source_by_path(abs_path).ok()?);
+
+        let source = match bundled_source {
+            Some(source) => source,
+            None => Cow::Borrowed(self.files.get(&(index, abs_path.to_owned()))?.as_str()),
+        };
 
         let lineno = lineno as usize;
         let start_line = lineno.saturating_sub(n);
@@ -423,6 +595,7 @@ impl FromIterator<CompleteObjectInfo> for SourceLookup {
     {
         let mut rv = SourceLookup {
             inner: iter.into_iter().map(|x| (x, None)).collect(),
+            files: BTreeMap::new(),
         };
         rv.sort();
         rv
@@ -433,6 +606,19 @@ struct SymCacheLookup {
     inner: Vec<(CompleteObjectInfo, Option<Arc<SymCacheFile>>)>,
 }
 
+/// Adds the candidates of the debug file lookup to the candidates of the unwind lookup.
+///
+/// Locations that were searched by both lookups are listed once, with the outcome of the debug
+/// file lookup.
+fn merge_candidates(candidates: &mut Vec<ObjectCandidate>, debug_candidates: &[ObjectCandidate]) {
+    let unwind_candidates = std::mem::replace(candidates, debug_candidates.to_vec());
+    candidates.extend(unwind_candidates.into_iter().filter(|unwind| {
+        !debug_candidates
+            .iter()
+            .any(|debug| debug.source == unwind.source && debug.location == unwind.location)
+    }));
+}
+
 impl FromIterator<CompleteObjectInfo> for SymCacheLookup {
     fn from_iter<T>(iter: T) -> Self
     where
@@ -472,6 +658,7 @@ impl SymCacheLookup {
         let sources = request.sources;
         let stacktraces = request.stacktraces;
         let scope = request.scope.clone();
+        let dif_candidates = request.options.dif_candidates;
 
         for stacktrace in stacktraces {
             for frame in stacktrace.frames {
@@ -486,6 +673,11 @@ impl SymCacheLookup {
             .into_iter()
             .enumerate()
             .map(move |(i, (mut object_info, _))| {
+                // Candidates of the unwind lookup are only reported on request, too.
+                if !dif_candidates {
+                    object_info.candidates.clear();
+                }
+
                 if !referenced_objects.contains(&i) {
                     object_info.debug_status = ObjectFileStatus::Unused;
                     return Either::B(future::ok((object_info, None)));
@@ -508,6 +700,15 @@ impl SymCacheLookup {
                             object_info.arch =
                                 symcache.as_ref().map(|c| c.arch()).unwrap_or_default();
 
+                            if dif_candidates {
+                                if let Some(ref symcache) = symcache {
+                                    merge_candidates(
+                                        &mut object_info.candidates,
+                                        symcache.candidates(),
+                                    );
+                                }
+                            }
+
                             object_info.debug_status = status;
                             (object_info, symcache)
                         })
@@ -701,6 +902,8 @@ fn symbolicate_stacktrace(
 ) -> CompleteStacktrace {
     let mut stacktrace = CompleteStacktrace {
         thread_id: thread.thread_id,
+        thread_name: thread.thread_name,
+        queue_name: thread.queue_name,
         is_requesting: thread.is_requesting,
         registers: thread.registers.clone(),
         frames: vec![],
@@ -768,6 +971,9 @@ pub struct SymbolicateStacktraces {
     /// covered by any image, the frame cannot be symbolicated as it is not clear which debug file
     /// to load.
     pub modules: Vec<CompleteObjectInfo>,
+
+    /// Options that control the output of this request.
+    pub options: RequestOptions,
 }
 
 impl SymbolicationActor {
@@ -821,8 +1027,12 @@ impl SymbolicationActor {
             })
             .and_then(move |response| {
                 source_lookup
-                    .fetch_sources(objects, scope, sources, &response)
-                    .map(move |source_lookup| (source_lookup, response))
+                    .fetch_sources(objects.clone(), scope.clone(), sources.clone(), &response)
+                    .and_then(move |source_lookup| {
+                        source_lookup
+                            .fetch_debuginfod_sources(objects, scope, sources, &response)
+                            .map(move |source_lookup| (source_lookup, response))
+                    })
             })
             .and_then(move |(source_lookup, mut response)| {
                 let debug_sessions = source_lookup.prepare_debug_sessions();
@@ -873,7 +1083,11 @@ impl SymbolicationActor {
         request_id: RequestId,
         timeout: Option<u64>,
     ) -> SendFuture<Option<SymbolicationResponse>, SymbolicationError> {
-        let channel_opt = self.requests.lock().get(&request_id).cloned();
+        let channel_opt = self
+            .requests
+            .lock()
+            .get(&request_id)
+            .map(|computation| computation.channel.clone());
         match channel_opt {
             Some(channel) => Box::new(
                 self.wrap_response_channel(request_id, timeout, channel)
@@ -888,9 +1102,48 @@ impl SymbolicationActor {
             }
         }
     }
+
+    /// Cancels a running symbolication task.
+    ///
+    /// Subsequent polls return a `canceled` status. Requests that have already finished keep their
+    /// response. Returns `false` if the request does not exist.
+    pub fn cancel_request(&self, request_id: RequestId) -> bool {
+        match self.requests.lock().get_mut(&request_id) {
+            Some(computation) => {
+                if let Some(cancel) = computation.cancel.take() {
+                    cancel.send(()).ok();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Rejects all subsequent symbolication requests.
+    ///
+    /// Requests that are already running continue, and their responses can still be polled.
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether new symbolication requests are rejected.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests that are still being computed.
+    ///
+    /// Finished requests are not counted, even if their response has not been polled yet.
+    pub fn running_requests(&self) -> usize {
+        self.requests
+            .lock()
+            .values()
+            .filter(|computation| !computation.finished.load(Ordering::Relaxed))
+            .count()
+    }
 }
 
-type CfiCacheResult = (CodeModuleId, Result<Arc<CfiCacheFile>, Arc<CfiCacheError>>);
+type CfiCacheResult<K = CodeModuleId> = (K, Result<Arc<CfiCacheFile>, Arc<CfiCacheError>>);
 
 #[derive(Debug)]
 struct MinidumpState {
@@ -947,27 +1200,25 @@ impl SymbolicationActor {
         Ok(cfi_modules)
     }
 
-    fn fetch_cficaches(
+    fn fetch_cficaches<K: Send + 'static>(
         &self,
         scope: Scope,
-        requests: Vec<(CodeModuleId, RawObjectInfo)>,
+        requests: Vec<(K, RawObjectInfo)>,
         sources: Arc<Vec<SourceConfig>>,
-    ) -> SendFuture<Vec<CfiCacheResult>, SymbolicationError> {
+    ) -> SendFuture<Vec<CfiCacheResult<K>>, SymbolicationError> {
         let cficaches = self.cficaches.clone();
 
-        let futures = requests
-            .into_iter()
-            .map(move |(code_module_id, object_info)| {
-                cficaches
-                    .fetch(FetchCfiCache {
-                        object_type: object_info.ty.clone(),
-                        identifier: object_id_from_object_info(&object_info),
-                        sources: sources.clone(),
-                        scope: scope.clone(),
-                    })
-                    .then(move |result| future::ok((code_module_id, result)))
-                    .bind_hub(Hub::new_from_top(Hub::current()))
-            });
+        let futures = requests.into_iter().map(move |(key, object_info)| {
+            cficaches
+                .fetch(FetchCfiCache {
+                    object_type: object_info.ty.clone(),
+                    identifier: object_id_from_object_info(&object_info),
+                    sources: sources.clone(),
+                    scope: scope.clone(),
+                })
+                .then(move |result| future::ok((key, result)))
+                .bind_hub(Hub::new_from_top(Hub::current()))
+        });
 
         Box::new(join_all(futures).measure("fetch_cficaches"))
     }
@@ -982,6 +1233,7 @@ impl SymbolicationActor {
         let minidump = ByteView::from_slice(&minidump);
         let mut frame_info_map = FrameInfoMap::new();
         let mut unwind_statuses = BTreeMap::new();
+        let mut unwind_candidates = BTreeMap::new();
 
         for (code_module_id, result) in &cfi_results {
             let cache_file = match result {
@@ -993,6 +1245,8 @@ impl SymbolicationActor {
                 }
             };
 
+            unwind_candidates.insert(code_module_id, cache_file.candidates());
+
             log::trace!("Loading cficache");
             let cfi_cache = match cache_file.parse() {
                 Ok(Some(x)) => x,
@@ -1039,11 +1293,16 @@ impl SymbolicationActor {
                 let mut info: CompleteObjectInfo =
                     object_info_from_minidump_module(object_type.clone(), code_module).into();
 
+                let code_module_id = code_module.id()?;
                 let status = unwind_statuses
-                    .get(&code_module.id()?)
+                    .get(&code_module_id)
                     .cloned()
                     .unwrap_or(ObjectFileStatus::Unused);
 
+                if let Some(candidates) = unwind_candidates.get(&code_module_id) {
+                    info.candidates = candidates.to_vec();
+                }
+
                 metric!(
                     counter("symbolication.unwind_status") += 1,
                     "status" => status.name()
@@ -1098,6 +1357,8 @@ impl SymbolicationActor {
             stacktraces.push(RawStacktrace {
                 is_requesting: requesting_thread_index.map(|r| r == index),
                 thread_id: Some(thread.thread_id().into()),
+                thread_name: None,
+                queue_name: None,
                 registers,
                 frames,
             });
@@ -1109,6 +1370,7 @@ impl SymbolicationActor {
             sources,
             signal: None,
             stacktraces,
+            options: RequestOptions::default(),
         };
 
         Ok((request, minidump_state))
@@ -1143,12 +1405,14 @@ impl SymbolicationActor {
         scope: Scope,
         minidump: Bytes,
         sources: Vec<SourceConfig>,
+        options: RequestOptions,
     ) -> SendFuture<CompletedSymbolicationResponse, SymbolicationError> {
         let slf = self.clone();
 
         let future = slf
             .do_stackwalk_minidump(scope, minidump, sources)
-            .and_then(move |(request, state)| {
+            .and_then(move |(mut request, state)| {
+                request.options = options;
                 slf.do_symbolicate(request)
                     .map(move |response| (response, state))
             })
@@ -1165,185 +1429,880 @@ impl SymbolicationActor {
         scope: Scope,
         minidump: Bytes,
         sources: Vec<SourceConfig>,
+        options: RequestOptions,
     ) -> RequestId {
         let slf = self.clone();
-        self.create_symbolication_request(move || slf.do_process_minidump(scope, minidump, sources))
+        self.create_symbolication_request(move || {
+            slf.do_process_minidump(scope, minidump, sources, options)
+        })
     }
 }
 
 #[derive(Debug)]
-struct AppleCrashReportState {
-    timestamp: Option<u64>,
+struct CoredumpState {
     system_info: SystemInfo,
     crash_reason: Option<String>,
-    crash_details: Option<String>,
 }
 
-impl AppleCrashReportState {
-    fn merge_into(mut self, response: &mut CompletedSymbolicationResponse) {
-        if self.system_info.cpu_arch == Arch::Unknown {
-            self.system_info.cpu_arch = response
-                .modules
-                .iter()
-                .map(|object| object.arch)
-                .find(|arch| *arch != Arch::Unknown)
-                .unwrap_or_default();
-        }
-
-        response.timestamp = self.timestamp;
+impl CoredumpState {
+    fn merge_into(self, response: &mut CompletedSymbolicationResponse) {
         response.system_info = Some(self.system_info);
         response.crash_reason = self.crash_reason;
-        response.crash_details = self.crash_details;
         response.crashed = Some(true);
     }
 }
 
-fn map_apple_binary_image(image: apple_crash_report_parser::BinaryImage) -> CompleteObjectInfo {
-    let code_id = CodeId::from_binary(&image.uuid.as_bytes()[..]);
-    let debug_id = DebugId::from_uuid(image.uuid);
+/// Returns the name of common Linux signals.
+fn get_signal_name(signal: u32) -> Option<&'static str> {
+    Some(match signal {
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        15 => "SIGTERM",
+        31 => "SIGSYS",
+        _ => return None,
+    })
+}
 
-    let raw_info = RawObjectInfo {
-        ty: ObjectType("macho".to_owned()),
-        code_id: Some(code_id.to_string()),
-        code_file: Some(image.path.clone()),
-        debug_id: Some(debug_id.to_string()),
-        debug_file: Some(image.path),
-        image_addr: HexValue(image.addr.0),
-        image_size: match image.size {
+fn object_info_from_core_module(module: &CoreModule) -> RawObjectInfo {
+    let build_id = module.build_id.as_ref();
+
+    RawObjectInfo {
+        ty: ObjectType("elf".to_owned()),
+        code_id: build_id.cloned(),
+        code_file: Some(module.path.clone()),
+        debug_id: build_id
+            .and_then(|id| debug_id_from_build_id(id))
+            .map(|id| id.to_string()),
+        debug_file: Some(module.path.clone()),
+        image_addr: HexValue(module.start),
+        image_size: match module.end.saturating_sub(module.start) {
             0 => None,
             size => Some(size),
         },
-    };
-
-    raw_info.into()
+    }
 }
 
 impl SymbolicationActor {
-    fn parse_apple_crash_report(
+    fn get_referenced_modules_from_coredump(
+        &self,
+        coredump: Bytes,
+    ) -> Result<Vec<(usize, RawObjectInfo)>, SymbolicationError> {
+        log::debug!("Processing core dump ({} bytes)", coredump.len());
+        metric!(time_raw("coredump.upload.size") = coredump.len() as u64);
+        let core = CoreFile::parse(&coredump)?;
+
+        // Modules are keyed by their index, which is stable across parsing the core file twice.
+        let cfi_modules = core
+            .modules()
+            .iter()
+            .enumerate()
+            .filter(|(_, module)| module.build_id.is_some())
+            .map(|(index, module)| (index, object_info_from_core_module(module)))
+            .collect();
+
+        Ok(cfi_modules)
+    }
+
+    fn stackwalk_coredump_with_cfi(
         &self,
         scope: Scope,
-        file: Bytes,
-        sources: Vec<SourceConfig>,
-    ) -> Result<(SymbolicateStacktraces, AppleCrashReportState), SymbolicationError> {
-        let report = AppleCrashReport::from_reader(&file[..])?;
-        let mut metadata = report.metadata;
+        coredump: Bytes,
+        sources: Arc<Vec<SourceConfig>>,
+        cfi_results: Vec<CfiCacheResult<usize>>,
+    ) -> Result<(SymbolicateStacktraces, CoredumpState), SymbolicationError> {
+        let core = CoreFile::parse(&coredump)?;
+        let core_modules = core.modules();
+        let mut cfi_caches = BTreeMap::new();
+        let mut unwind_statuses = BTreeMap::new();
+        let mut unwind_candidates = BTreeMap::new();
 
-        let arch = report
-            .code_type
-            .as_ref()
-            .and_then(|code_type| code_type.split(' ').next())
-            .and_then(|word| word.parse().ok())
-            .unwrap_or_default();
+        for (index, result) in &cfi_results {
+            let cache_file = match result {
+                Ok(x) => x,
+                Err(e) => {
+                    log::info!("Error while fetching cficache: {}", LogError(&**e));
+                    unwind_statuses.insert(*index, (&**e).into());
+                    continue;
+                }
+            };
 
-        let modules = report
-            .binary_images
-            .into_iter()
-            .map(map_apple_binary_image)
+            unwind_candidates.insert(*index, cache_file.candidates());
+
+            log::trace!("Loading cficache");
+            let cfi_cache = match cache_file.parse() {
+                Ok(Some(x)) => x,
+                Ok(None) => {
+                    unwind_statuses.insert(*index, ObjectFileStatus::Missing);
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("Error while parsing cficache: {}", LogError(&e));
+                    unwind_statuses.insert(*index, (&e).into());
+                    continue;
+                }
+            };
+
+            unwind_statuses.insert(*index, ObjectFileStatus::Found);
+            cfi_caches.insert(*index, cfi_cache);
+        }
+
+        let unwind_modules: Vec<_> = core_modules
+            .iter()
+            .enumerate()
+            .map(|(index, module)| UnwindModule {
+                start: module.start,
+                end: module.end,
+                rules: cfi_caches
+                    .get(&index)
+                    .map(|cfi_cache| CfiRules::parse(cfi_cache.as_slice())),
+            })
             .collect();
 
-        let mut stacktraces = Vec::with_capacity(report.threads.len());
+        let arch = core.arch();
+        let mut stacktraces = Vec::with_capacity(core.threads().len());
 
-        for thread in report.threads {
+        for (index, thread) in core.threads().iter().enumerate() {
             let registers = thread
                 .registers
-                .unwrap_or_default()
-                .into_iter()
-                .map(|(name, addr)| (name, HexValue(addr.0)))
+                .iter()
+                .map(|(name, value)| (name.clone(), HexValue(*value)))
                 .collect();
 
-            let frames = thread
-                .frames
+            let frames = walk_stack(arch, thread.registers.clone(), &unwind_modules, &core)
                 .into_iter()
                 .map(|frame| RawFrame {
-                    instruction_addr: HexValue(frame.instruction_addr.0),
-                    package: frame.module,
+                    instruction_addr: HexValue(frame.instruction_addr),
+                    package: core_modules
+                        .iter()
+                        .find(|m| {
+                            frame.instruction_addr >= m.start && frame.instruction_addr < m.end
+                        })
+                        .map(|m| m.path.clone()),
+                    trust: frame.trust,
                     ..RawFrame::default()
                 })
                 .collect();
 
+            // The kernel writes the thread that received the signal first.
             stacktraces.push(RawStacktrace {
-                thread_id: Some(thread.id),
-                is_requesting: Some(thread.crashed),
+                thread_id: Some(thread.tid.into()),
+                thread_name: None,
+                queue_name: None,
+                is_requesting: Some(index == 0),
                 registers,
                 frames,
             });
         }
 
+        let modules = core_modules
+            .iter()
+            .enumerate()
+            .map(|(index, module)| {
+                let mut info: CompleteObjectInfo = object_info_from_core_module(module).into();
+
+                let status = unwind_statuses
+                    .get(&index)
+                    .cloned()
+                    .unwrap_or(ObjectFileStatus::Unused);
+
+                if let Some(candidates) = unwind_candidates.get(&index) {
+                    info.candidates = candidates.to_vec();
+                }
+
+                metric!(
+                    counter("symbolication.unwind_status") += 1,
+                    "status" => status.name()
+                );
+                info.unwind_status = Some(status);
+
+                info
+            })
+            .collect();
+
+        let signal = core.threads()[0].signal;
+
         let request = SymbolicateStacktraces {
             modules,
             scope,
-            sources: Arc::new(sources),
-            signal: None,
+            sources,
+            signal: Some(Signal(signal)).filter(|s| s.0 != 0),
             stacktraces,
+            options: RequestOptions::default(),
         };
 
-        let mut system_info = SystemInfo {
-            os_name: metadata.remove("OS Version").unwrap_or_default(),
-            device_model: metadata.remove("Hardware Model").unwrap_or_default(),
-            cpu_arch: arch,
-            ..SystemInfo::default()
-        };
-
-        if let Some(captures) = OS_MACOS_REGEX.captures(&system_info.os_name) {
-            system_info.os_version = captures
-                .name("version")
-                .map(|m| m.as_str().to_string())
-                .unwrap_or_default();
-            system_info.os_build = captures
-                .name("build")
-                .map(|m| m.as_str().to_string())
-                .unwrap_or_default();
-            system_info.os_name = "macOS".to_string();
-        }
-
-        // https://developer.apple.com/library/archive/technotes/tn2151/_index.html
-        let crash_reason = metadata.remove("Exception Type");
-        let crash_details = report
-            .application_specific_information
-            .or_else(|| metadata.remove("Exception Message"))
-            .or_else(|| metadata.remove("Exception Subtype"))
-            .or_else(|| metadata.remove("Exception Codes"));
-
-        let state = AppleCrashReportState {
-            timestamp: report.timestamp.map(|t| t.timestamp() as u64),
-            system_info,
-            crash_reason,
-            crash_details,
+        let state = CoredumpState {
+            system_info: SystemInfo {
+                os_name: "Linux".to_owned(),
+                cpu_arch: arch,
+                ..SystemInfo::default()
+            },
+            crash_reason: get_signal_name(signal).map(str::to_owned),
         };
 
         Ok((request, state))
     }
 
-    fn do_process_apple_crash_report(
+    fn do_stackwalk_coredump(
         &self,
         scope: Scope,
-        report: Bytes,
+        coredump: Bytes,
         sources: Vec<SourceConfig>,
-    ) -> SendFuture<CompletedSymbolicationResponse, SymbolicationError> {
+    ) -> SendFuture<(SymbolicateStacktraces, CoredumpState), SymbolicationError> {
         let slf = self.clone();
+        let sources = Arc::new(sources);
 
-        let future = future::result(self.parse_apple_crash_report(scope, report, sources))
-            .and_then(move |(request, state)| {
-                slf.do_symbolicate(request)
-                    .map(move |response| (response, state))
-            })
-            .map(|(mut response, state)| {
-                state.merge_into(&mut response);
+        let future = future::result(slf.get_referenced_modules_from_coredump(coredump.clone()))
+            .and_then(clone!(slf, scope, sources, |referenced_modules| {
+                slf.fetch_cficaches(scope, referenced_modules, sources)
+            }))
+            .and_then(move |cfi_caches| {
+                slf.stackwalk_coredump_with_cfi(scope, coredump, sources, cfi_caches)
+            })
+            .timeout(Duration::from_secs(1200), || {
+                SymbolicationErrorKind::Timeout
+            })
+            .measure("coredump_stackwalk");
+
+        Box::new(future)
+    }
+
+    fn do_process_coredump(
+        &self,
+        scope: Scope,
+        coredump: Bytes,
+        sources: Vec<SourceConfig>,
+        options: RequestOptions,
+    ) -> SendFuture<CompletedSymbolicationResponse, SymbolicationError> {
+        let slf = self.clone();
+
+        let future = slf
+            .do_stackwalk_coredump(scope, coredump, sources)
+            .and_then(move |(mut request, state)| {
+                request.options = options;
+                slf.do_symbolicate(request)
+                    .map(move |response| (response, state))
+            })
+            .map(|(mut response, state)| {
+                state.merge_into(&mut response);
+                response
+            });
+
+        Box::new(future)
+    }
+
+    pub fn process_coredump(
+        &self,
+        scope: Scope,
+        coredump: Bytes,
+        sources: Vec<SourceConfig>,
+        options: RequestOptions,
+    ) -> RequestId {
+        let slf = self.clone();
+        self.create_symbolication_request(move || {
+            slf.do_process_coredump(scope, coredump, sources, options)
+        })
+    }
+}
+
+#[derive(Debug)]
+struct AppleCrashReportState {
+    timestamp: Option<u64>,
+    system_info: SystemInfo,
+    crash_reason: Option<String>,
+    crash_details: Option<String>,
+}
+
+impl AppleCrashReportState {
+    fn merge_into(mut self, response: &mut CompletedSymbolicationResponse) {
+        if self.system_info.cpu_arch == Arch::Unknown {
+            self.system_info.cpu_arch = response
+                .modules
+                .iter()
+                .map(|object| object.arch)
+                .find(|arch| *arch != Arch::Unknown)
+                .unwrap_or_default();
+        }
+
+        response.timestamp = self.timestamp;
+        response.system_info = Some(self.system_info);
+        response.crash_reason = self.crash_reason;
+        response.crash_details = self.crash_details;
+        response.crashed = Some(true);
+    }
+}
+
+fn map_apple_binary_image(image: apple_crash_report_parser::BinaryImage) -> CompleteObjectInfo {
+    let code_id = CodeId::from_binary(&image.uuid.as_bytes()[..]);
+    let debug_id = DebugId::from_uuid(image.uuid);
+
+    let raw_info = RawObjectInfo {
+        ty: ObjectType("macho".to_owned()),
+        code_id: Some(code_id.to_string()),
+        code_file: Some(image.path.clone()),
+        debug_id: Some(debug_id.to_string()),
+        debug_file: Some(image.path),
+        image_addr: HexValue(image.addr.0),
+        image_size: match image.size {
+            0 => None,
+            size => Some(size),
+        },
+    };
+
+    raw_info.into()
+}
+
+fn map_ips_image(image: &IpsImage) -> Option<CompleteObjectInfo> {
+    let uuid = image.uuid()?;
+    let path = image.path.clone().or_else(|| image.name.clone());
+
+    let raw_info = RawObjectInfo {
+        ty: ObjectType("macho".to_owned()),
+        code_id: Some(CodeId::from_binary(&uuid.as_bytes()[..]).to_string()),
+        code_file: path.clone(),
+        debug_id: Some(DebugId::from_uuid(uuid).to_string()),
+        debug_file: path,
+        image_addr: HexValue(image.base),
+        image_size: match image.size {
+            0 => None,
+            size => Some(size),
+        },
+    };
+
+    Some(raw_info.into())
+}
+
+/// Resolves the absolute addresses of frames in an `.ips` report.
+///
+/// Frames in images without a UUID are resolved against the image base but cannot be symbolicated.
+/// Frames with an invalid image index are kept with their raw offset as address, so that the stack
+/// trace remains complete.
+fn map_ips_frames(report: &IpsReport, frames: &[IpsFrame]) -> Vec<RawFrame> {
+    frames
+        .iter()
+        .map(|frame| match report.image(frame) {
+            Some(image) => RawFrame {
+                instruction_addr: HexValue(image.base.wrapping_add(frame.image_offset)),
+                package: image.path.clone().or_else(|| image.name.clone()),
+                ..RawFrame::default()
+            },
+            None => RawFrame {
+                instruction_addr: HexValue(frame.image_offset),
+                package: None,
+                ..RawFrame::default()
+            },
+        })
+        .collect()
+}
+
+/// Maps the CPU types used in `.ips` reports to architectures.
+fn get_arch_from_ips_cpu_type(cpu_type: &str) -> Arch {
+    match cpu_type {
+        "ARM" => Arch::Arm,
+        "ARM-64" => Arch::Arm64,
+        "X86" => Arch::X86,
+        "X86-64" => Arch::Amd64,
+        _ => Arch::Unknown,
+    }
+}
+
+impl SymbolicationActor {
+    fn parse_ips_report(
+        &self,
+        scope: Scope,
+        file: Bytes,
+        sources: Vec<SourceConfig>,
+    ) -> Result<(SymbolicateStacktraces, AppleCrashReportState), SymbolicationError> {
+        let report = IpsReport::parse(&file)?;
+        let body = &report.body;
+
+        let modules = body.used_images.iter().filter_map(map_ips_image).collect();
+
+        let mut stacktraces = Vec::with_capacity(body.threads.len() + 1);
+
+        // The backtrace of an uncaught exception is where the exception was thrown, while the
+        // crashed thread only shows where it was rethrown. List it first, like Apple does.
+        if let Some(ref frames) = body.last_exception_backtrace {
+            stacktraces.push(RawStacktrace {
+                thread_id: None,
+                thread_name: Some("Last Exception Backtrace".to_owned()),
+                queue_name: None,
+                is_requesting: None,
+                registers: Registers::default(),
+                frames: map_ips_frames(&report, frames),
+            });
+        }
+
+        for thread in &body.threads {
+            let registers = thread
+                .registers()
+                .into_iter()
+                .map(|(name, value)| (name, HexValue(value)))
+                .collect();
+
+            stacktraces.push(RawStacktrace {
+                thread_id: Some(thread.id),
+                thread_name: thread.name.clone(),
+                queue_name: thread.queue.clone(),
+                is_requesting: Some(thread.triggered),
+                registers,
+                frames: map_ips_frames(&report, &thread.frames),
+            });
+        }
+
+        let request = SymbolicateStacktraces {
+            modules,
+            scope,
+            sources: Arc::new(sources),
+            signal: None,
+            stacktraces,
+            options: RequestOptions::default(),
+        };
+
+        let mut system_info = SystemInfo {
+            device_model: body.model_code.clone().unwrap_or_default(),
+            cpu_arch: body.cpu_type.as_ref().map_or(Arch::Unknown, |cpu_type| {
+                get_arch_from_ips_cpu_type(cpu_type)
+            }),
+            ..SystemInfo::default()
+        };
+
+        if let Some(ref os_version) = body.os_version {
+            // The train contains the name and version of the OS, e.g. "iPhone OS 15.0".
+            let train = os_version
+                .train
+                .as_ref()
+                .map(String::as_str)
+                .unwrap_or_default();
+            let mut parts = train.rsplitn(2, ' ');
+            system_info.os_version = parts.next().unwrap_or_default().to_owned();
+            system_info.os_name = parts.next().unwrap_or_default().to_owned();
+            system_info.os_build = os_version.build.clone().unwrap_or_default();
+        }
+
+        let IpsException {
+            ty,
+            signal,
+            subtype,
+            message,
+            codes,
+        } = body.exception.clone().unwrap_or_default();
+
+        let crash_reason = ty.map(|ty| match signal {
+            Some(signal) => format!("{} ({})", ty, signal),
+            None => ty,
+        });
+
+        let application_specific_information = body
+            .asi
+            .as_ref()
+            .map(|asi| {
+                asi.values()
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .filter(|info| !info.is_empty());
+
+        let crash_details = application_specific_information
+            .or(message)
+            .or(subtype)
+            .or(codes);
+
+        let state = AppleCrashReportState {
+            timestamp: report.timestamp().map(|t| t.timestamp() as u64),
+            system_info,
+            crash_reason,
+            crash_details,
+        };
+
+        Ok((request, state))
+    }
+
+    fn parse_apple_crash_report(
+        &self,
+        scope: Scope,
+        file: Bytes,
+        sources: Vec<SourceConfig>,
+    ) -> Result<(SymbolicateStacktraces, AppleCrashReportState), SymbolicationError> {
+        if IpsReport::test(&file) {
+            return self.parse_ips_report(scope, file, sources);
+        }
+
+        let report = AppleCrashReport::from_reader(&file[..])?;
+        let mut metadata = report.metadata;
+
+        let arch = report
+            .code_type
+            .as_ref()
+            .and_then(|code_type| code_type.split(' ').next())
+            .and_then(|word| word.parse().ok())
+            .unwrap_or_default();
+
+        let modules = report
+            .binary_images
+            .into_iter()
+            .map(map_apple_binary_image)
+            .collect();
+
+        let mut stacktraces = Vec::with_capacity(report.threads.len());
+
+        for thread in report.threads {
+            let registers = thread
+                .registers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, addr)| (name, HexValue(addr.0)))
+                .collect();
+
+            let frames = thread
+                .frames
+                .into_iter()
+                .map(|frame| RawFrame {
+                    instruction_addr: HexValue(frame.instruction_addr.0),
+                    package: frame.module,
+                    ..RawFrame::default()
+                })
+                .collect();
+
+            stacktraces.push(RawStacktrace {
+                thread_id: Some(thread.id),
+                thread_name: None,
+                queue_name: None,
+                is_requesting: Some(thread.crashed),
+                registers,
+                frames,
+            });
+        }
+
+        let request = SymbolicateStacktraces {
+            modules,
+            scope,
+            sources: Arc::new(sources),
+            signal: None,
+            stacktraces,
+            options: RequestOptions::default(),
+        };
+
+        let mut system_info = SystemInfo {
+            os_name: metadata.remove("OS Version").unwrap_or_default(),
+            device_model: metadata.remove("Hardware Model").unwrap_or_default(),
+            cpu_arch: arch,
+            ..SystemInfo::default()
+        };
+
+        if let Some(captures) = OS_MACOS_REGEX.captures(&system_info.os_name) {
+            system_info.os_version = captures
+                .name("version")
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+            system_info.os_build = captures
+                .name("build")
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+            system_info.os_name = "macOS".to_string();
+        }
+
+        // https://developer.apple.com/library/archive/technotes/tn2151/_index.html
+        let crash_reason = metadata.remove("Exception Type");
+        let crash_details = report
+            .application_specific_information
+            .or_else(|| metadata.remove("Exception Message"))
+            .or_else(|| metadata.remove("Exception Subtype"))
+            .or_else(|| metadata.remove("Exception Codes"));
+
+        let state = AppleCrashReportState {
+            timestamp: report.timestamp.map(|t| t.timestamp() as u64),
+            system_info,
+            crash_reason,
+            crash_details,
+        };
+
+        Ok((request, state))
+    }
+
+    fn do_process_apple_crash_report(
+        &self,
+        scope: Scope,
+        report: Bytes,
+        sources: Vec<SourceConfig>,
+        options: RequestOptions,
+    ) -> SendFuture<CompletedSymbolicationResponse, SymbolicationError> {
+        let slf = self.clone();
+
+        let future = future::result(self.parse_apple_crash_report(scope, report, sources))
+            .and_then(move |(mut request, state)| {
+                request.options = options;
+                slf.do_symbolicate(request)
+                    .map(move |response| (response, state))
+            })
+            .map(|(mut response, state)| {
+                state.merge_into(&mut response);
+                response
+            });
+
+        Box::new(future)
+    }
+
+    pub fn process_apple_crash_report(
+        &self,
+        scope: Scope,
+        apple_crash_report: Bytes,
+        sources: Vec<SourceConfig>,
+        options: RequestOptions,
+    ) -> RequestId {
+        let slf = self.clone();
+        self.create_symbolication_request(move || {
+            slf.do_process_apple_crash_report(scope, apple_crash_report, sources, options)
+        })
+    }
+}
+
+#[derive(Debug)]
+struct TombstoneState {
+    timestamp: Option<u64>,
+    system_info: SystemInfo,
+    crash_reason: Option<String>,
+    crash_details: Option<String>,
+}
+
+impl TombstoneState {
+    fn merge_into(self, response: &mut CompletedSymbolicationResponse) {
+        response.timestamp = self.timestamp;
+        response.system_info = Some(self.system_info);
+        response.crash_reason = self.crash_reason;
+        response.crash_details = self.crash_details;
+        response.crashed = Some(true);
+    }
+}
+
+/// Maps the ABI names used by Android to architectures.
+fn get_arch_from_android_abi(abi: &str) -> Arch {
+    match abi {
+        "arm" => Arch::Arm,
+        "arm64" => Arch::Arm64,
+        "x86" => Arch::X86,
+        "x86_64" => Arch::Amd64,
+        _ => Arch::Unknown,
+    }
+}
+
+/// Extracts system information from an Android build fingerprint.
+///
+/// Fingerprints have the format `brand/product/device:version/build/incremental:type/tags`.
+fn get_system_info_from_fingerprint(fingerprint: &str, system_info: &mut SystemInfo) {
+    let mut parts = fingerprint.split(':');
+
+    if let Some(device) = parts.next().and_then(|product| product.split('/').nth(2)) {
+        system_info.device_model = device.to_owned();
+    }
+
+    if let Some(release) = parts.next() {
+        let mut release = release.split('/');
+        system_info.os_version = release.next().unwrap_or_default().to_owned();
+        system_info.os_build = release.next().unwrap_or_default().to_owned();
+    }
+}
+
+/// An ELF module referenced by a tombstone.
+#[derive(Debug)]
+struct TombstoneModule {
+    start: u64,
+    end: u64,
+    build_id: String,
+}
+
+fn map_tombstone_module(path: String, module: TombstoneModule) -> CompleteObjectInfo {
+    let raw_info = RawObjectInfo {
+        ty: ObjectType("elf".to_owned()),
+        code_id: Some(module.build_id.clone()),
+        code_file: Some(path.clone()),
+        debug_id: debug_id_from_build_id(&module.build_id).map(|id| id.to_string()),
+        debug_file: Some(path),
+        image_addr: HexValue(module.start),
+        image_size: match module.end.saturating_sub(module.start) {
+            0 => None,
+            size => Some(size),
+        },
+    };
+
+    raw_info.into()
+}
+
+impl SymbolicationActor {
+    fn parse_tombstone(
+        &self,
+        scope: Scope,
+        file: Bytes,
+        sources: Vec<SourceConfig>,
+    ) -> Result<(SymbolicateStacktraces, TombstoneState), SymbolicationError> {
+        let tombstone = Tombstone::parse(&String::from_utf8_lossy(&file))?;
+
+        // Modules are identified by their memory maps. A library can span multiple mappings, for
+        // instance one for its code and one for its data, so merge all mappings of the same file.
+        let mut modules = BTreeMap::<String, TombstoneModule>::new();
+        for map in tombstone.memory_maps {
+            let (name, build_id) = match (map.name, map.build_id) {
+                (Some(name), Some(build_id)) => (name, build_id),
+                _ => continue,
+            };
+
+            // The end of a mapping is inclusive. Skip mappings that end at the top of the address
+            // space, which are not valid in practice.
+            let end = match map.end.checked_add(1) {
+                Some(end) => end,
+                None => continue,
+            };
+
+            let module = modules.entry(name).or_insert(TombstoneModule {
+                start: map.start,
+                end,
+                build_id,
+            });
+
+            module.start = module.start.min(map.start);
+            module.end = module.end.max(end);
+        }
+
+        // Tombstones may omit the memory maps. Since frames only carry addresses relative to their
+        // module, assign synthetic load addresses to all modules that have no mapping.
+        let mut next_addr = modules.values().map(|m| m.end).max().unwrap_or(0);
+        for frame in tombstone.threads.iter().flat_map(|thread| &thread.frames) {
+            let build_id = match frame.build_id {
+                Some(ref build_id) if !modules.contains_key(&frame.module) => build_id,
+                _ => continue,
+            };
+
+            // Frames of modules that no longer fit into the address space are dropped below.
+            let start = match next_addr.checked_add(0xfff) {
+                Some(addr) => addr & !0xfff,
+                None => break,
+            };
+            next_addr = start.saturating_add(0x1000_0000);
+            modules.insert(
+                frame.module.clone(),
+                TombstoneModule {
+                    start,
+                    end: start,
+                    build_id: build_id.clone(),
+                },
+            );
+        }
+
+        let mut stacktraces = Vec::with_capacity(tombstone.threads.len());
+
+        for thread in tombstone.threads {
+            let registers = thread
+                .registers
+                .into_iter()
+                .map(|(name, value)| (name, HexValue(value)))
+                .collect();
+
+            let frames = thread
+                .frames
+                .into_iter()
+                .filter_map(|frame| {
+                    let image_addr = modules.get(&frame.module).map_or(0, |m| m.start);
+                    Some(RawFrame {
+                        instruction_addr: HexValue(image_addr.checked_add(frame.rel_pc)?),
+                        package: Some(frame.module),
+                        ..RawFrame::default()
+                    })
+                })
+                .collect();
+
+            stacktraces.push(RawStacktrace {
+                thread_id: Some(thread.tid),
+                thread_name: Some(thread.name),
+                queue_name: None,
+                is_requesting: Some(thread.crashed),
+                registers,
+                frames,
+            });
+        }
+
+        let request = SymbolicateStacktraces {
+            modules: modules
+                .into_iter()
+                .map(|(path, module)| map_tombstone_module(path, module))
+                .collect(),
+            scope,
+            sources: Arc::new(sources),
+            signal: tombstone
+                .signal
+                .as_ref()
+                .map(|signal| Signal(signal.number)),
+            stacktraces,
+            options: RequestOptions::default(),
+        };
+
+        let mut system_info = SystemInfo {
+            os_name: "Android".to_owned(),
+            cpu_arch: tombstone
+                .abi
+                .as_ref()
+                .map_or(Arch::Unknown, |abi| get_arch_from_android_abi(abi)),
+            ..SystemInfo::default()
+        };
+
+        if let Some(ref fingerprint) = tombstone.build_fingerprint {
+            get_system_info_from_fingerprint(fingerprint, &mut system_info);
+        }
+
+        let crash_reason = tombstone
+            .signal
+            .map(|signal| format!("{} / {}", signal.name, signal.code_name));
+
+        let state = TombstoneState {
+            timestamp: tombstone.timestamp.map(|t| t.timestamp() as u64),
+            system_info,
+            crash_reason,
+            crash_details: tombstone.cause.or(tombstone.abort_message),
+        };
+
+        Ok((request, state))
+    }
+
+    fn do_process_tombstone(
+        &self,
+        scope: Scope,
+        tombstone: Bytes,
+        sources: Vec<SourceConfig>,
+        options: RequestOptions,
+    ) -> SendFuture<CompletedSymbolicationResponse, SymbolicationError> {
+        let slf = self.clone();
+
+        let future = future::result(self.parse_tombstone(scope, tombstone, sources))
+            .and_then(move |(mut request, state)| {
+                request.options = options;
+                slf.do_symbolicate(request)
+                    .map(move |response| (response, state))
+            })
+            .map(|(mut response, state)| {
+                state.merge_into(&mut response);
                 response
             });
 
         Box::new(future)
     }
 
-    pub fn process_apple_crash_report(
+    pub fn process_tombstone(
         &self,
         scope: Scope,
-        apple_crash_report: Bytes,
+        tombstone: Bytes,
         sources: Vec<SourceConfig>,
+        options: RequestOptions,
     ) -> RequestId {
         let slf = self.clone();
         self.create_symbolication_request(move || {
-            slf.do_process_apple_crash_report(scope, apple_crash_report, sources)
+            slf.do_process_tombstone(scope, tombstone, sources, options)
         })
     }
 }
@@ -1414,7 +2373,9 @@ mod tests {
     use crate::config::Config;
     use crate::service::Service;
     use crate::test;
-    use crate::types::SourceConfig;
+    use crate::types::{
+        CandidateStatus, CommonSourceConfig, DirectoryLayout, HttpSourceConfig, SourceConfig,
+    };
 
     /// Setup tests and create a test service.
     ///
@@ -1458,6 +2419,7 @@ mod tests {
                 code_file: None,
                 debug_file: None,
             })],
+            options: RequestOptions::default(),
         }
     }
 
@@ -1515,6 +2477,98 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_dif_candidates() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+        let (_symsrv, source) = test::symbol_server();
+
+        let get_candidates = || -> Result<Vec<ObjectCandidate>, Error> {
+            let response = test::block_fn(|| {
+                let mut request = get_symbolication_request(vec![source.clone()]);
+                request.options.dif_candidates = true;
+                let request_id = service.symbolication().symbolicate_stacktraces(request);
+                service.symbolication().get_response(request_id, None)
+            })?;
+
+            match response {
+                Some(SymbolicationResponse::Completed(mut response)) => {
+                    Ok(response.modules.swap_remove(0).candidates)
+                }
+                _ => panic!("unexpected response: {:?}", response),
+            }
+        };
+
+        let candidates = get_candidates()?;
+        assert!(candidates.iter().all(|c| c.source == "local"));
+        assert!(candidates.iter().all(|c| !c.cache_hit));
+
+        let selected: Vec<_> = candidates
+            .iter()
+            .filter(|c| c.status == CandidateStatus::Selected)
+            .collect();
+
+        assert_eq!(selected.len(), 1);
+        assert_eq!(
+            selected[0].file_format.as_ref().map(String::as_str),
+            Some("macho")
+        );
+        assert_eq!(selected[0].filetype, Some(FileType::MachDebug));
+        assert_eq!(selected[0].status_code, Some(200));
+
+        let missing = candidates
+            .iter()
+            .find(|c| c.status == CandidateStatus::NotFound)
+            .expect("missing candidate");
+        assert_eq!(missing.status_code, Some(404));
+
+        // The second lookup is served from the cache.
+        let candidates = get_candidates()?;
+        assert!(candidates
+            .iter()
+            .all(|c| c.cache_hit && c.status_code.is_none()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dif_candidates_concurrent() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+        let (_symsrv, source) = test::symbol_server();
+
+        // The second request joins the downloads of the first one and must not report cache hits.
+        let responses = test::block_fn(|| {
+            let futures: Vec<_> = (0..2)
+                .map(|_| {
+                    let mut request = get_symbolication_request(vec![source.clone()]);
+                    request.options.dif_candidates = true;
+                    let request_id = service.symbolication().symbolicate_stacktraces(request);
+                    service.symbolication().get_response(request_id, None)
+                })
+                .collect();
+
+            join_all(futures)
+        })?;
+
+        for response in responses {
+            let candidates = match response {
+                Some(SymbolicationResponse::Completed(mut response)) => {
+                    response.modules.swap_remove(0).candidates
+                }
+                _ => panic!("unexpected response: {:?}", response),
+            };
+
+            assert!(candidates.iter().all(|c| !c.cache_hit));
+
+            let selected = candidates
+                .iter()
+                .find(|c| c.status == CandidateStatus::Selected)
+                .expect("selected candidate");
+            assert_eq!(selected.status_code, Some(200));
+        }
+
+        Ok(())
+    }
+
     fn stackwalk_minidump(path: &str) -> Result<(), Error> {
         let (service, _cache_dir) = setup_service();
         let (_symsrv, source) = test::symbol_server();
@@ -1522,10 +2576,12 @@ mod tests {
         let minidump = Bytes::from(fs::read(path)?);
 
         let response = test::block_fn(|| {
-            let request_id =
-                service
-                    .symbolication()
-                    .process_minidump(Scope::Global, minidump, vec![source]);
+            let request_id = service.symbolication().process_minidump(
+                Scope::Global,
+                minidump,
+                vec![source],
+                RequestOptions::default(),
+            );
             service.symbolication().get_response(request_id, None)
         })?;
 
@@ -1569,6 +2625,7 @@ mod tests {
                 Scope::Global,
                 report_file,
                 vec![source],
+                RequestOptions::default(),
             );
 
             service.symbolication().get_response(request_id, None)
@@ -1578,6 +2635,380 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_cancel_request() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+        let symbolication = service.symbolication();
+
+        let request_id =
+            symbolication.create_symbolication_request(future::empty::<_, SymbolicationError>);
+        assert!(symbolication.cancel_request(request_id));
+
+        let response = test::block_fn(|| symbolication.get_response(request_id, None))?;
+        assert!(match response {
+            Some(SymbolicationResponse::Canceled) => true,
+            _ => false,
+        });
+
+        let unknown_id = RequestId::new(uuid::Uuid::new_v4());
+        assert!(!symbolication.cancel_request(unknown_id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shut_down() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+        let symbolication = service.symbolication();
+
+        let request_id =
+            symbolication.create_symbolication_request(future::empty::<_, SymbolicationError>);
+        assert_eq!(symbolication.running_requests(), 1);
+
+        symbolication.shut_down();
+        assert!(symbolication.is_shutting_down());
+
+        // Running requests can still be polled while shutting down.
+        assert!(symbolication.cancel_request(request_id));
+        let response = test::block_fn(|| symbolication.get_response(request_id, None))?;
+        assert!(response.is_some());
+        assert_eq!(symbolication.running_requests(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coredump_frame_pointers() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+
+        // The crashing frame's rbp points to the saved rbp and return address on the stack.
+        let base = 0x7f00_0000_0000;
+        let mut stack = vec![0u8; 0x210];
+        stack[0x100..0x108].copy_from_slice(&0x1200u64.to_le_bytes());
+        stack[0x108..0x110].copy_from_slice(&(base + 0x2000u64).to_le_bytes());
+
+        let coredump = crate::utils::coredump::tests::build_core(
+            base,
+            &[("rip", base + 0x1010), ("rsp", 0x1000), ("rbp", 0x1100)],
+            &[(0x1000, stack)],
+        );
+
+        let response = test::block_fn(|| {
+            let request_id = service.symbolication().process_coredump(
+                Scope::Global,
+                Bytes::from(coredump),
+                vec![],
+                RequestOptions::default(),
+            );
+
+            service.symbolication().get_response(request_id, None)
+        })?;
+
+        let response = match response {
+            Some(SymbolicationResponse::Completed(response)) => response,
+            _ => panic!("unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            response.crash_reason.as_ref().map(String::as_str),
+            Some("SIGSEGV")
+        );
+        assert_eq!(response.modules.len(), 1);
+        assert_eq!(
+            response.modules[0].raw.code_id.as_ref().map(String::as_str),
+            Some("0102030405060708090a0b0c0d0e0f10")
+        );
+
+        let frames = &response.stacktraces[0].frames;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].raw.instruction_addr, HexValue(base + 0x1010));
+        assert_eq!(frames[1].raw.instruction_addr, HexValue(base + 0x2000));
+        assert_eq!(frames[1].raw.trust, FrameTrust::FramePointer);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ips_report() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+        let report = Bytes::from(fs::read("./tests/fixtures/apple_crash_report.ips")?);
+
+        let (request, state) =
+            service
+                .symbolication()
+                .parse_apple_crash_report(Scope::Global, report, vec![])?;
+
+        assert_eq!(request.modules.len(), 3);
+        assert_eq!(request.stacktraces.len(), 3);
+
+        let exception = &request.stacktraces[0];
+        assert_eq!(
+            exception.thread_name.as_ref().map(String::as_str),
+            Some("Last Exception Backtrace")
+        );
+        assert_eq!(exception.frames.len(), 2);
+
+        // The first frame is in an unknown image and keeps its raw offset.
+        assert_eq!(exception.frames[0].instruction_addr, HexValue(647_420));
+        assert_eq!(exception.frames[0].package, None);
+        assert_eq!(
+            exception.frames[1].instruction_addr,
+            HexValue(0x1_0264_ca34)
+        );
+
+        let crashed = &request.stacktraces[1];
+        assert_eq!(crashed.is_requesting, Some(true));
+        assert_eq!(
+            crashed.queue_name.as_ref().map(String::as_str),
+            Some("com.apple.main-thread")
+        );
+        assert_eq!(crashed.registers.get("pc"), Some(&HexValue(0x1_b8f2_9964)));
+        assert_eq!(crashed.frames[0].instruction_addr, HexValue(0x1_b8f2_9964));
+
+        let worker = &request.stacktraces[2];
+        assert_eq!(
+            worker.thread_name.as_ref().map(String::as_str),
+            Some("com.example.worker")
+        );
+
+        assert_eq!(
+            state.crash_reason.as_ref().map(String::as_str),
+            Some("EXC_CRASH (SIGABRT)")
+        );
+        assert_eq!(
+            state.crash_details.as_ref().map(String::as_str),
+            Some("abort() called")
+        );
+        assert_eq!(state.system_info.os_name, "iPhone OS");
+        assert_eq!(state.system_info.os_version, "15.0");
+        assert_eq!(state.system_info.os_build, "19A346");
+        assert_eq!(state.system_info.device_model, "iPhone12,1");
+        assert_eq!(state.system_info.cpu_arch, Arch::Arm64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ips_report_without_uuid() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+        let report = Bytes::from(
+            r#"{"bug_type":"309","timestamp":"2021-09-28 15:42:09.00 +0200"}
+{
+  "threads": [{
+    "id": 1,
+    "triggered": true,
+    "frames": [
+      {"imageIndex": 0, "imageOffset": 4096},
+      {"imageIndex": 1, "imageOffset": 8192},
+      {"imageIndex": 2, "imageOffset": 16}
+    ]
+  }],
+  "usedImages": [
+    {"base": 4335091712, "size": 65536, "name": "Anonymous"},
+    {"base": 6614216704, "size": 516096, "uuid": "5b8a7d9e-2c1f-3a4b-8e6d-7f0a1b2c3d4e", "name": "libsystem_c.dylib"}
+  ]
+}"#,
+        );
+
+        let (request, _) =
+            service
+                .symbolication()
+                .parse_apple_crash_report(Scope::Global, report, vec![])?;
+
+        // Only the image with a UUID can be symbolicated.
+        assert_eq!(request.modules.len(), 1);
+
+        let frames = &request.stacktraces[0].frames;
+        assert_eq!(frames[0].instruction_addr, HexValue(0x1_0264_5000));
+        assert_eq!(
+            frames[0].package.as_ref().map(String::as_str),
+            Some("Anonymous")
+        );
+        assert_eq!(frames[1].instruction_addr, HexValue(0x1_8a3d_1000));
+        assert_eq!(frames[2].instruction_addr, HexValue(16));
+        assert_eq!(frames[2].package, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_tombstone() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+        let tombstone = Bytes::from(fs::read("./tests/fixtures/tombstone.txt")?);
+
+        let (request, state) =
+            service
+                .symbolication()
+                .parse_tombstone(Scope::Global, tombstone, vec![])?;
+
+        assert_eq!(request.signal, Some(Signal(11)));
+        assert_eq!(request.modules.len(), 2);
+
+        let native = &request.modules[1].raw;
+        assert_eq!(
+            native.code_file.as_ref().map(String::as_str),
+            Some("/data/app/com.example.app-1/lib/arm64/libnative.so")
+        );
+        assert_eq!(native.image_addr, HexValue(0x73_daf6_c000));
+        assert_eq!(native.image_size, Some(0x2000));
+        assert_eq!(
+            native.debug_id.as_ref().map(String::as_str),
+            Some("c0bcc3f1-9827-fe65-3058-404b2831d9e6")
+        );
+
+        let crashed = &request.stacktraces[0];
+        assert_eq!(crashed.is_requesting, Some(true));
+        assert_eq!(crashed.frames[0].instruction_addr, HexValue(0x73_daf6_d0d4));
+
+        assert_eq!(
+            state.crash_reason.as_ref().map(String::as_str),
+            Some("SIGSEGV / SEGV_MAPERR")
+        );
+        assert_eq!(state.system_info.os_name, "Android");
+        assert_eq!(state.system_info.os_version, "10");
+        assert_eq!(state.system_info.os_build, "QQ1A.200105.002");
+        assert_eq!(state.system_info.device_model, "sargo");
+        assert_eq!(state.system_info.cpu_arch, Arch::Arm64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_tombstone_overflow() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+        let tombstone = Bytes::from(
+            r#"*** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***
+pid: 1, tid: 1, name: example  >>> example <<<
+signal 11 (SIGSEGV), code 1 (SEGV_MAPERR), fault addr 0x0
+
+backtrace:
+      #00 pc ffffffffffffffff  /system/lib64/libfoo.so (BuildId: f1c3bcc0279865fe3058404b2831d9e64135386c)
+      #01 pc 0000000000001000  /system/lib64/libbar.so (BuildId: 2f4f5e6b8a1d4c2e9b0f3a7c6d5e4f3a)
+      #02 pc 0000000000001000  /system/lib64/libbaz.so (BuildId: 2f4f5e6b8a1d4c2e9b0f3a7c6d5e4f3a)
+
+memory map (2 entries):
+    ffffffff'fffff000-ffffffff'ffffffff r-x         0      1000  /system/lib64/libinvalid.so (BuildId: 2f4f5e6b8a1d4c2e9b0f3a7c6d5e4f3a)
+    ffffffff'e0000000-ffffffff'efffffff r-x         0  10000000  /system/lib64/libfoo.so (BuildId: f1c3bcc0279865fe3058404b2831d9e64135386c)
+"#,
+        );
+
+        let (request, _) =
+            service
+                .symbolication()
+                .parse_tombstone(Scope::Global, tombstone, vec![])?;
+
+        // The mapping at the end of the address space is skipped, and there is only room for one
+        // synthetic module after libfoo.so.
+        let files: Vec<_> = request
+            .modules
+            .iter()
+            .filter_map(|module| module.raw.code_file.as_ref().map(String::as_str))
+            .collect();
+        assert_eq!(
+            files,
+            ["/system/lib64/libbar.so", "/system/lib64/libfoo.so"]
+        );
+
+        // The first frame overflows the address space and is dropped.
+        let frames = &request.stacktraces[0].frames;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].instruction_addr, HexValue(0xffff_ffff_f000_1000));
+        assert_eq!(frames[1].instruction_addr, HexValue(0x1000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_debuginfod_source_context() -> Result<(), Error> {
+        let (service, _cache_dir) = setup_service();
+
+        // Serve a stand-in debuginfod server from a temporary directory.
+        let debuginfod_dir = test::tempdir();
+        let source_path = debuginfod_dir
+            .path()
+            .join("buildid/dfb85de42daffd09640c8fe377d572de3e168920/source/src/main.c");
+        fs::create_dir_all(source_path.parent().unwrap())?;
+        fs::write(&source_path, "int main() {\n    return 0;\n}\n")?;
+
+        let root = debuginfod_dir.path().to_owned();
+        let server = actix_http_test::TestServer::new(move || {
+            actix_http::HttpService::new(
+                actix_web::App::new()
+                    .service(actix_files::Files::new("/debuginfod/", root.clone())),
+            )
+        });
+
+        let source = SourceConfig::Http(Arc::new(HttpSourceConfig {
+            id: "debuginfod".to_owned(),
+            url: server.url("/debuginfod/").parse().unwrap(),
+            headers: Default::default(),
+            auth: None,
+            tls: None,
+            redirects: Default::default(),
+            files: CommonSourceConfig {
+                layout: DirectoryLayout {
+                    ty: DirectoryLayoutType::Debuginfod,
+                    casing: Default::default(),
+                    template: None,
+                },
+                ..Default::default()
+            },
+        }));
+
+        let source_lookup: SourceLookup = vec![CompleteObjectInfo::from(RawObjectInfo {
+            ty: ObjectType("elf".to_owned()),
+            code_id: Some("dfb85de42daffd09640c8fe377d572de3e168920".to_owned()),
+            debug_id: None,
+            code_file: None,
+            debug_file: None,
+            image_addr: HexValue(0x1000),
+            image_size: Some(4096),
+        })]
+        .into_iter()
+        .collect();
+
+        let response = CompletedSymbolicationResponse {
+            stacktraces: vec![CompleteStacktrace {
+                frames: vec![SymbolicatedFrame {
+                    status: FrameStatus::Symbolicated,
+                    original_index: Some(0),
+                    raw: RawFrame {
+                        instruction_addr: HexValue(0x1010),
+                        abs_path: Some("/src/main.c".to_owned()),
+                        lineno: Some(2),
+                        ..RawFrame::default()
+                    },
+                }],
+                ..CompleteStacktrace::default()
+            }],
+            ..CompletedSymbolicationResponse::default()
+        };
+
+        let source_lookup = test::block_fn(|| {
+            source_lookup.fetch_debuginfod_sources(
+                service.objects(),
+                Scope::Global,
+                Arc::new(vec![source]),
+                &response,
+            )
+        })?;
+
+        let debug_sessions = source_lookup.prepare_debug_sessions();
+        let context_lines =
+            source_lookup.get_context_lines(&debug_sessions, 0x1010, "/src/main.c", 2, 5);
+
+        assert_eq!(
+            context_lines,
+            Some((
+                vec!["int main() {".to_owned()],
+                "    return 0;".to_owned(),
+                vec!["}".to_owned()]
+            ))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_symcache_lookup_open_end_addr() {
         // The Rust SDK and some other clients sometimes send zero-sized images when no end addr
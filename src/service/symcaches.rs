@@ -16,7 +16,7 @@ use crate::service::cache::{CacheItemRequest, Cacher};
 use crate::service::objects::{
     FindObject, ObjectFile, ObjectFileMeta, ObjectPurpose, ObjectsActor,
 };
-use crate::types::{FileType, ObjectId, ObjectType, Scope, SourceConfig};
+use crate::types::{FileType, ObjectCandidate, ObjectId, ObjectType, Scope, SourceConfig};
 use crate::utils::futures::{FutureExt, SendFuture, ThreadPool};
 use crate::utils::sentry::ToSentryScope;
 
@@ -73,6 +73,7 @@ pub struct SymCacheFile {
     data: ByteView<'static>,
     status: CacheStatus,
     arch: Arch,
+    candidates: Vec<ObjectCandidate>,
 }
 
 impl SymCacheFile {
@@ -80,6 +81,7 @@ impl SymCacheFile {
         match self.status {
             CacheStatus::Negative => Ok(None),
             CacheStatus::Malformed => Err(SymCacheErrorKind::ObjectParsing.into()),
+            CacheStatus::DownloadError => Err(SymCacheErrorKind::Fetching.into()),
             CacheStatus::Positive => Ok(Some(
                 SymCache::parse(&self.data).context(SymCacheErrorKind::Parsing)?,
             )),
@@ -90,6 +92,11 @@ impl SymCacheFile {
     pub fn arch(&self) -> Arch {
         self.arch
     }
+
+    /// Returns all locations that were searched for the object file of this symcache.
+    pub fn candidates(&self) -> &[ObjectCandidate] {
+        &self.candidates
+    }
 }
 
 fn compute_symcache(
@@ -170,6 +177,7 @@ impl CacheItemRequest for FetchSymCacheInternal {
             data,
             status,
             arch,
+            candidates: Vec::new(),
         }
     }
 }
@@ -206,25 +214,35 @@ impl SymCacheActor {
         let identifier = request.identifier.clone();
         let scope = request.scope.clone();
 
-        let future = object.and_then(move |object| {
-            object
-                .map(move |object| {
-                    Either::A(symcaches.compute_memoized(FetchSymCacheInternal {
-                        request,
-                        objects_actor: objects,
-                        object_meta: object,
-                    }))
-                })
-                .unwrap_or_else(move || {
-                    Either::B(future::ok(Arc::new(SymCacheFile {
-                        object_type,
-                        identifier,
-                        scope,
-                        data: ByteView::from_slice(b""),
-                        status: CacheStatus::Negative,
-                        arch: Arch::Unknown,
-                    })))
-                })
+        let future = object.and_then(move |found| {
+            let candidates = found.candidates;
+            match found.meta {
+                Some(object) => Either::A(
+                    symcaches
+                        .compute_memoized(FetchSymCacheInternal {
+                            request,
+                            objects_actor: objects,
+                            object_meta: object,
+                        })
+                        .map(move |symcache| {
+                            // The cached symcache is shared, so attach the candidates of this
+                            // lookup to a copy.
+                            Arc::new(SymCacheFile {
+                                candidates,
+                                ..(*symcache).clone()
+                            })
+                        }),
+                ),
+                None => Either::B(future::ok(Arc::new(SymCacheFile {
+                    object_type,
+                    identifier,
+                    scope,
+                    data: ByteView::from_slice(b""),
+                    status: CacheStatus::Negative,
+                    arch: Arch::Unknown,
+                    candidates,
+                }))),
+            }
         });
 
         Box::new(future)
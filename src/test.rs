@@ -33,7 +33,10 @@ use log::LevelFilter;
 use crate::config::Config;
 use crate::server::create_app;
 use crate::service::Service;
-use crate::types::{FilesystemSourceConfig, HttpSourceConfig, SourceConfig};
+use crate::types::{
+    CommonSourceConfig, DirectoryLayout, DirectoryLayoutType, FilesystemSourceConfig,
+    HttpSourceConfig, SourceConfig,
+};
 
 pub use actix_web::test::*;
 pub use tempfile::TempDir;
@@ -72,7 +75,15 @@ pub(crate) fn tempdir() -> TempDir {
 pub(crate) fn test_service(
     config: Config,
 ) -> impl ActixService<Request = Request, Response = ServiceResponse<Body>, Error = Error> {
-    let service = Service::create(config);
+    test_app(Service::create(config))
+}
+
+/// Creates a test service running the full application for an existing service.
+///
+/// Like `test_service`, but allows to access the service's state while handling requests.
+pub(crate) fn test_app(
+    service: Service,
+) -> impl ActixService<Request = Request, Response = ServiceResponse<Body>, Error = Error> {
     actix_web::test::init_service(create_app(service))
 }
 
@@ -88,6 +99,24 @@ pub(crate) fn local_source() -> SourceConfig {
     }))
 }
 
+/// Get bucket configuration for the debuginfod files in the local fixtures.
+///
+/// Like `local_source`, but uses the `debuginfod` layout.
+pub(crate) fn local_debuginfod_source() -> SourceConfig {
+    SourceConfig::Filesystem(Arc::new(FilesystemSourceConfig {
+        id: "local".to_owned(),
+        path: PathBuf::from(SYMBOLS_PATH),
+        files: CommonSourceConfig {
+            layout: DirectoryLayout {
+                ty: DirectoryLayoutType::Debuginfod,
+                casing: Default::default(),
+                template: None,
+            },
+            ..Default::default()
+        },
+    }))
+}
+
 /// Spawn an actual HTTP symbol server for local fixtures.
 ///
 /// The symbol server serves static files from the local symbols fixture location under the
@@ -111,6 +140,9 @@ pub(crate) fn symbol_server() -> (actix_http_test::TestServerRuntime, SourceConf
         id: "local".to_owned(),
         url: server.url("/download/").parse().unwrap(),
         headers: Default::default(),
+        auth: None,
+        tls: None,
+        redirects: Default::default(),
         files: Default::default(),
     }));
 
@@ -0,0 +1,612 @@
+//! Reader for Linux ELF core dumps.
+//!
+//! Core files are ELF files of type `ET_CORE`. Their `PT_NOTE` segments contain the register
+//! state of every thread (`NT_PRSTATUS`) and the list of mapped files (`NT_FILE`), while their
+//! `PT_LOAD` segments contain the dumped memory of the process. By default, the kernel and
+//! systemd-coredump include the first page of every mapped ELF file, which allows to read the
+//! build ids of all loaded modules.
+//!
+//! Only 64-bit little endian cores for x86_64 and AArch64 are supported.
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::ops::Range;
+
+use failure::Fail;
+use symbolic::common::Arch;
+
+use crate::utils::unwind::Memory;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NT_FILE: u32 = 0x4649_4c45;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// Offset of `pr_cursig` in `struct elf_prstatus`.
+const PRSTATUS_CURSIG_OFFSET: usize = 12;
+/// Offset of `pr_pid` in `struct elf_prstatus`.
+const PRSTATUS_PID_OFFSET: usize = 32;
+/// Offset of `pr_reg` in `struct elf_prstatus`.
+const PRSTATUS_REG_OFFSET: usize = 112;
+
+/// Registers in the order of `struct user_regs_struct` on x86_64.
+const X86_64_REGISTERS: &[&str] = &[
+    "r15", "r14", "r13", "r12", "rbp", "rbx", "r11", "r10", "r9", "r8", "rax", "rcx", "rdx", "rsi",
+    "rdi", "orig_rax", "rip", "cs", "eflags", "rsp", "ss", "fs_base", "gs_base", "ds", "es", "fs",
+    "gs",
+];
+
+/// Registers in the order of `struct user_pt_regs` on AArch64.
+const AARCH64_REGISTERS: &[&str] = &[
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27",
+    "x28", "x29", "x30", "sp", "pc", "pstate",
+];
+
+/// An error returned when parsing a core file.
+#[derive(Debug, Fail)]
+pub enum CoreError {
+    /// The file is not an ELF core file.
+    #[fail(display = "not an ELF core file")]
+    InvalidFormat,
+
+    /// The core file is for an unsupported platform.
+    #[fail(display = "unsupported core file platform")]
+    Unsupported,
+
+    /// The core file is truncated.
+    #[fail(display = "truncated core file")]
+    Truncated,
+
+    /// The core file does not contain any thread.
+    #[fail(display = "no threads in core file")]
+    MissingThread,
+}
+
+/// A thread of the crashed process.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CoreThread {
+    /// The thread id.
+    pub tid: u32,
+    /// The signal that was delivered to this thread, or `0`.
+    pub signal: u32,
+    /// Register values at the time of the crash.
+    pub registers: BTreeMap<String, u64>,
+}
+
+/// A file mapped into the crashed process.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CoreMapping {
+    /// Start address of the mapping.
+    pub start: u64,
+    /// End address of the mapping (exclusive).
+    pub end: u64,
+    /// Offset of the mapping in the file, in bytes.
+    pub offset: u64,
+    /// Path to the mapped file.
+    pub path: String,
+}
+
+/// An ELF module loaded into the crashed process.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CoreModule {
+    /// Path to the module.
+    pub path: String,
+    /// Load address of the module.
+    pub start: u64,
+    /// End address of the last mapping of the module.
+    pub end: u64,
+    /// GNU build id of the module, if its note was included in the core.
+    pub build_id: Option<String>,
+}
+
+/// A `PT_LOAD` segment of the core file.
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    vaddr: u64,
+    offset: u64,
+    filesz: u64,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Returns the range of `size` bytes at `offset` in the file, unless it overflows.
+fn file_range(offset: u64, size: u64) -> Option<Range<usize>> {
+    let start: usize = offset.try_into().ok()?;
+    let end = start.checked_add(size.try_into().ok()?)?;
+    Some(start..end)
+}
+
+/// A note in a `PT_NOTE` segment.
+struct Note<'data> {
+    ty: u32,
+    name: &'data [u8],
+    desc: &'data [u8],
+}
+
+/// Iterates the notes in a `PT_NOTE` segment. Stops at the first malformed note.
+struct NoteIter<'data> {
+    data: &'data [u8],
+    offset: usize,
+}
+
+impl<'data> NoteIter<'data> {
+    fn new(data: &'data [u8]) -> Self {
+        NoteIter { data, offset: 0 }
+    }
+
+    fn align(value: usize) -> Option<usize> {
+        Some(value.checked_add(3)? & !3)
+    }
+}
+
+impl<'data> Iterator for NoteIter<'data> {
+    type Item = Note<'data>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.data.get(self.offset..)?;
+        let name_size = read_u32(header, 0)? as usize;
+        let desc_size = read_u32(header, 4)? as usize;
+        let ty = read_u32(header, 8)?;
+
+        let name_start = self.offset.checked_add(12)?;
+        let name_end = name_start.checked_add(name_size)?;
+        let desc_start = Self::align(name_end)?;
+        let desc_end = desc_start.checked_add(desc_size)?;
+
+        let name = self.data.get(name_start..name_end)?;
+        let desc = self.data.get(desc_start..desc_end)?;
+        self.offset = Self::align(desc_end)?;
+
+        // The name is NUL-terminated.
+        let name = name.split(|b| *b == 0).next().unwrap_or_default();
+        Some(Note { ty, name, desc })
+    }
+}
+
+fn parse_prstatus(desc: &[u8], register_names: &[&str]) -> Option<CoreThread> {
+    let mut registers = BTreeMap::new();
+    for (index, name) in register_names.iter().enumerate() {
+        let value = read_u64(desc, PRSTATUS_REG_OFFSET + index * 8)?;
+        registers.insert((*name).to_owned(), value);
+    }
+
+    Some(CoreThread {
+        tid: read_u32(desc, PRSTATUS_PID_OFFSET)?,
+        signal: u32::from(read_u16(desc, PRSTATUS_CURSIG_OFFSET)?),
+        registers,
+    })
+}
+
+fn parse_file_note(desc: &[u8]) -> Option<Vec<CoreMapping>> {
+    let count = read_u64(desc, 0)? as usize;
+    let page_size = read_u64(desc, 8)?;
+
+    let names_start = count.checked_mul(24)?.checked_add(16)?;
+    let mut names = desc.get(names_start..)?.split(|b| *b == 0);
+
+    let mut mappings = Vec::with_capacity(count.min(desc.len() / 24));
+    for index in 0..count {
+        let entry = 16 + index * 24;
+        let name = names.next()?;
+
+        mappings.push(CoreMapping {
+            start: read_u64(desc, entry)?,
+            end: read_u64(desc, entry + 8)?,
+            offset: read_u64(desc, entry + 16)?.checked_mul(page_size)?,
+            path: String::from_utf8_lossy(name).into_owned(),
+        });
+    }
+
+    Some(mappings)
+}
+
+/// A parsed Linux ELF core file.
+#[derive(Debug)]
+pub struct CoreFile<'data> {
+    data: &'data [u8],
+    arch: Arch,
+    segments: Vec<Segment>,
+    threads: Vec<CoreThread>,
+    mappings: Vec<CoreMapping>,
+}
+
+impl<'data> CoreFile<'data> {
+    /// Checks whether the given data looks like an ELF core file.
+    pub fn test(data: &[u8]) -> bool {
+        data.starts_with(ELF_MAGIC) && read_u16(data, 16) == Some(ET_CORE)
+    }
+
+    /// Parses the threads, mappings and memory segments of a core file.
+    pub fn parse(data: &'data [u8]) -> Result<Self, CoreError> {
+        if !Self::test(data) {
+            return Err(CoreError::InvalidFormat);
+        }
+
+        if data.get(4) != Some(&ELFCLASS64) || data.get(5) != Some(&ELFDATA2LSB) {
+            return Err(CoreError::Unsupported);
+        }
+
+        let (arch, register_names) = match read_u16(data, 18) {
+            Some(EM_X86_64) => (Arch::Amd64, X86_64_REGISTERS),
+            Some(EM_AARCH64) => (Arch::Arm64, AARCH64_REGISTERS),
+            _ => return Err(CoreError::Unsupported),
+        };
+
+        let phoff = read_u64(data, 0x20)
+            .and_then(|phoff| phoff.try_into().ok())
+            .ok_or(CoreError::Truncated)?;
+        let phentsize = read_u16(data, 0x36).ok_or(CoreError::Truncated)? as usize;
+        let phnum = read_u16(data, 0x38).ok_or(CoreError::Truncated)? as usize;
+
+        let mut core = CoreFile {
+            data,
+            arch,
+            segments: Vec::new(),
+            threads: Vec::new(),
+            mappings: Vec::new(),
+        };
+
+        for index in 0..phnum {
+            let header = index
+                .checked_mul(phentsize)
+                .and_then(|offset| offset.checked_add(phoff))
+                .and_then(|offset| data.get(offset..))
+                .ok_or(CoreError::Truncated)?;
+
+            let p_type = read_u32(header, 0).ok_or(CoreError::Truncated)?;
+            let offset = read_u64(header, 8).ok_or(CoreError::Truncated)?;
+            let vaddr = read_u64(header, 16).ok_or(CoreError::Truncated)?;
+            let filesz = read_u64(header, 32).ok_or(CoreError::Truncated)?;
+
+            match p_type {
+                PT_LOAD => core.segments.push(Segment {
+                    vaddr,
+                    offset,
+                    filesz,
+                }),
+                PT_NOTE => {
+                    let notes = file_range(offset, filesz)
+                        .and_then(|range| data.get(range))
+                        .ok_or(CoreError::Truncated)?;
+
+                    for note in NoteIter::new(notes) {
+                        if note.name != b"CORE" {
+                            continue;
+                        }
+
+                        match note.ty {
+                            NT_PRSTATUS => {
+                                let thread = parse_prstatus(note.desc, register_names)
+                                    .ok_or(CoreError::Truncated)?;
+                                core.threads.push(thread);
+                            }
+                            NT_FILE => {
+                                core.mappings =
+                                    parse_file_note(note.desc).ok_or(CoreError::Truncated)?;
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if core.threads.is_empty() {
+            return Err(CoreError::MissingThread);
+        }
+
+        Ok(core)
+    }
+
+    /// The CPU architecture of the crashed process.
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    /// All threads of the crashed process. The thread that received the signal comes first.
+    pub fn threads(&self) -> &[CoreThread] {
+        &self.threads
+    }
+
+    /// All files mapped into the crashed process.
+    pub fn mappings(&self) -> &[CoreMapping] {
+        &self.mappings
+    }
+
+    /// Reads `size` bytes of process memory at `addr`, if they were included in the core.
+    pub fn read(&self, addr: u64, size: u64) -> Option<&'data [u8]> {
+        let end = addr.checked_add(size)?;
+        let segment = self.segments.iter().find(|segment| {
+            let segment_end = segment.vaddr.checked_add(segment.filesz);
+            addr >= segment.vaddr && segment_end.map_or(false, |segment_end| end <= segment_end)
+        })?;
+
+        let offset = segment.offset.checked_add(addr - segment.vaddr)?;
+        self.data.get(file_range(offset, size)?)
+    }
+
+    /// Reads a 64-bit value of process memory at `addr`.
+    pub fn read_u64(&self, addr: u64) -> Option<u64> {
+        read_u64(self.read(addr, 8)?, 0)
+    }
+
+    /// Reads the GNU build id from the ELF headers mapped at `base`.
+    fn read_build_id(&self, base: u64) -> Option<String> {
+        let header = self.read(base, 0x40)?;
+        let phoff = read_u64(header, 0x20)?;
+        let phentsize = u64::from(read_u16(header, 0x36)?);
+        let phnum = u64::from(read_u16(header, 0x38)?);
+        let headers = self.read(base.checked_add(phoff)?, phentsize.checked_mul(phnum)?)?;
+
+        let program_headers = (0..phnum as usize).filter_map(move |index| {
+            let header = headers.get(index.checked_mul(phentsize as usize)?..)?;
+            let p_type = read_u32(header, 0)?;
+            let vaddr = read_u64(header, 16)?;
+            let filesz = read_u64(header, 32)?;
+            Some((p_type, vaddr, filesz))
+        });
+
+        // Shared libraries are linked at address zero, while executables are linked at their load
+        // address. The difference to the first loaded segment is the load bias.
+        let min_vaddr = program_headers
+            .clone()
+            .filter(|&(p_type, _, _)| p_type == PT_LOAD)
+            .map(|(_, vaddr, _)| vaddr)
+            .min()?;
+        let bias = base.wrapping_sub(min_vaddr);
+
+        program_headers
+            .filter(|&(p_type, _, _)| p_type == PT_NOTE)
+            .filter_map(|(_, vaddr, filesz)| self.read(bias.wrapping_add(vaddr), filesz))
+            .flat_map(NoteIter::new)
+            .find(|note| note.ty == NT_GNU_BUILD_ID && note.name == b"GNU")
+            .map(|note| note.desc.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Returns all ELF modules loaded into the crashed process.
+    ///
+    /// Mapped files are merged by path. Files whose ELF header was not included in the core are
+    /// skipped, since they cannot be identified.
+    pub fn modules(&self) -> Vec<CoreModule> {
+        let mut modules = Vec::<CoreModule>::new();
+
+        for mapping in &self.mappings {
+            if let Some(module) = modules.iter_mut().find(|m| m.path == mapping.path) {
+                module.end = module.end.max(mapping.end);
+                continue;
+            }
+
+            if mapping.offset != 0 || self.read(mapping.start, 4) != Some(ELF_MAGIC) {
+                continue;
+            }
+
+            modules.push(CoreModule {
+                path: mapping.path.clone(),
+                start: mapping.start,
+                end: mapping.end,
+                build_id: self.read_build_id(mapping.start),
+            });
+        }
+
+        modules
+    }
+}
+
+impl Memory for CoreFile<'_> {
+    fn read_u64(&self, addr: u64) -> Option<u64> {
+        CoreFile::read_u64(self, addr)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn push_note(buf: &mut Vec<u8>, ty: u32, name: &[u8], desc: &[u8]) {
+        buf.extend(&(name.len() as u32 + 1).to_le_bytes());
+        buf.extend(&(desc.len() as u32).to_le_bytes());
+        buf.extend(&ty.to_le_bytes());
+        buf.extend(name);
+        buf.push(0);
+        while buf.len() % 4 != 0 {
+            buf.push(0);
+        }
+        buf.extend(desc);
+        while buf.len() % 4 != 0 {
+            buf.push(0);
+        }
+    }
+
+    fn push_program_header(buf: &mut Vec<u8>, ty: u32, offset: u64, vaddr: u64, size: u64) {
+        buf.extend(&ty.to_le_bytes());
+        buf.extend(&0u32.to_le_bytes());
+        for value in &[offset, vaddr, vaddr, size, size, 0] {
+            buf.extend(&value.to_le_bytes());
+        }
+    }
+
+    fn elf_header(ty: u16, phnum: u16) -> Vec<u8> {
+        let mut header = vec![0u8; 0x40];
+        header[..4].copy_from_slice(ELF_MAGIC);
+        header[4] = ELFCLASS64;
+        header[5] = ELFDATA2LSB;
+        header[16..18].copy_from_slice(&ty.to_le_bytes());
+        header[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+        header[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes());
+        header[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        header[0x38..0x3a].copy_from_slice(&phnum.to_le_bytes());
+        header
+    }
+
+    /// Builds an x86_64 core file with a single thread and the given memory segments.
+    ///
+    /// The first page of the memory at `module_base` contains the ELF header of a shared library
+    /// mapped from `/usr/lib/libfoo.so`, with build id `010203...10`.
+    pub(crate) fn build_core(
+        module_base: u64,
+        registers: &[(&str, u64)],
+        memory: &[(u64, Vec<u8>)],
+    ) -> Vec<u8> {
+        let mut prstatus = vec![0u8; PRSTATUS_REG_OFFSET + X86_64_REGISTERS.len() * 8 + 8];
+        prstatus[PRSTATUS_CURSIG_OFFSET..PRSTATUS_CURSIG_OFFSET + 2]
+            .copy_from_slice(&11u16.to_le_bytes());
+        prstatus[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4]
+            .copy_from_slice(&4242u32.to_le_bytes());
+        for (name, value) in registers {
+            let index = X86_64_REGISTERS.iter().position(|r| r == name).unwrap();
+            let offset = PRSTATUS_REG_OFFSET + index * 8;
+            prstatus[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let mut file = Vec::new();
+        for value in &[2u64, 0x1000, module_base, module_base + 0x1000, 0] {
+            file.extend(&value.to_le_bytes());
+        }
+        for value in &[module_base + 0x1000, module_base + 0x3000, 1] {
+            file.extend(&value.to_le_bytes());
+        }
+        file.extend(b"/usr/lib/libfoo.so\0/usr/lib/libfoo.so\0");
+
+        let mut notes = Vec::new();
+        push_note(&mut notes, NT_PRSTATUS, b"CORE", &prstatus);
+        push_note(&mut notes, NT_FILE, b"CORE", &file);
+
+        // The module's first page: ELF header, a PT_LOAD and a PT_NOTE with the build id.
+        let mut module = elf_header(3, 2);
+        push_program_header(&mut module, PT_LOAD, 0, 0, 0x3000);
+        push_program_header(&mut module, PT_NOTE, 0xb0, 0xb0, 0x20);
+        push_note(
+            &mut module,
+            NT_GNU_BUILD_ID,
+            b"GNU",
+            &(1..=16).collect::<Vec<u8>>(),
+        );
+
+        let mut segments = vec![(module_base, module)];
+        segments.extend(memory.iter().cloned());
+
+        let phnum = 1 + segments.len();
+        let mut offset = (0x40 + phnum * 56) as u64;
+        let mut core = elf_header(ET_CORE, phnum as u16);
+
+        push_program_header(&mut core, PT_NOTE, offset, 0, notes.len() as u64);
+        offset += notes.len() as u64;
+        for (vaddr, data) in &segments {
+            push_program_header(&mut core, PT_LOAD, offset, *vaddr, data.len() as u64);
+            offset += data.len() as u64;
+        }
+
+        core.extend(notes);
+        for (_, data) in segments {
+            core.extend(data);
+        }
+
+        core
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = build_core(0x7f00_0000_0000, &[("rip", 0x7f00_0000_1010)], &[]);
+        let core = CoreFile::parse(&data).unwrap();
+
+        assert_eq!(core.arch(), Arch::Amd64);
+        assert_eq!(core.threads().len(), 1);
+
+        let thread = &core.threads()[0];
+        assert_eq!(thread.tid, 4242);
+        assert_eq!(thread.signal, 11);
+        assert_eq!(thread.registers.get("rip"), Some(&0x7f00_0000_1010));
+        assert_eq!(thread.registers.get("rsp"), Some(&0));
+
+        assert_eq!(core.mappings().len(), 2);
+        assert_eq!(core.mappings()[1].offset, 0x1000);
+    }
+
+    #[test]
+    fn test_modules() {
+        let data = build_core(0x7f00_0000_0000, &[], &[]);
+        let core = CoreFile::parse(&data).unwrap();
+
+        assert_eq!(
+            core.modules(),
+            vec![CoreModule {
+                path: "/usr/lib/libfoo.so".to_owned(),
+                start: 0x7f00_0000_0000,
+                end: 0x7f00_0000_3000,
+                build_id: Some("0102030405060708090a0b0c0d0e0f10".to_owned()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_read_memory() {
+        let memory = vec![(0x1000, 0xdead_beef_u64.to_le_bytes().to_vec())];
+        let data = build_core(0x7f00_0000_0000, &[], &memory);
+        let core = CoreFile::parse(&data).unwrap();
+
+        assert_eq!(core.read_u64(0x1000), Some(0xdead_beef));
+        assert_eq!(core.read_u64(0x1004), None);
+        assert_eq!(core.read_u64(0x2000), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(!CoreFile::test(b"MDMP"));
+        assert!(CoreFile::parse(&elf_header(3, 0)).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = build_core(0x7f00_0000_0000, &[], &[]);
+
+        // Parsing a truncated core must fail gracefully at any length.
+        for len in 0..data.len() {
+            let _ = CoreFile::parse(&data[..len]);
+        }
+    }
+
+    #[test]
+    fn test_huge_offsets() {
+        let mut data = build_core(0x7f00_0000_0000, &[], &[]);
+        data[0x20..0x28].copy_from_slice(&(u64::max_value() - 8).to_le_bytes());
+        assert!(match CoreFile::parse(&data) {
+            Err(CoreError::Truncated) => true,
+            _ => false,
+        });
+
+        // Point the note segment at the end of the address space.
+        let mut data = build_core(0x7f00_0000_0000, &[], &[]);
+        data[0x48..0x50].copy_from_slice(&(u64::max_value() - 8).to_le_bytes());
+        assert!(match CoreFile::parse(&data) {
+            Err(CoreError::Truncated) => true,
+            _ => false,
+        });
+
+        let data = build_core(0x7f00_0000_0000, &[], &[]);
+        let core = CoreFile::parse(&data).unwrap();
+        assert_eq!(core.read(u64::max_value() - 4, 8), None);
+    }
+}
@@ -1,11 +1,14 @@
 use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use actix_rt::Arbiter;
 use cadence::{prelude::*, Metric, MetricBuilder};
-use futures::{future, sync::oneshot, Async, Future, IntoFuture, Poll};
+use futures::{future, future::Either, sync::oneshot, Async, Future, IntoFuture, Poll};
+use parking_lot::Mutex;
 use tokio::runtime::Runtime as TokioRuntime;
 use tokio::timer::Timeout as TokioTimeout;
 
@@ -146,6 +149,20 @@ impl ThreadPool {
             None => actix_rt::spawn(future),
         }
     }
+
+    /// Runs a trivial task on the pool.
+    ///
+    /// The returned future resolves once the task has run, which indicates that the pool is
+    /// responsive. If all threads are blocked, it does not resolve.
+    pub fn ping(&self) -> impl Future<Item = (), Error = ()> {
+        let (sender, receiver) = oneshot::channel();
+        self.spawn(future::lazy(move || {
+            sender.send(()).ok();
+            Ok(())
+        }));
+
+        receiver.map_err(|_| ())
+    }
 }
 
 /// Execute a callback on dropping of the container type.
@@ -165,9 +182,115 @@ impl CallOnDrop {
     }
 }
 
+impl fmt::Debug for CallOnDrop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallOnDrop").finish()
+    }
+}
+
 impl Drop for CallOnDrop {
     fn drop(&mut self) {
-        (self.f.take().unwrap())();
+        if let Some(f) = self.f.take() {
+            f();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LimitState {
+    running: usize,
+    waiting: VecDeque<oneshot::Sender<CallOnDrop>>,
+}
+
+#[derive(Debug)]
+struct LimitInner {
+    max: usize,
+    state: Mutex<LimitState>,
+}
+
+/// Limits the number of futures that run concurrently.
+///
+/// Futures passed to `run` wait until fewer than `max` futures are running. Waiting futures are
+/// started in the order in which they were submitted. Clones of this limit share their state.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimit {
+    inner: Arc<LimitInner>,
+}
+
+impl ConcurrencyLimit {
+    /// Creates a new limit that runs at most `max` futures at a time.
+    pub fn new(max: usize) -> Self {
+        ConcurrencyLimit {
+            inner: Arc::new(LimitInner {
+                max: max.max(1),
+                state: Mutex::new(LimitState::default()),
+            }),
+        }
+    }
+
+    /// Returns the maximum number of concurrently running futures.
+    pub fn max(&self) -> usize {
+        self.inner.max
+    }
+
+    /// Creates a permit that passes on its slot when dropped.
+    fn permit(&self) -> CallOnDrop {
+        let limit = self.clone();
+        CallOnDrop::new(move || limit.release())
+    }
+
+    /// Hands the slot of a finished future to the next waiting future.
+    ///
+    /// Waiting futures that have been dropped in the meanwhile are skipped.
+    fn release(&self) {
+        loop {
+            let sender = {
+                let mut state = self.inner.state.lock();
+                match state.waiting.pop_front() {
+                    Some(sender) => sender,
+                    None => {
+                        state.running -= 1;
+                        return;
+                    }
+                }
+            };
+
+            match sender.send(self.permit()) {
+                Ok(()) => return,
+                // The permit is returned if the receiver is gone. Disarm it instead of dropping,
+                // which would release the slot recursively.
+                Err(mut permit) => {
+                    permit.f.take();
+                }
+            }
+        }
+    }
+
+    fn acquire(&self) -> impl Future<Item = Option<CallOnDrop>, Error = ()> {
+        let mut state = self.inner.state.lock();
+        if state.running < self.inner.max {
+            state.running += 1;
+            return Either::A(future::ok(Some(self.permit())));
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        state.waiting.push_back(sender);
+        Either::B(receiver.then(|result| Ok(result.ok())))
+    }
+
+    /// Runs the future once a slot is available.
+    ///
+    /// The slot is released as soon as the future resolves or is dropped.
+    pub fn run<F>(&self, future: F) -> impl Future<Item = F::Item, Error = F::Error>
+    where
+        F: Future,
+    {
+        self.acquire().then(move |permit| {
+            future.then(move |result| {
+                drop(permit);
+                result
+            })
+        })
     }
 }
 
@@ -502,3 +625,64 @@ pub type ResultFuture<T, E> = Box<dyn Future<Item = T, Error = E>>;
 ///
 /// This future can be shared across threads, which makes it eligible for the use in thread pools.
 pub type SendFuture<T, E> = Box<dyn Future<Item = T, Error = E> + Send + 'static>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    use tokio::timer::Delay;
+
+    use crate::test;
+
+    #[test]
+    fn test_concurrency_limit() {
+        let limit = ConcurrencyLimit::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(Mutex::new(0));
+
+        let futures = (0..5).map(|_| {
+            let running = running.clone();
+            let max_running = max_running.clone();
+
+            limit.run(future::lazy(move || {
+                let current = running.fetch_add(1, Ordering::SeqCst) + 1;
+                let mut max_running = max_running.lock();
+                *max_running = (*max_running).max(current);
+
+                Delay::new(Instant::now() + Duration::from_millis(10)).then(move |_| {
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok::<_, ()>(())
+                })
+            }))
+        });
+
+        let futures: Vec<_> = futures.collect();
+        test::block_fn(|| future::join_all(futures)).unwrap();
+
+        assert_eq!(*max_running.lock(), 2);
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_concurrency_limit_dropped_waiters() {
+        let limit = ConcurrencyLimit::new(1);
+        let permit = limit.acquire().wait().unwrap();
+
+        // Releasing the slot skips all dropped waiters without recursing for each of them.
+        let waiting: Vec<_> = (0..100_000).map(|_| limit.acquire()).collect();
+        drop(waiting);
+        drop(permit);
+
+        {
+            let state = limit.inner.state.lock();
+            assert_eq!(state.running, 0);
+            assert!(state.waiting.is_empty());
+        }
+
+        let permit = limit.acquire().wait().unwrap();
+        assert!(permit.is_some());
+        assert_eq!(limit.inner.state.lock().running, 1);
+    }
+}
@@ -0,0 +1,317 @@
+//! Parser for Apple crash reports in the JSON based `.ips` format.
+//!
+//! Since iOS 15 and macOS 12, crash reports are written as two concatenated JSON documents: a
+//! single line header with metadata on the report, followed by the body of the report. Frames
+//! reference images by their index in `usedImages` and an offset relative to the image base.
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, FixedOffset};
+use failure::Fail;
+use serde::Deserialize;
+use serde_json::Value;
+use symbolic::common::Uuid;
+
+/// An error returned when parsing an `.ips` crash report.
+#[derive(Debug, Fail)]
+pub enum IpsError {
+    /// The report is not valid JSON.
+    #[fail(display = "invalid crash report JSON")]
+    Json(#[cause] serde_json::Error),
+
+    /// The report does not contain a body after the header.
+    #[fail(display = "missing crash report body")]
+    MissingBody,
+}
+
+impl From<serde_json::Error> for IpsError {
+    fn from(error: serde_json::Error) -> Self {
+        IpsError::Json(error)
+    }
+}
+
+/// The header line of an `.ips` report.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct IpsHeader {
+    /// The type of the report, `309` for crash reports.
+    pub bug_type: Option<String>,
+    /// When the report was written.
+    pub timestamp: Option<String>,
+    /// The full OS version, e.g. `iPhone OS 15.0 (19A346)`.
+    pub os_version: Option<String>,
+}
+
+/// Version information of the operating system.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IpsOsVersion {
+    /// Name and version of the OS, e.g. `iPhone OS 15.0`.
+    pub train: Option<String>,
+    /// The build number, e.g. `19A346`.
+    pub build: Option<String>,
+}
+
+/// The exception that caused the crash.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IpsException {
+    /// The Mach exception type, e.g. `EXC_BAD_ACCESS`.
+    #[serde(rename = "type")]
+    pub ty: Option<String>,
+    /// The corresponding signal, e.g. `SIGSEGV`.
+    pub signal: Option<String>,
+    /// The exception subtype, e.g. `KERN_INVALID_ADDRESS at 0x0000000000000000`.
+    pub subtype: Option<String>,
+    /// An additional message describing the exception.
+    pub message: Option<String>,
+    /// Formatted exception codes.
+    pub codes: Option<String>,
+}
+
+/// A frame in a backtrace.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IpsFrame {
+    /// Index of the image containing this frame in `usedImages`.
+    pub image_index: Option<usize>,
+    /// Offset of the instruction relative to the image base.
+    pub image_offset: u64,
+    /// Name of the function, if the crash reporter could resolve it.
+    pub symbol: Option<String>,
+}
+
+/// A thread of the crashed process.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IpsThread {
+    /// The thread id.
+    pub id: u64,
+    /// Whether this thread caused the crash.
+    pub triggered: bool,
+    /// The name of the thread.
+    pub name: Option<String>,
+    /// The name of the dispatch queue that the thread was running.
+    pub queue: Option<String>,
+    /// Raw register values of the thread. Only present for the crashed thread.
+    pub thread_state: Option<BTreeMap<String, Value>>,
+    /// The backtrace of this thread, starting with the innermost frame.
+    pub frames: Vec<IpsFrame>,
+}
+
+/// An image loaded into the crashed process.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IpsImage {
+    /// The load address of the image.
+    pub base: u64,
+    /// The size of the image in memory.
+    pub size: u64,
+    /// The UUID of the image, if known.
+    pub uuid: Option<String>,
+    /// Full path to the image.
+    pub path: Option<String>,
+    /// File name of the image.
+    pub name: Option<String>,
+    /// The architecture of the image, e.g. `arm64e`.
+    pub arch: Option<String>,
+}
+
+/// The body of an `.ips` report.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IpsBody {
+    /// When the crash occurred.
+    pub capture_time: Option<String>,
+    /// The CPU type of the crashed process, e.g. `ARM-64`.
+    pub cpu_type: Option<String>,
+    /// The hardware model, e.g. `iPhone12,1`.
+    pub model_code: Option<String>,
+    /// Version information of the operating system.
+    pub os_version: Option<IpsOsVersion>,
+    /// The exception that caused the crash.
+    pub exception: Option<IpsException>,
+    /// Application specific information, keyed by image name.
+    pub asi: Option<BTreeMap<String, Vec<String>>>,
+    /// All threads of the crashed process.
+    pub threads: Vec<IpsThread>,
+    /// All images referenced by frames.
+    pub used_images: Vec<IpsImage>,
+    /// The backtrace of the last uncaught exception, if any.
+    pub last_exception_backtrace: Option<Vec<IpsFrame>>,
+}
+
+/// A parsed `.ips` crash report.
+#[derive(Clone, Debug, Default)]
+pub struct IpsReport {
+    /// The header line of the report.
+    pub header: IpsHeader,
+    /// The body of the report.
+    pub body: IpsBody,
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f %z").ok()
+}
+
+impl IpsReport {
+    /// Checks whether the given data looks like an `.ips` report.
+    ///
+    /// The header line of the report is a JSON object that declares a `bug_type`.
+    pub fn test(data: &[u8]) -> bool {
+        let header = data.split(|b| *b == b'\n').next().unwrap_or_default();
+        serde_json::from_slice::<IpsHeader>(header)
+            .map(|header| header.bug_type.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Parses an `.ips` report from the header and body JSON documents.
+    pub fn parse(data: &[u8]) -> Result<Self, IpsError> {
+        let mut stream = serde_json::Deserializer::from_slice(data).into_iter::<Value>();
+
+        let header = match stream.next() {
+            Some(header) => serde_json::from_value(header?)?,
+            None => return Err(IpsError::MissingBody),
+        };
+
+        let body = match stream.next() {
+            Some(body) => serde_json::from_value(body?)?,
+            None => return Err(IpsError::MissingBody),
+        };
+
+        Ok(IpsReport { header, body })
+    }
+
+    /// Returns the time of the crash.
+    pub fn timestamp(&self) -> Option<DateTime<FixedOffset>> {
+        let body_time = self.body.capture_time.as_ref();
+        let header_time = self.header.timestamp.as_ref();
+
+        body_time
+            .and_then(|time| parse_timestamp(time))
+            .or_else(|| header_time.and_then(|time| parse_timestamp(time)))
+    }
+
+    /// Returns the image referenced by the given frame.
+    ///
+    /// This also returns images without a UUID. Their frames cannot be symbolicated, but the base
+    /// address of the image is still needed to resolve the absolute address of the frame.
+    pub fn image(&self, frame: &IpsFrame) -> Option<&IpsImage> {
+        self.body.used_images.get(frame.image_index?)
+    }
+}
+
+impl IpsImage {
+    /// Returns the UUID of the image, or `None` if it is missing or nil.
+    pub fn uuid(&self) -> Option<Uuid> {
+        let uuid = Uuid::parse_str(self.uuid.as_ref()?).ok()?;
+        if uuid.is_nil() {
+            None
+        } else {
+            Some(uuid)
+        }
+    }
+}
+
+impl IpsThread {
+    /// Returns the register values from the thread state.
+    ///
+    /// General purpose registers on ARM are listed in the `x` array and named `x0` to `x28`. All
+    /// other registers are objects with a `value`.
+    pub fn registers(&self) -> BTreeMap<String, u64> {
+        let mut registers = BTreeMap::new();
+        let state = match self.thread_state {
+            Some(ref state) => state,
+            None => return registers,
+        };
+
+        for (name, value) in state {
+            match value {
+                Value::Array(values) => {
+                    for (index, value) in values.iter().enumerate() {
+                        if let Some(value) = value.get("value").and_then(Value::as_u64) {
+                            registers.insert(format!("{}{}", name, index), value);
+                        }
+                    }
+                }
+                value => {
+                    if let Some(value) = value.get("value").and_then(Value::as_u64) {
+                        registers.insert(name.clone(), value);
+                    }
+                }
+            }
+        }
+
+        registers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &[u8] = include_bytes!("../../tests/fixtures/apple_crash_report.ips");
+
+    #[test]
+    fn test_detect() {
+        assert!(IpsReport::test(REPORT));
+        assert!(!IpsReport::test(b"{\"stacktraces\": []}"));
+        assert!(!IpsReport::test(b"Incident Identifier: 5C32DF84"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let report = IpsReport::parse(REPORT).unwrap();
+
+        assert_eq!(
+            report.header.bug_type.as_ref().map(String::as_str),
+            Some("309")
+        );
+        assert_eq!(
+            report.timestamp().map(|t| t.timestamp()),
+            Some(1_632_836_529)
+        );
+
+        let exception = report.body.exception.as_ref().unwrap();
+        assert_eq!(exception.ty.as_ref().map(String::as_str), Some("EXC_CRASH"));
+        assert_eq!(
+            exception.signal.as_ref().map(String::as_str),
+            Some("SIGABRT")
+        );
+
+        assert_eq!(report.body.used_images.len(), 4);
+        assert_eq!(report.body.threads.len(), 2);
+        assert_eq!(
+            report.body.last_exception_backtrace.as_ref().unwrap().len(),
+            2
+        );
+
+        let thread = &report.body.threads[0];
+        assert!(thread.triggered);
+        assert_eq!(
+            thread.queue.as_ref().map(String::as_str),
+            Some("com.apple.main-thread")
+        );
+
+        let image = report.image(&thread.frames[2]).unwrap();
+        assert_eq!(image.base, 0x1_0264_4000);
+        assert_eq!(image.name.as_ref().map(String::as_str), Some("Example"));
+    }
+
+    #[test]
+    fn test_registers() {
+        let report = IpsReport::parse(REPORT).unwrap();
+        let registers = report.body.threads[0].registers();
+
+        assert_eq!(registers.get("x0"), Some(&0));
+        assert_eq!(registers.get("x1"), Some(&0x1_0321_8000));
+        assert_eq!(registers.get("pc"), Some(&0x1_b8f2_9964));
+        assert_eq!(registers.get("flavor"), None);
+        assert!(report.body.threads[1].registers().is_empty());
+    }
+
+    #[test]
+    fn test_missing_body() {
+        let header = br#"{"bug_type":"309","timestamp":"2021-09-28 15:42:09.00 +0200"}"#;
+        assert!(IpsReport::parse(header).is_err());
+    }
+}
@@ -1,7 +1,11 @@
+pub mod coredump;
 pub mod futures;
 pub mod hex;
 pub mod http;
+pub mod ips;
 pub mod multipart;
 pub mod objects;
 pub mod paths;
 pub mod sentry;
+pub mod tombstone;
+pub mod unwind;
@@ -3,7 +3,7 @@ use actix_web::web::{Bytes, BytesMut};
 use actix_web::{error, Error};
 use futures::{Future, Stream};
 
-use crate::types::SourceConfig;
+use crate::types::RequestSource;
 
 const MAX_SOURCES_SIZE: usize = 1_000_000;
 
@@ -35,6 +35,6 @@ pub fn read_multipart_file(field: Field) -> impl Future<Item = Bytes, Error = Er
 
 pub fn read_multipart_sources(
     field: Field,
-) -> impl Future<Item = Vec<SourceConfig>, Error = Error> {
+) -> impl Future<Item = Vec<RequestSource>, Error = Error> {
     read_multipart_data(field, MAX_SOURCES_SIZE).and_then(|data| Ok(serde_json::from_slice(&data)?))
 }
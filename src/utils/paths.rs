@@ -2,9 +2,11 @@ use std::borrow::Cow;
 use std::fmt::Write;
 
 use symbolic::common::{CodeId, DebugId, Uuid};
+use url::percent_encoding::{percent_decode, utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
 
 use crate::types::{
-    DirectoryLayout, DirectoryLayoutType, FileType, FilenameCasing, Glob, ObjectId,
+    DirectoryLayout, DirectoryLayoutType, FileType, FilenameCasing, Glob, ObjectId, PathTemplate,
+    TemplatePlaceholder, TemplateSegment,
 };
 
 const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
@@ -33,8 +35,7 @@ fn get_mach_uuid(identifier: &ObjectId) -> Option<Uuid> {
     }
 }
 
-fn get_lldb_path(identifier: &ObjectId) -> Option<String> {
-    let uuid = get_mach_uuid(identifier)?;
+fn get_uuid_path(uuid: &Uuid) -> Option<String> {
     let slice = uuid.as_bytes();
 
     // Format the UUID as "xxxx/xxxx/xxxx/xxxx/xxxx/xxxxxxxxxxxx"
@@ -49,6 +50,11 @@ fn get_lldb_path(identifier: &ObjectId) -> Option<String> {
     Some(path)
 }
 
+fn get_lldb_path(identifier: &ObjectId) -> Option<String> {
+    let uuid = get_mach_uuid(identifier)?;
+    get_uuid_path(&uuid)
+}
+
 fn get_pdb_symstore_path(identifier: &ObjectId, ssqp_casing: bool) -> Option<String> {
     let debug_file = identifier.debug_file_basename()?;
     let debug_id = identifier.debug_id.as_ref()?;
@@ -210,6 +216,131 @@ fn get_symstore_path(
     }
 }
 
+fn get_unified_path(filetype: FileType, identifier: &ObjectId) -> Option<String> {
+    // symsorter keys all files by the UUID of the debug identifier. PDBs are rebuilt with the same
+    // signature and an incremented age, so the age is appended for PDB and PE files.
+    let (uuid, age) = match identifier.debug_id {
+        Some(ref debug_id) => (debug_id.uuid(), debug_id.appendix()),
+        None => (get_mach_uuid(identifier)?, 0),
+    };
+
+    let suffix = match filetype {
+        FileType::Pe | FileType::MachCode | FileType::ElfCode => "executable",
+        FileType::Pdb | FileType::MachDebug | FileType::ElfDebug => "debuginfo",
+        FileType::Breakpad => "breakpad",
+        FileType::SourceBundle => "sourcebundle",
+    };
+
+    let mut path = get_uuid_path(&uuid)?;
+    if let FileType::Pdb | FileType::Pe = filetype {
+        write!(path, "{:X}", age).ok()?;
+    }
+    path.push('.');
+    path.push_str(suffix);
+    Some(path)
+}
+
+fn get_debuginfod_path(filetype: FileType, identifier: &ObjectId) -> Option<String> {
+    let code_id = identifier.code_id.as_ref()?.as_str();
+
+    // debuginfod only serves ELF files, which are identified by their GNU build id.
+    match filetype {
+        FileType::ElfCode => Some(format!("buildid/{}/executable", code_id)),
+        FileType::ElfDebug => Some(format!("buildid/{}/debuginfo", code_id)),
+        _ => None,
+    }
+}
+
+/// Returns the conventional extension of a file type, including the leading dot.
+///
+/// Code files do not have a common extension and return an empty string.
+fn get_extension(filetype: FileType) -> &'static str {
+    match filetype {
+        FileType::Pdb => ".pdb",
+        FileType::ElfDebug => ".debug",
+        FileType::Breakpad => ".sym",
+        FileType::SourceBundle => ".src.zip",
+        FileType::Pe | FileType::MachCode | FileType::MachDebug | FileType::ElfCode => "",
+    }
+}
+
+fn get_template_path(
+    template: &PathTemplate,
+    filetype: FileType,
+    identifier: &ObjectId,
+) -> Option<String> {
+    let mut path = String::new();
+
+    for segment in template.segments() {
+        let placeholder = match segment {
+            TemplateSegment::Literal(literal) => {
+                path.push_str(literal);
+                continue;
+            }
+            TemplateSegment::Placeholder(placeholder) => placeholder,
+        };
+
+        match placeholder {
+            TemplatePlaceholder::DebugId => {
+                write!(path, "{}", identifier.debug_id.as_ref()?).ok()?;
+            }
+            TemplatePlaceholder::DebugIdBreakpad => {
+                write!(path, "{}", identifier.debug_id.as_ref()?.breakpad()).ok()?;
+            }
+            TemplatePlaceholder::CodeId => path.push_str(identifier.code_id.as_ref()?.as_str()),
+            TemplatePlaceholder::CodeFile => path.push_str(identifier.code_file.as_ref()?),
+            TemplatePlaceholder::CodeFileBasename => {
+                path.push_str(identifier.code_file_basename()?)
+            }
+            TemplatePlaceholder::DebugFile => path.push_str(identifier.debug_file.as_ref()?),
+            TemplatePlaceholder::DebugFileBasename => {
+                path.push_str(identifier.debug_file_basename()?)
+            }
+            TemplatePlaceholder::MachUuid => {
+                write!(path, "{}", get_mach_uuid(identifier)?.to_simple_ref()).ok()?;
+            }
+            TemplatePlaceholder::Extension => path.push_str(get_extension(filetype)),
+        }
+    }
+
+    Some(path)
+}
+
+/// Determines the path of a source file on a debuginfod server.
+///
+/// The source file is identified by the build id of the ELF file referencing it, and the absolute
+/// path of the source file at compile time. Returns `None` if the path contains empty, `.` or `..`
+/// segments or backslashes, since these could resolve outside of the sources of this build id.
+///
+/// With `encode`, every segment is percent-encoded so that it can be joined to the URL of an HTTP
+/// source. Other sources look up files by their verbatim path.
+pub fn get_debuginfod_source_path(
+    code_id: &CodeId,
+    abs_path: &str,
+    encode: bool,
+) -> Option<String> {
+    let mut path = format!("buildid/{}/source", code_id.as_str());
+
+    for segment in abs_path.trim_start_matches('/').split('/') {
+        // Check the decoded segment as well, since URL normalization treats `%2E%2E` like `..`.
+        let decoded = percent_decode(segment.as_bytes()).decode_utf8_lossy();
+        for part in &[segment, decoded.as_ref()] {
+            if part.is_empty() || *part == "." || *part == ".." || part.contains('\\') {
+                return None;
+            }
+        }
+
+        path.push('/');
+        if encode {
+            path.extend(utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET));
+        } else {
+            path.push_str(segment);
+        }
+    }
+
+    Some(path)
+}
+
 fn get_symstore_index2_path(filetype: FileType, identifier: &ObjectId) -> Option<String> {
     let rv = get_symstore_path(filetype, identifier, false)?;
     if let Some(prefix) = rv.get(..2) {
@@ -224,7 +355,7 @@ fn get_symstore_index2_path(filetype: FileType, identifier: &ObjectId) -> Option
 
 /// Determines the path for an object file in the given layout.
 pub fn get_directory_path(
-    directory_layout: DirectoryLayout,
+    directory_layout: &DirectoryLayout,
     filetype: FileType,
     identifier: &ObjectId,
 ) -> Option<String> {
@@ -233,6 +364,11 @@ pub fn get_directory_path(
         DirectoryLayoutType::Symstore => get_symstore_path(filetype, identifier, false)?,
         DirectoryLayoutType::SymstoreIndex2 => get_symstore_index2_path(filetype, identifier)?,
         DirectoryLayoutType::SSQP => get_symstore_path(filetype, identifier, true)?,
+        DirectoryLayoutType::Unified => get_unified_path(filetype, identifier)?,
+        DirectoryLayoutType::Debuginfod => get_debuginfod_path(filetype, identifier)?,
+        DirectoryLayoutType::Template => {
+            get_template_path(directory_layout.template.as_ref()?, filetype, identifier)?
+        }
     };
 
     match directory_layout.casing {
@@ -320,6 +456,34 @@ pub fn parse_symstore_path(path: &str) -> Option<(&'static [FileType], ObjectId)
     }
 }
 
+/// Parses the build id and file kind of a debuginfod request.
+///
+/// The kind is either `debuginfo` or `executable`. Source file requests are not supported.
+pub fn parse_debuginfod_path(
+    build_id: &str,
+    kind: &str,
+) -> Option<(&'static [FileType], ObjectId)> {
+    if build_id.is_empty() || !build_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let filetypes: &'static [FileType] = match kind {
+        "debuginfo" => &[FileType::ElfDebug],
+        "executable" => &[FileType::ElfCode],
+        _ => return None,
+    };
+
+    Some((
+        filetypes,
+        ObjectId {
+            code_id: Some(CodeId::new(build_id.to_lowercase())),
+            code_file: None,
+            debug_id: None,
+            debug_file: None,
+        },
+    ))
+}
+
 pub fn matches_path_patterns(object_id: &ObjectId, patterns: &[Glob]) -> bool {
     fn canonicalize_path(s: &str) -> String {
         s.replace(r"\", "/")
@@ -350,6 +514,285 @@ mod tests {
         Glob(x.parse().unwrap())
     }
 
+    fn unified_object_id() -> ObjectId {
+        ObjectId {
+            debug_id: Some("502fc0a5-1ec1-3e47-9998-684fa139dca7".parse().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_unified_path_debug() {
+        let layout = DirectoryLayout {
+            ty: DirectoryLayoutType::Unified,
+            casing: FilenameCasing::Default,
+            template: None,
+        };
+
+        assert_eq!(
+            get_directory_path(&layout, FileType::MachDebug, &unified_object_id())
+                .as_ref()
+                .map(String::as_str),
+            Some("502F/C0A5/1EC1/3E47/9998/684FA139DCA7.debuginfo")
+        );
+    }
+
+    #[test]
+    fn test_unified_path_suffixes() {
+        let layout = DirectoryLayout {
+            ty: DirectoryLayoutType::Unified,
+            casing: FilenameCasing::Lowercase,
+            template: None,
+        };
+
+        let object_id = unified_object_id();
+        let paths: Vec<_> = [
+            FileType::ElfCode,
+            FileType::Pdb,
+            FileType::Breakpad,
+            FileType::SourceBundle,
+        ]
+        .iter()
+        .filter_map(|&filetype| get_directory_path(&layout, filetype, &object_id))
+        .collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "502f/c0a5/1ec1/3e47/9998/684fa139dca7.executable",
+                "502f/c0a5/1ec1/3e47/9998/684fa139dca70.debuginfo",
+                "502f/c0a5/1ec1/3e47/9998/684fa139dca7.breakpad",
+                "502f/c0a5/1ec1/3e47/9998/684fa139dca7.sourcebundle",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unified_path_pdb_age() {
+        let layout = DirectoryLayout {
+            ty: DirectoryLayoutType::Unified,
+            casing: FilenameCasing::Default,
+            template: None,
+        };
+
+        let object_id = ObjectId {
+            debug_id: Some("502fc0a5-1ec1-3e47-9998-684fa139dca7-1a".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let paths: Vec<_> = [FileType::Pe, FileType::Pdb, FileType::Breakpad]
+            .iter()
+            .filter_map(|&filetype| get_directory_path(&layout, filetype, &object_id))
+            .collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "502F/C0A5/1EC1/3E47/9998/684FA139DCA71A.executable",
+                "502F/C0A5/1EC1/3E47/9998/684FA139DCA71A.debuginfo",
+                "502F/C0A5/1EC1/3E47/9998/684FA139DCA7.breakpad",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unified_path_from_code_id() {
+        let layout = DirectoryLayout {
+            ty: DirectoryLayoutType::Unified,
+            casing: FilenameCasing::Default,
+            template: None,
+        };
+
+        let object_id = ObjectId {
+            code_id: Some(CodeId::new("502fc0a51ec13e479998684fa139dca7".into())),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            get_directory_path(&layout, FileType::MachCode, &object_id)
+                .as_ref()
+                .map(String::as_str),
+            Some("502F/C0A5/1EC1/3E47/9998/684FA139DCA7.executable")
+        );
+    }
+
+    #[test]
+    fn test_debuginfod_path() {
+        let layout = DirectoryLayout {
+            ty: DirectoryLayoutType::Debuginfod,
+            casing: FilenameCasing::Default,
+            template: None,
+        };
+
+        let object_id = ObjectId {
+            code_id: Some(CodeId::new(
+                "dfb85de42daffd09640c8fe377d572de3e168920".into(),
+            )),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            get_directory_path(&layout, FileType::ElfCode, &object_id)
+                .as_ref()
+                .map(String::as_str),
+            Some("buildid/dfb85de42daffd09640c8fe377d572de3e168920/executable")
+        );
+
+        assert_eq!(
+            get_directory_path(&layout, FileType::ElfDebug, &object_id)
+                .as_ref()
+                .map(String::as_str),
+            Some("buildid/dfb85de42daffd09640c8fe377d572de3e168920/debuginfo")
+        );
+
+        assert_eq!(get_directory_path(&layout, FileType::Pdb, &object_id), None);
+    }
+
+    #[test]
+    fn test_template_path() {
+        let layout = DirectoryLayout {
+            ty: DirectoryLayoutType::Template,
+            casing: FilenameCasing::Default,
+            template: Some(
+                "macos/{debug_file_basename}/{debug_id_breakpad}/{debug_file_basename}{extension}"
+                    .parse()
+                    .unwrap(),
+            ),
+        };
+
+        let object_id = ObjectId {
+            debug_id: Some("502fc0a5-1ec1-3e47-9998-684fa139dca7".parse().unwrap()),
+            debug_file: Some("/Applications/Example.app/Contents/MacOS/Example".to_owned()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            get_directory_path(&layout, FileType::Breakpad, &object_id)
+                .as_ref()
+                .map(String::as_str),
+            Some("macos/Example/502FC0A51EC13E479998684FA139DCA70/Example.sym")
+        );
+
+        assert_eq!(
+            get_directory_path(&layout, FileType::MachDebug, &object_id)
+                .as_ref()
+                .map(String::as_str),
+            Some("macos/Example/502FC0A51EC13E479998684FA139DCA70/Example")
+        );
+    }
+
+    #[test]
+    fn test_template_path_missing_value() {
+        let layout = DirectoryLayout {
+            ty: DirectoryLayoutType::Template,
+            casing: FilenameCasing::Lowercase,
+            template: Some(
+                "{code_file_basename}/{mach_uuid}/{code_id}"
+                    .parse()
+                    .unwrap(),
+            ),
+        };
+
+        let object_id = ObjectId {
+            code_file: Some("/usr/lib/libfoo.dylib".to_owned()),
+            debug_id: Some("502fc0a5-1ec1-3e47-9998-684fa139dca7".parse().unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            get_directory_path(&layout, FileType::MachCode, &object_id),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_template() {
+        let template: PathTemplate = "{debug_id}.debug".parse().unwrap();
+        assert_eq!(
+            template.segments(),
+            &[
+                TemplateSegment::Placeholder(TemplatePlaceholder::DebugId),
+                TemplateSegment::Literal(".debug".to_owned()),
+            ]
+        );
+
+        assert!("{debug_id".parse::<PathTemplate>().is_err());
+        assert!("{platform}/{debug_id}".parse::<PathTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_template_layout() {
+        let layout: DirectoryLayout =
+            serde_json::from_str(r#"{"type": "template", "template": "{debug_id}"}"#).unwrap();
+        assert_eq!(layout.ty, DirectoryLayoutType::Template);
+        assert!(layout.template.is_some());
+
+        let layout: DirectoryLayout = serde_json::from_str(r#"{"type": "native"}"#).unwrap();
+        assert_eq!(layout.ty, DirectoryLayoutType::Native);
+
+        assert!(serde_json::from_str::<DirectoryLayout>(r#"{"type": "template"}"#).is_err());
+    }
+
+    #[test]
+    fn test_debuginfod_source_path() {
+        let code_id = CodeId::new("dfb85de42daffd09640c8fe377d572de3e168920".into());
+
+        assert_eq!(
+            get_debuginfod_source_path(&code_id, "/usr/include/stdio.h", true).unwrap(),
+            "buildid/dfb85de42daffd09640c8fe377d572de3e168920/source/usr/include/stdio.h"
+        );
+
+        assert_eq!(
+            get_debuginfod_source_path(&code_id, "/src/my file?#1%.c", true).unwrap(),
+            "buildid/dfb85de42daffd09640c8fe377d572de3e168920/source/src/my%20file%3F%231%25.c"
+        );
+
+        assert_eq!(
+            get_debuginfod_source_path(&code_id, "/src/my file?#1%.c", false).unwrap(),
+            "buildid/dfb85de42daffd09640c8fe377d572de3e168920/source/src/my file?#1%.c"
+        );
+    }
+
+    #[test]
+    fn test_debuginfod_source_path_traversal() {
+        let code_id = CodeId::new("dfb85de42daffd09640c8fe377d572de3e168920".into());
+
+        for path in &[
+            "/src/../../etc/passwd",
+            "/src/./main.c",
+            "/src//main.c",
+            "/src/%2E%2E/%2e%2e/etc/passwd",
+            "/src/..\\..\\etc/passwd",
+        ] {
+            assert_eq!(get_debuginfod_source_path(&code_id, path, true), None);
+            assert_eq!(get_debuginfod_source_path(&code_id, path, false), None);
+        }
+    }
+
+    #[test]
+    fn test_parse_debuginfod_path() {
+        let (filetypes, object_id) =
+            parse_debuginfod_path("DFB85DE42DAFFD09640C8FE377D572DE3E168920", "debuginfo").unwrap();
+
+        assert_eq!(filetypes, &[FileType::ElfDebug]);
+        assert_eq!(
+            object_id.code_id.as_ref().map(CodeId::as_str),
+            Some("dfb85de42daffd09640c8fe377d572de3e168920")
+        );
+
+        let (filetypes, _) =
+            parse_debuginfod_path("dfb85de42daffd09640c8fe377d572de3e168920", "executable")
+                .unwrap();
+        assert_eq!(filetypes, &[FileType::ElfCode]);
+    }
+
+    #[test]
+    fn test_parse_debuginfod_path_invalid() {
+        assert!(parse_debuginfod_path("dfb85de42daffd09", "source").is_none());
+        assert!(parse_debuginfod_path("not-a-build-id", "debuginfo").is_none());
+        assert!(parse_debuginfod_path("", "executable").is_none());
+    }
+
     #[test]
     fn test_matches_path_patterns_empty() {
         assert!(matches_path_patterns(
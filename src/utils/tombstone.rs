@@ -0,0 +1,398 @@
+//! Parser for Android native crash reports (tombstones).
+//!
+//! Tombstones are written by `debuggerd` when a native process crashes on Android. They are plain
+//! text files, consisting of a header with information on the device and the crash, followed by
+//! sections for every thread and a dump of the process' memory maps.
+use std::cmp;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, FixedOffset};
+use failure::Fail;
+use lazy_static::lazy_static;
+use regex::Regex;
+use symbolic::common::{DebugId, Uuid};
+
+lazy_static! {
+    static ref HEADER_REGEX: Regex = Regex::new(r"^(Build fingerprint|ABI|Timestamp|Abort message): '?(.*?)'?$").unwrap();
+    static ref THREAD_REGEX: Regex = Regex::new(r"^pid: (\d+), tid: (\d+), name: (.*?)\s+>>> (.*) <<<").unwrap();
+    static ref SIGNAL_REGEX: Regex = Regex::new(r"^signal (\d+) \((\w+)\), code (-?\d+) \((\w+)\), fault addr (?:0x)?([0-9a-fA-F]+|-+)").unwrap();
+    static ref FRAME_REGEX: Regex = Regex::new(r"^#\d+ pc ([0-9a-fA-F]+)\s+(.+?)(?: \(offset 0x[0-9a-fA-F]+\))?(?: \((.+)\+\d+\))?(?: \(BuildId: ([0-9a-fA-F]+)\))?$").unwrap();
+    static ref MAP_REGEX: Regex = Regex::new(r"^(?:--->)?([0-9a-fA-F']+)-([0-9a-fA-F']+) (\S+)\s+([0-9a-fA-F]+)\s+([0-9a-fA-F]+)(?:\s+(.+?))?(?: \(BuildId: ([0-9a-fA-F]+)\))?(?: \(load bias 0x[0-9a-fA-F]+\))?$").unwrap();
+}
+
+/// An error returned when parsing a tombstone.
+#[derive(Debug, Fail)]
+pub enum TombstoneError {
+    /// The tombstone does not contain a crashed thread.
+    #[fail(display = "no crashed thread in tombstone")]
+    MissingThread,
+}
+
+/// The signal that caused the crash.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TombstoneSignal {
+    /// The signal number, e.g. `11`.
+    pub number: u32,
+    /// The signal name, e.g. `SIGSEGV`.
+    pub name: String,
+    /// The name of the signal code, e.g. `SEGV_MAPERR`.
+    pub code_name: String,
+    /// The address that caused the fault, if any.
+    pub fault_addr: Option<u64>,
+}
+
+/// A frame in the backtrace of a thread.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TombstoneFrame {
+    /// Program counter relative to the load address of the module.
+    pub rel_pc: u64,
+    /// Path to the module containing this frame.
+    pub module: String,
+    /// Name of the function, if `debuggerd` could resolve it.
+    pub function: Option<String>,
+    /// GNU build id of the module.
+    pub build_id: Option<String>,
+}
+
+/// A thread in the crashed process.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TombstoneThread {
+    /// The thread id.
+    pub tid: u64,
+    /// The name of the thread.
+    pub name: String,
+    /// Whether this thread caused the crash.
+    pub crashed: bool,
+    /// Register values at the time of the crash.
+    pub registers: BTreeMap<String, u64>,
+    /// The backtrace of this thread, starting with the innermost frame.
+    pub frames: Vec<TombstoneFrame>,
+}
+
+/// A mapped memory region of the crashed process.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoryMap {
+    /// Start address of the region.
+    pub start: u64,
+    /// End address of the region (inclusive).
+    pub end: u64,
+    /// Access permissions, e.g. `r-x`.
+    pub permissions: String,
+    /// Offset of the region in the mapped file.
+    pub offset: u64,
+    /// Name of the mapped file, if any.
+    pub name: Option<String>,
+    /// GNU build id of the mapped file.
+    pub build_id: Option<String>,
+}
+
+/// A parsed Android tombstone.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Tombstone {
+    /// The build fingerprint of the device.
+    pub build_fingerprint: Option<String>,
+    /// The ABI of the crashed process, e.g. `arm64`.
+    pub abi: Option<String>,
+    /// When the crash occurred.
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    /// Name of the crashed process.
+    pub process_name: Option<String>,
+    /// The signal that caused the crash.
+    pub signal: Option<TombstoneSignal>,
+    /// A message passed to `abort`.
+    pub abort_message: Option<String>,
+    /// The probable cause of the crash, as determined by `debuggerd`.
+    pub cause: Option<String>,
+    /// All threads of the process. The crashed thread comes first.
+    pub threads: Vec<TombstoneThread>,
+    /// Memory maps of the process.
+    pub memory_maps: Vec<MemoryMap>,
+}
+
+/// The section of the tombstone that is currently being parsed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Section {
+    Header,
+    Thread,
+    Backtrace,
+    MemoryMap,
+    Other,
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(&value.replace('\'', ""), 16).ok()
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f%z")
+        .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%z"))
+        .ok()
+}
+
+/// Parses a line of register values, such as `x0  0000000000000000  x1  0000007fd2d3d5c8`.
+fn parse_registers(line: &str, registers: &mut BTreeMap<String, u64>) -> bool {
+    let tokens: Vec<_> = line.split_whitespace().collect();
+    if tokens.is_empty() || tokens.len() % 2 != 0 {
+        return false;
+    }
+
+    let mut parsed = Vec::with_capacity(tokens.len() / 2);
+    for pair in tokens.chunks(2) {
+        let (name, value) = (pair[0], pair[1]);
+        let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric());
+
+        if !is_name || value.len() < 8 {
+            return false;
+        }
+
+        match parse_hex(value) {
+            Some(value) => parsed.push((name.to_owned(), value)),
+            None => return false,
+        }
+    }
+
+    registers.extend(parsed);
+    true
+}
+
+fn parse_frame(line: &str) -> Option<TombstoneFrame> {
+    let captures = FRAME_REGEX.captures(line)?;
+
+    Some(TombstoneFrame {
+        rel_pc: parse_hex(&captures[1])?,
+        module: captures[2].to_owned(),
+        function: captures.get(3).map(|m| m.as_str().to_owned()),
+        build_id: captures.get(4).map(|m| m.as_str().to_lowercase()),
+    })
+}
+
+fn parse_memory_map(line: &str) -> Option<MemoryMap> {
+    let captures = MAP_REGEX.captures(line)?;
+
+    Some(MemoryMap {
+        start: parse_hex(&captures[1])?,
+        end: parse_hex(&captures[2])?,
+        permissions: captures[3].to_owned(),
+        offset: parse_hex(&captures[4])?,
+        name: captures.get(6).map(|m| m.as_str().to_owned()),
+        build_id: captures.get(7).map(|m| m.as_str().to_lowercase()),
+    })
+}
+
+impl Tombstone {
+    /// Parses a tombstone from its text representation.
+    pub fn parse(text: &str) -> Result<Self, TombstoneError> {
+        let mut tombstone = Tombstone::default();
+        let mut section = Section::Header;
+
+        for line in text.lines() {
+            let line = line.trim();
+
+            if line.starts_with("--- --- ---") || line.starts_with("*** *** ***") {
+                section = Section::Other;
+                continue;
+            }
+
+            if let Some(captures) = THREAD_REGEX.captures(line) {
+                let crashed = tombstone.threads.is_empty();
+                if crashed {
+                    tombstone.process_name = Some(captures[4].to_owned());
+                }
+
+                tombstone.threads.push(TombstoneThread {
+                    tid: captures[2].parse().unwrap_or_default(),
+                    name: captures[3].to_owned(),
+                    crashed,
+                    ..TombstoneThread::default()
+                });
+
+                section = Section::Thread;
+                continue;
+            }
+
+            if line == "backtrace:" {
+                section = Section::Backtrace;
+                continue;
+            }
+
+            if line.starts_with("memory map") {
+                section = Section::MemoryMap;
+                continue;
+            }
+
+            match section {
+                Section::Header => {
+                    if let Some(captures) = HEADER_REGEX.captures(line) {
+                        let value = Some(captures[2].to_owned());
+                        match &captures[1] {
+                            "Build fingerprint" => tombstone.build_fingerprint = value,
+                            "ABI" => tombstone.abi = value,
+                            "Timestamp" => tombstone.timestamp = parse_timestamp(&captures[2]),
+                            "Abort message" => tombstone.abort_message = value,
+                            _ => (),
+                        }
+                    }
+                }
+                Section::Thread => {
+                    if let Some(captures) = SIGNAL_REGEX.captures(line) {
+                        tombstone.signal = Some(TombstoneSignal {
+                            number: captures[1].parse().unwrap_or_default(),
+                            name: captures[2].to_owned(),
+                            code_name: captures[4].to_owned(),
+                            fault_addr: parse_hex(&captures[5]),
+                        });
+                    } else if line.starts_with("Cause: ") {
+                        tombstone.cause = Some(line["Cause: ".len()..].to_owned());
+                    } else if line.starts_with("Abort message: ") {
+                        let message = &line["Abort message: ".len()..];
+                        tombstone.abort_message = Some(message.trim_matches('\'').to_owned());
+                    } else if let Some(thread) = tombstone.threads.last_mut() {
+                        parse_registers(line, &mut thread.registers);
+                    }
+                }
+                Section::Backtrace => match parse_frame(line) {
+                    Some(frame) => {
+                        if let Some(thread) = tombstone.threads.last_mut() {
+                            thread.frames.push(frame);
+                        }
+                    }
+                    None => section = Section::Other,
+                },
+                Section::MemoryMap => {
+                    if let Some(map) = parse_memory_map(line) {
+                        tombstone.memory_maps.push(map);
+                    }
+                }
+                Section::Other => (),
+            }
+        }
+
+        if tombstone.threads.is_empty() {
+            return Err(TombstoneError::MissingThread);
+        }
+
+        Ok(tombstone)
+    }
+}
+
+/// Computes the debug identifier of an ELF file from its GNU build id.
+///
+/// This matches the identifier that symbolic computes for little endian ELF files, which covers
+/// all ABIs supported by Android. The first 16 bytes of the build id are interpreted as a GUID in
+/// little endian byte order, and the age is always zero.
+pub fn debug_id_from_build_id(build_id: &str) -> Option<DebugId> {
+    if build_id.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut data = [0u8; 16];
+    let len = cmp::min(build_id.len() / 2, data.len());
+    for (i, byte) in data.iter_mut().enumerate().take(len) {
+        *byte = u8::from_str_radix(build_id.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    data[0..4].reverse();
+    data[4..6].reverse();
+    data[6..8].reverse();
+
+    Uuid::from_slice(&data).ok().map(DebugId::from_uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOMBSTONE: &str = include_str!("../../tests/fixtures/tombstone.txt");
+
+    #[test]
+    fn test_parse_header() {
+        let tombstone = Tombstone::parse(TOMBSTONE).unwrap();
+
+        assert_eq!(tombstone.abi.as_ref().map(String::as_str), Some("arm64"));
+        assert_eq!(
+            tombstone.build_fingerprint.as_ref().map(String::as_str),
+            Some("google/sargo/sargo:10/QQ1A.200105.002/6031802:user/release-keys")
+        );
+        assert_eq!(
+            tombstone.process_name.as_ref().map(String::as_str),
+            Some("com.example.app")
+        );
+        assert_eq!(
+            tombstone.timestamp.map(|t| t.timestamp()),
+            Some(1_579_617_398)
+        );
+        assert_eq!(
+            tombstone.signal,
+            Some(TombstoneSignal {
+                number: 11,
+                name: "SIGSEGV".to_owned(),
+                code_name: "SEGV_MAPERR".to_owned(),
+                fault_addr: Some(0),
+            })
+        );
+        assert_eq!(
+            tombstone.cause.as_ref().map(String::as_str),
+            Some("null pointer dereference")
+        );
+    }
+
+    #[test]
+    fn test_parse_threads() {
+        let tombstone = Tombstone::parse(TOMBSTONE).unwrap();
+        assert_eq!(tombstone.threads.len(), 2);
+
+        let crashed = &tombstone.threads[0];
+        assert!(crashed.crashed);
+        assert_eq!(crashed.tid, 14516);
+        assert_eq!(crashed.registers.get("pc"), Some(&0x73_daf6_d0d4));
+        assert_eq!(crashed.registers.get("x1"), Some(&0x7f_d2d3_d5c8));
+        assert_eq!(crashed.frames.len(), 3);
+        assert_eq!(
+            crashed.frames[0],
+            TombstoneFrame {
+                rel_pc: 0x10d4,
+                module: "/data/app/com.example.app-1/lib/arm64/libnative.so".to_owned(),
+                function: Some("crash".to_owned()),
+                build_id: Some("f1c3bcc0279865fe3058404b2831d9e64135386c".to_owned()),
+            }
+        );
+
+        let other = &tombstone.threads[1];
+        assert!(!other.crashed);
+        assert_eq!(other.name, "RenderThread");
+        assert_eq!(other.frames.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_memory_maps() {
+        let tombstone = Tombstone::parse(TOMBSTONE).unwrap();
+        assert_eq!(tombstone.memory_maps.len(), 4);
+
+        assert_eq!(
+            tombstone.memory_maps[1],
+            MemoryMap {
+                start: 0x73_daf6_d000,
+                end: 0x73_daf6_dfff,
+                permissions: "r-x".to_owned(),
+                offset: 0x1000,
+                name: Some("/data/app/com.example.app-1/lib/arm64/libnative.so".to_owned()),
+                build_id: Some("f1c3bcc0279865fe3058404b2831d9e64135386c".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert!(Tombstone::parse("").is_err());
+    }
+
+    #[test]
+    fn test_debug_id_from_build_id() {
+        let debug_id = debug_id_from_build_id("f1c3bcc0279865fe3058404b2831d9e64135386c");
+        assert_eq!(
+            debug_id.map(|id| id.to_string()),
+            Some("c0bcc3f1-9827-fe65-3058-404b2831d9e6".to_owned())
+        );
+    }
+}
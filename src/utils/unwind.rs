@@ -0,0 +1,430 @@
+//! Stack unwinding with Breakpad call frame information.
+//!
+//! CFI caches contain the `STACK CFI` records of a module in the Breakpad text format. Each
+//! `STACK CFI INIT` record covers a range of addresses and specifies rules to recover the
+//! registers of the caller, which are updated by subsequent `STACK CFI` records for addresses
+//! within that range. Rules are postfix expressions over the callee's registers, the canonical
+//! frame address (`.cfa`) and memory.
+//!
+//! If no CFI is available for an address, the walker falls back to following frame pointers.
+use std::collections::BTreeMap;
+
+use symbolic::common::Arch;
+use symbolic::minidump::processor::FrameTrust;
+
+/// The maximum number of frames to unwind per thread.
+const MAX_FRAMES: usize = 1024;
+
+/// Registers by name, without the `$` prefix that Breakpad uses on some architectures.
+pub type RegisterValues = BTreeMap<String, u64>;
+
+/// Read access to the memory of the crashed process.
+pub trait Memory {
+    /// Reads a 64-bit value at `addr`, if it is available.
+    fn read_u64(&self, addr: u64) -> Option<u64>;
+}
+
+/// A `STACK CFI INIT` record and the `STACK CFI` records that apply to its address range.
+#[derive(Debug)]
+struct CfiRecord<'data> {
+    start: u64,
+    size: u64,
+    init: &'data str,
+    deltas: Vec<(u64, &'data str)>,
+}
+
+/// The call frame information of a single module.
+#[derive(Debug, Default)]
+pub struct CfiRules<'data> {
+    records: Vec<CfiRecord<'data>>,
+}
+
+impl<'data> CfiRules<'data> {
+    /// Parses `STACK CFI` records from Breakpad text. Other records are ignored.
+    pub fn parse(data: &'data [u8]) -> Self {
+        let text = std::str::from_utf8(data).unwrap_or_default();
+        let mut records = Vec::<CfiRecord<'_>>::new();
+
+        for line in text.lines() {
+            if line.starts_with("STACK CFI INIT ") {
+                let mut parts = line["STACK CFI INIT ".len()..].splitn(3, ' ');
+                let start = parts.next().and_then(|s| u64::from_str_radix(s, 16).ok());
+                let size = parts.next().and_then(|s| u64::from_str_radix(s, 16).ok());
+
+                if let (Some(start), Some(size)) = (start, size) {
+                    records.push(CfiRecord {
+                        start,
+                        size,
+                        init: parts.next().unwrap_or_default(),
+                        deltas: Vec::new(),
+                    });
+                }
+            } else if line.starts_with("STACK CFI ") {
+                let mut parts = line["STACK CFI ".len()..].splitn(2, ' ');
+                let addr = parts.next().and_then(|s| u64::from_str_radix(s, 16).ok());
+
+                if let (Some(addr), Some(record)) = (addr, records.last_mut()) {
+                    record.deltas.push((addr, parts.next().unwrap_or_default()));
+                }
+            }
+        }
+
+        records.sort_by_key(|record| record.start);
+        CfiRules { records }
+    }
+
+    /// Returns the rules to recover the caller's registers at the given module-relative address.
+    pub fn find(&self, addr: u64) -> Option<BTreeMap<&'data str, String>> {
+        let index = match self.records.binary_search_by_key(&addr, |r| r.start) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+
+        let record = &self.records[index];
+        if addr >= record.start.saturating_add(record.size) {
+            return None;
+        }
+
+        let mut rules = BTreeMap::new();
+        parse_rules(record.init, &mut rules);
+        for &(delta_addr, delta) in &record.deltas {
+            if delta_addr <= addr {
+                parse_rules(delta, &mut rules);
+            }
+        }
+
+        Some(rules)
+    }
+}
+
+/// Parses rules in the form `.cfa: $rsp 8 + .ra: .cfa -8 + ^` into `rules`.
+fn parse_rules<'data>(text: &'data str, rules: &mut BTreeMap<&'data str, String>) {
+    let mut current = None;
+
+    for token in text.split_whitespace() {
+        if token.ends_with(':') {
+            let name = &token[..token.len() - 1];
+            rules.insert(name, String::new());
+            current = Some(name);
+        } else if let Some(name) = current {
+            if let Some(expr) = rules.get_mut(name) {
+                if !expr.is_empty() {
+                    expr.push(' ');
+                }
+                expr.push_str(token);
+            }
+        }
+    }
+}
+
+/// Evaluates a postfix CFI expression.
+fn evaluate(
+    expr: &str,
+    registers: &RegisterValues,
+    cfa: Option<u64>,
+    memory: &dyn Memory,
+) -> Option<u64> {
+    let mut stack = Vec::new();
+
+    for token in expr.split_whitespace() {
+        let value = match token {
+            "+" | "-" | "*" | "/" | "%" | "@" => {
+                let b: u64 = stack.pop()?;
+                let a: u64 = stack.pop()?;
+                match token {
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" => a.checked_div(b)?,
+                    "%" => a.checked_rem(b)?,
+                    _ => a & !(b.checked_sub(1)?),
+                }
+            }
+            "^" => memory.read_u64(stack.pop()?)?,
+            ".cfa" => cfa?,
+            _ => match token.parse::<i64>() {
+                Ok(number) => number as u64,
+                Err(_) => *registers.get(token.trim_start_matches('$'))?,
+            },
+        };
+
+        stack.push(value);
+    }
+
+    match stack.as_slice() {
+        [value] => Some(*value),
+        _ => None,
+    }
+}
+
+/// Computes the caller's registers by applying CFI rules.
+fn unwind_cfi(
+    rules: &BTreeMap<&str, String>,
+    registers: &RegisterValues,
+    memory: &dyn Memory,
+    ip: &str,
+    sp: &str,
+) -> Option<RegisterValues> {
+    let cfa = evaluate(rules.get(".cfa")?, registers, None, memory)?;
+    let ra = evaluate(rules.get(".ra")?, registers, Some(cfa), memory)?;
+
+    // Callee-saved registers without a rule retain their value.
+    let mut caller = registers.clone();
+    for (name, expr) in rules {
+        if !name.starts_with('.') {
+            let value = evaluate(expr, registers, Some(cfa), memory)?;
+            caller.insert(name.trim_start_matches('$').to_owned(), value);
+        }
+    }
+
+    caller.insert(sp.to_owned(), cfa);
+    caller.insert(ip.to_owned(), ra);
+    Some(caller)
+}
+
+/// Computes the caller's registers by following the frame pointer.
+///
+/// On both x86_64 and AArch64, the frame pointer points to the saved frame pointer of the caller,
+/// followed by the return address.
+fn unwind_frame_pointer(
+    registers: &RegisterValues,
+    memory: &dyn Memory,
+    ip: &str,
+    sp: &str,
+    fp: &str,
+) -> Option<RegisterValues> {
+    let frame_pointer = *registers.get(fp)?;
+    if frame_pointer == 0 || frame_pointer < *registers.get(sp)? {
+        return None;
+    }
+
+    let mut caller = registers.clone();
+    caller.insert(fp.to_owned(), memory.read_u64(frame_pointer)?);
+    caller.insert(
+        ip.to_owned(),
+        memory.read_u64(frame_pointer.checked_add(8)?)?,
+    );
+    caller.insert(sp.to_owned(), frame_pointer.checked_add(16)?);
+    Some(caller)
+}
+
+/// A loaded module with its call frame information.
+#[derive(Debug)]
+pub struct UnwindModule<'data> {
+    /// Load address of the module.
+    pub start: u64,
+    /// End address of the module (exclusive).
+    pub end: u64,
+    /// Call frame information of the module, if it could be loaded.
+    pub rules: Option<CfiRules<'data>>,
+}
+
+/// A frame recovered by the stack walker.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UnwoundFrame {
+    /// The instruction address of the frame. For all but the first frame, this is the return
+    /// address.
+    pub instruction_addr: u64,
+    /// How the frame was recovered.
+    pub trust: FrameTrust,
+}
+
+/// Returns the names of the instruction pointer, stack pointer and frame pointer registers.
+fn get_register_names(arch: Arch) -> Option<(&'static str, &'static str, &'static str)> {
+    match arch {
+        Arch::Amd64 => Some(("rip", "rsp", "rbp")),
+        Arch::Arm64 => Some(("pc", "sp", "x29")),
+        _ => None,
+    }
+}
+
+/// Walks the stack of a thread, starting with the given register values.
+pub fn walk_stack(
+    arch: Arch,
+    mut registers: RegisterValues,
+    modules: &[UnwindModule<'_>],
+    memory: &dyn Memory,
+) -> Vec<UnwoundFrame> {
+    let mut frames = Vec::new();
+    let (ip, sp, fp) = match get_register_names(arch) {
+        Some(names) => names,
+        None => return frames,
+    };
+
+    let find_module = |addr: u64| modules.iter().find(|m| addr >= m.start && addr < m.end);
+
+    let mut trust = FrameTrust::Context;
+    while frames.len() < MAX_FRAMES {
+        let (pc, stack_pointer) = match (registers.get(ip), registers.get(sp)) {
+            (Some(&pc), Some(&stack_pointer)) if pc != 0 => (pc, stack_pointer),
+            _ => break,
+        };
+
+        frames.push(UnwoundFrame {
+            instruction_addr: pc,
+            trust,
+        });
+
+        // Return addresses point after the call instruction, which may already belong to the
+        // next function or CFI range.
+        let lookup_addr = if frames.len() == 1 { pc } else { pc - 1 };
+        let rules = find_module(lookup_addr).and_then(|module| {
+            let rules = module.rules.as_ref()?;
+            rules.find(lookup_addr - module.start)
+        });
+
+        let cfi_caller = rules.and_then(|rules| unwind_cfi(&rules, &registers, memory, ip, sp));
+        let (caller, caller_trust) = match cfi_caller {
+            Some(caller) => (caller, FrameTrust::Cfi),
+            None => match unwind_frame_pointer(&registers, memory, ip, sp, fp) {
+                // Without CFI, only trust return addresses that point into a known module.
+                Some(caller) if caller.get(ip).and_then(|&pc| find_module(pc)).is_some() => {
+                    (caller, FrameTrust::FramePointer)
+                }
+                _ => break,
+            },
+        };
+
+        // The stack grows downwards, so the caller's frame must be above the callee's.
+        match caller.get(sp) {
+            Some(&caller_sp) if caller_sp > stack_pointer => (),
+            _ => break,
+        }
+
+        registers = caller;
+        trust = caller_trust;
+    }
+
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    struct TestMemory(HashMap<u64, u64>);
+
+    impl Memory for TestMemory {
+        fn read_u64(&self, addr: u64) -> Option<u64> {
+            self.0.get(&addr).cloned()
+        }
+    }
+
+    fn registers(values: &[(&str, u64)]) -> RegisterValues {
+        values
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value))
+            .collect()
+    }
+
+    const CFI: &[u8] = b"MODULE Linux x86_64 0102030405060708090A0B0C0D0E0F100 libfoo.so
+STACK CFI INIT 10 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+STACK CFI 11 .cfa: $rsp 16 + $rbp: .cfa -16 + ^
+STACK CFI INIT 100 10 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+";
+
+    #[test]
+    fn test_find_rules() {
+        let rules = CfiRules::parse(CFI);
+
+        let init = rules.find(0x10).unwrap();
+        assert_eq!(init.get(".cfa").map(String::as_str), Some("$rsp 8 +"));
+        assert_eq!(init.get(".ra").map(String::as_str), Some(".cfa -8 + ^"));
+        assert_eq!(init.get("$rbp"), None);
+
+        let delta = rules.find(0x20).unwrap();
+        assert_eq!(delta.get(".cfa").map(String::as_str), Some("$rsp 16 +"));
+        assert_eq!(delta.get("$rbp").map(String::as_str), Some(".cfa -16 + ^"));
+
+        assert!(rules.find(0x5).is_none());
+        assert!(rules.find(0x30).is_none());
+        assert!(rules.find(0x105).is_some());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let memory = TestMemory(vec![(0x1000, 0x42)].into_iter().collect());
+        let regs = registers(&[("rsp", 0x1008)]);
+
+        assert_eq!(evaluate("$rsp 8 +", &regs, None, &memory), Some(0x1010));
+        assert_eq!(
+            evaluate(".cfa -16 + ^", &regs, Some(0x1010), &memory),
+            Some(0x42)
+        );
+        assert_eq!(evaluate("$rsp 16 @", &regs, None, &memory), Some(0x1000));
+        assert_eq!(evaluate(".cfa", &regs, None, &memory), None);
+        assert_eq!(evaluate("$rax", &regs, None, &memory), None);
+        assert_eq!(evaluate("1 2", &regs, None, &memory), None);
+    }
+
+    #[test]
+    fn test_walk_cfi() {
+        let modules = [UnwindModule {
+            start: 0x4000,
+            end: 0x5000,
+            rules: Some(CfiRules::parse(CFI)),
+        }];
+
+        // The crashing function at 0x4020 has pushed rbp; its caller at 0x4105 is a leaf.
+        let memory = TestMemory(
+            vec![(0x1000, 0x2000), (0x1008, 0x4106), (0x1010, 0)]
+                .into_iter()
+                .collect(),
+        );
+        let regs = registers(&[("rip", 0x4020), ("rsp", 0x1000), ("rbp", 0)]);
+
+        let frames = walk_stack(Arch::Amd64, regs, &modules, &memory);
+        assert_eq!(
+            frames,
+            vec![
+                UnwoundFrame {
+                    instruction_addr: 0x4020,
+                    trust: FrameTrust::Context,
+                },
+                UnwoundFrame {
+                    instruction_addr: 0x4106,
+                    trust: FrameTrust::Cfi,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_frame_pointer() {
+        let modules = [UnwindModule {
+            start: 0x4000,
+            end: 0x5000,
+            rules: None,
+        }];
+
+        let memory = TestMemory(
+            vec![
+                (0x1100, 0x1200),
+                (0x1108, 0x4800),
+                (0x1200, 0),
+                (0x1208, 0x9000),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let regs = registers(&[("pc", 0x4010), ("sp", 0x1000), ("x29", 0x1100)]);
+
+        let frames = walk_stack(Arch::Arm64, regs, &modules, &memory);
+        assert_eq!(
+            frames,
+            vec![
+                UnwoundFrame {
+                    instruction_addr: 0x4010,
+                    trust: FrameTrust::Context,
+                },
+                UnwoundFrame {
+                    instruction_addr: 0x4800,
+                    trust: FrameTrust::FramePointer,
+                },
+            ]
+        );
+    }
+}
@@ -7,7 +7,7 @@ use console::style;
 use failure::Error;
 use structopt::StructOpt;
 use symbolic::common::{ByteView, DebugId};
-use symbolic::debuginfo::{Archive, FileFormat};
+use symbolic::debuginfo::{Archive, FileFormat, Object, ObjectKind};
 use walkdir::WalkDir;
 use zip::ZipArchive;
 use zstd::stream::copy_encode;
@@ -42,20 +42,46 @@ struct Cli {
     pub input: Vec<PathBuf>,
 }
 
-fn get_target_filename(debug_id: &DebugId) -> PathBuf {
-    // Format the UUID as "xxxx/xxxx/xxxx/xxxx/xxxx/xxxxxxxxxxxx"
+fn get_target_filename(debug_id: &DebugId, with_age: bool, suffix: &str) -> PathBuf {
+    // Format the UUID as "xxxx/xxxx/xxxx/xxxx/xxxx/xxxxxxxxxxxx.suffix"
     let uuid = debug_id.uuid();
     let slice = uuid.as_bytes();
-    let mut path = String::with_capacity(37);
+    let mut path = String::with_capacity(46 + suffix.len());
     for (i, byte) in slice.iter().enumerate() {
         write!(path, "{:02X}", byte).ok();
         if i % 2 == 1 && i <= 9 {
             path.push('/');
         }
     }
+    // PDBs with the same signature are told apart by their age.
+    if with_age {
+        write!(path, "{:X}", debug_id.appendix()).ok();
+    }
+    path.push('.');
+    path.push_str(suffix);
     path.into()
 }
 
+/// Returns whether the age of the debug identifier is part of the file name.
+fn has_age(obj: &Object<'_>) -> bool {
+    match obj.file_format() {
+        FileFormat::Pdb | FileFormat::Pe => true,
+        _ => false,
+    }
+}
+
+/// Returns the file suffix of the unified layout for the given object.
+fn get_file_suffix(obj: &Object<'_>) -> &'static str {
+    match obj.file_format() {
+        FileFormat::Breakpad => "breakpad",
+        FileFormat::SourceBundle => "sourcebundle",
+        _ => match obj.kind() {
+            ObjectKind::Debug => "debuginfo",
+            _ => "executable",
+        },
+    }
+}
+
 fn process_file(cli: &Cli, bv: ByteView<'static>, filename: String) -> Result<usize, Error> {
     let mut rv = 0;
 
@@ -90,7 +116,11 @@ fn process_file(cli: &Cli, bv: ByteView<'static>, filename: String) -> Result<us
     let archive = maybe_ignore_error!(Archive::parse(&bv));
     for obj in archive.objects() {
         let obj = maybe_ignore_error!(obj);
-        let new_filename = cli.output.join(get_target_filename(&obj.debug_id()));
+        let new_filename = cli.output.join(get_target_filename(
+            &obj.debug_id(),
+            has_age(&obj),
+            get_file_suffix(&obj),
+        ));
         fs::create_dir_all(new_filename.parent().unwrap())?;
         if !cli.quiet {
             println!(